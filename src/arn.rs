@@ -0,0 +1,198 @@
+// Clusters are referenced either by full ARN
+// (arn:aws:ecs:eu-west-1:123456789012:cluster/prod) or by bare name (prod).
+
+pub fn cluster_short_name(arn: &str) -> &str {
+    match arn.find("cluster/") {
+        Some(idx) => &arn[idx + "cluster/".len()..],
+        None => arn.rsplit(':').next().unwrap(),
+    }
+}
+
+// Task definitions are referenced either by full ARN
+// (arn:aws:ecs:eu-west-1:123456789012:task-definition/my-task:42),
+// by family:revision (my-task:42) or by bare family (my-task).
+
+pub fn task_definition_short_name(arn: &str) -> &str {
+    arn.rsplit('/').next().unwrap()
+}
+
+pub fn task_definition_family(arn: &str) -> &str {
+    task_definition_short_name(arn).split(':').next().unwrap()
+}
+
+pub fn task_definition_revision(arn: &str) -> Option<u32> {
+    task_definition_short_name(arn)
+        .split(':')
+        .nth(1)
+        .and_then(|revision| revision.parse().ok())
+}
+
+// arn:partition:service:region:account-id:resource, None for bare names
+pub fn account_id(arn: &str) -> Option<&str> {
+    let mut parts = arn.splitn(6, ':');
+    if parts.next() != Some("arn") {
+        return None;
+    }
+    parts.nth(3).filter(|account_id| !account_id.is_empty())
+}
+
+// arn:aws:..., arn:aws-us-gov:... or arn:aws-cn:...
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partition {
+    Standard,
+    GovCloud,
+    China,
+    Custom(String),
+}
+
+impl Partition {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "aws" => Partition::Standard,
+            "aws-us-gov" => Partition::GovCloud,
+            "aws-cn" => Partition::China,
+            other => Partition::Custom(other.to_owned()),
+        }
+    }
+
+    // regions are named after their partition, e.g. us-gov-west-1 or cn-north-1
+    pub fn from_region(region: &str) -> Self {
+        if region.starts_with("us-gov-") {
+            Partition::GovCloud
+        } else if region.starts_with("cn-") {
+            Partition::China
+        } else {
+            Partition::Standard
+        }
+    }
+
+    pub fn console_host(&self) -> &str {
+        match self {
+            Partition::GovCloud => "console.amazonaws-us-gov.com",
+            Partition::China => "console.amazonaws.cn",
+            _ => "console.aws.amazon.com",
+        }
+    }
+
+    // dual-stack (IPv4 and IPv6) endpoints are {service}.{region}.{suffix}
+    pub fn dual_stack_dns_suffix(&self) -> &str {
+        match self {
+            Partition::China => "api.amazonwebservices.com.cn",
+            _ => "api.aws",
+        }
+    }
+}
+
+// bare names carry no partition and are assumed to be in the standard one
+pub fn parse_arn_partition(arn: &str) -> Partition {
+    let mut parts = arn.splitn(3, ':');
+    match (parts.next(), parts.next()) {
+        (Some("arn"), Some(partition)) => Partition::from_name(partition),
+        _ => Partition::Standard,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK_DEFINITION_ARN: &str =
+        "arn:aws:ecs:eu-west-1:123456789012:task-definition/my-task:42";
+
+    #[test]
+    fn task_definition_parts_of_full_arn() {
+        assert_eq!(
+            task_definition_short_name(TASK_DEFINITION_ARN),
+            "my-task:42"
+        );
+        assert_eq!(task_definition_family(TASK_DEFINITION_ARN), "my-task");
+        assert_eq!(task_definition_revision(TASK_DEFINITION_ARN), Some(42));
+    }
+
+    #[test]
+    fn task_definition_parts_of_family_and_revision() {
+        assert_eq!(task_definition_short_name("my-task:42"), "my-task:42");
+        assert_eq!(task_definition_family("my-task:42"), "my-task");
+        assert_eq!(task_definition_revision("my-task:42"), Some(42));
+    }
+
+    #[test]
+    fn task_definition_parts_of_bare_family() {
+        assert_eq!(task_definition_short_name("my-task"), "my-task");
+        assert_eq!(task_definition_family("my-task"), "my-task");
+        assert_eq!(task_definition_revision("my-task"), None);
+    }
+
+    #[test]
+    fn cluster_short_name_of_path_arn() {
+        assert_eq!(
+            cluster_short_name("arn:aws:ecs:eu-west-1:123456789012:cluster/prod"),
+            "prod"
+        );
+        assert_eq!(
+            cluster_short_name("arn:aws-us-gov:ecs:us-gov-west-1:123456789012:cluster/prod"),
+            "prod"
+        );
+    }
+
+    #[test]
+    fn cluster_short_name_of_non_path_arn_or_name() {
+        assert_eq!(
+            cluster_short_name("arn:aws:ecs:eu-west-1:123456789012:prod"),
+            "prod"
+        );
+        assert_eq!(cluster_short_name("prod"), "prod");
+    }
+
+    #[test]
+    fn account_id_of_arn() {
+        assert_eq!(account_id(TASK_DEFINITION_ARN), Some("123456789012"));
+        assert_eq!(account_id("arn:aws:s3:::bucket"), None);
+        assert_eq!(account_id("my-task:42"), None);
+    }
+
+    #[test]
+    fn partition_of_arn_and_region() {
+        assert_eq!(
+            parse_arn_partition(TASK_DEFINITION_ARN),
+            Partition::Standard
+        );
+        assert_eq!(
+            parse_arn_partition("arn:aws-us-gov:ecs:us-gov-west-1:123456789012:cluster/prod"),
+            Partition::GovCloud
+        );
+        assert_eq!(
+            parse_arn_partition("arn:aws-cn:ecs:cn-north-1:123456789012:cluster/prod"),
+            Partition::China
+        );
+        assert_eq!(
+            parse_arn_partition("arn:aws-iso:ecs:us-iso-east-1:123456789012:cluster/prod"),
+            Partition::Custom("aws-iso".to_owned())
+        );
+        assert_eq!(parse_arn_partition("prod"), Partition::Standard);
+        assert_eq!(Partition::from_region("cn-north-1"), Partition::China);
+        assert_eq!(Partition::from_region("us-gov-east-1"), Partition::GovCloud);
+        assert_eq!(Partition::from_region("eu-west-1"), Partition::Standard);
+    }
+
+    #[test]
+    fn arn_helpers_in_every_partition() {
+        let gov_cloud = "arn:aws-us-gov:ecs:us-gov-west-1:123456789012:task-definition/web:3";
+        assert_eq!(account_id(gov_cloud), Some("123456789012"));
+        assert_eq!(task_definition_short_name(gov_cloud), "web:3");
+        assert_eq!(
+            cluster_short_name("arn:aws-cn:ecs:cn-north-1:123456789012:cluster/prod"),
+            "prod"
+        );
+    }
+
+    #[test]
+    fn dual_stack_dns_suffix_per_partition() {
+        assert_eq!(Partition::Standard.dual_stack_dns_suffix(), "api.aws");
+        assert_eq!(Partition::GovCloud.dual_stack_dns_suffix(), "api.aws");
+        assert_eq!(
+            Partition::China.dual_stack_dns_suffix(),
+            "api.amazonwebservices.com.cn"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::ecs::{self, ClusterScanResult, ClusterScanStatus, ScanOptions, ScanOutcome};
+
+use anyhow::Context;
+use anyhow::Result;
+use rusoto_ecs::EcsClient;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+// One line of the newline-delimited JSON checkpoint file
+#[derive(Serialize, Deserialize)]
+struct CheckpointEntry {
+    cluster: String,
+    result: ClusterScanResult,
+}
+
+// A crash while appending leaves a last line without its newline. It is cut off the
+// file, so that its cluster is scanned again and new entries start on a fresh line.
+fn load_checkpoint(path: &Path) -> Result<BTreeMap<String, ClusterScanResult>> {
+    let mut completed = BTreeMap::new();
+    if !path.exists() {
+        return Ok(completed);
+    }
+    let mut data = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut data))
+        .with_context(|| format!("could not read {:?}", path))?;
+    let complete_len = data.rfind('\n').map_or(0, |idx| idx + 1);
+    if complete_len < data.len() {
+        warn!("Ignoring the truncated last entry of checkpoint {:?}", path);
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_len(complete_len as u64))
+            .with_context(|| format!("could not truncate {:?}", path))?;
+    }
+    for line in data[..complete_len].lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: CheckpointEntry = serde_json::from_str(line)
+            .with_context(|| format!("Invalid checkpoint entry in {:?}", path))?;
+        completed.insert(entry.cluster, entry.result);
+    }
+    debug!(
+        "Resuming scan, {} clusters already completed",
+        completed.len()
+    );
+    Ok(completed)
+}
+
+pub async fn get_images_of_clusters_with_checkpoint(
+    ecs_client: &EcsClient,
+    options: &ScanOptions,
+    checkpoint_path: Option<&Path>,
+) -> Result<ScanOutcome> {
+    let path = match checkpoint_path {
+        Some(path) => path,
+        None => return ecs::get_images_of_clusters(ecs_client, options).await,
+    };
+
+    let completed = load_checkpoint(path)?;
+    let checkpoint_file = Mutex::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("could not open {:?}", path))?,
+    );
+    let append_to_checkpoint = |cluster: &str, result: &ClusterScanResult| -> Result<()> {
+        // failed clusters are not checkpointed so that they are retried on resume
+        if result.status != ClusterScanStatus::Scanned {
+            return Ok(());
+        }
+        let mut line = serde_json::to_string(&CheckpointEntry {
+            cluster: cluster.to_owned(),
+            result: result.clone(),
+        })?;
+        // a single write, so that the entry and its newline cannot be separated
+        line.push('\n');
+        checkpoint_file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    };
+
+    let outcome = ecs::scan_clusters(ecs_client, options, completed, &append_to_checkpoint).await?;
+    std::fs::remove_file(path)?;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, container, service, task_definition, MockEcsClient};
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    const CLUSTERS: [&str; 3] = [
+        "arn:aws:ecs:eu-west-1:123456789012:cluster/a",
+        "arn:aws:ecs:eu-west-1:123456789012:cluster/b",
+        "arn:aws:ecs:eu-west-1:123456789012:cluster/c",
+    ];
+    const TD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3";
+    const WORKER_TD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/worker:1";
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.ndjson", name, std::process::id()))
+    }
+
+    // every request about cluster c fails until it is fixed
+    fn three_cluster_mock(fixed: bool) -> MockEcsClient {
+        let mock = MockEcsClient::with_clusters(CLUSTERS.to_vec())
+            .with_services(CLUSTERS[0], vec![service("web", TD_ARN)])
+            .with_services(CLUSTERS[1], vec![service("web", TD_ARN)])
+            .with_services(CLUSTERS[2], vec![service("worker", WORKER_TD_ARN)])
+            .with_task_definition(
+                TD_ARN,
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                WORKER_TD_ARN,
+                task_definition(vec![container("worker", "worker:2")]),
+            );
+        if fixed {
+            mock
+        } else {
+            mock.fail_cluster(CLUSTERS[2], "ServerException")
+        }
+    }
+
+    fn scan(mock: &MockEcsClient, path: &Path) -> Result<ScanOutcome> {
+        block_on(get_images_of_clusters_with_checkpoint(
+            &mock.client(),
+            &ScanOptions::default(),
+            Some(path),
+        ))
+    }
+
+    #[test]
+    fn resumed_scan_skips_checkpointed_clusters() {
+        let path = checkpoint_path("resumed-scan");
+        assert!(scan(&three_cluster_mock(false), &path).is_err());
+        assert_eq!(
+            load_checkpoint(&path).unwrap().keys().collect::<Vec<_>>(),
+            vec![CLUSTERS[0], CLUSTERS[1]]
+        );
+
+        let mock = three_cluster_mock(true);
+        let outcome = scan(&mock, &path).unwrap();
+
+        let scanned: Vec<Value> = mock
+            .requests("ListServices")
+            .iter()
+            .map(|req| req["cluster"].clone())
+            .collect();
+        assert_eq!(scanned, vec![CLUSTERS[2]]);
+        assert_eq!(
+            outcome.clusters.keys().collect::<Vec<_>>(),
+            CLUSTERS.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(outcome.clusters[CLUSTERS[0]].images.len(), 1);
+        assert_eq!(
+            outcome.clusters[CLUSTERS[2]].images[0].image_name,
+            "worker:2"
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn torn_last_entry_is_scanned_again() {
+        let path = checkpoint_path("torn-entry");
+        assert!(scan(&three_cluster_mock(false), &path).is_err());
+        // the scan of cluster c was interrupted while its entry was written
+        let mut checkpoint = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(
+            checkpoint,
+            r#"{{"cluster":"{}","result":{{"sta"#,
+            CLUSTERS[2]
+        )
+        .unwrap();
+        drop(checkpoint);
+
+        assert_eq!(load_checkpoint(&path).unwrap().len(), 2);
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with('\n'));
+
+        let mock = three_cluster_mock(true);
+        let outcome = scan(&mock, &path).unwrap();
+        assert_eq!(mock.requests("ListServices").len(), 1);
+        assert_eq!(outcome.clusters.len(), 3);
+        assert!(!path.exists());
+    }
+}
@@ -8,7 +8,7 @@ type HttpConnector =
 
 pub type HttpClient = rusoto_core::HttpClient<HttpConnector>;
 
-pub fn new_client() -> Result<HttpClient> {
+fn new_connector() -> Result<HttpConnector> {
     let connector = HttpsConnector::new();
 
     let http_connector: HttpConnector;
@@ -18,6 +18,16 @@ pub fn new_client() -> Result<HttpClient> {
     } else {
         http_connector = ProxyConnector::new(connector)?;
     }
+    Ok(http_connector)
+}
+
+// plain hyper client going through the same proxy, for non AWS endpoints such as webhooks
+pub fn new_hyper_client() -> Result<Client<HttpConnector>> {
+    Ok(Client::builder().build(new_connector()?))
+}
+
+pub fn new_client() -> Result<HttpClient> {
+    let http_connector = new_connector()?;
     let mut hyper_builder = Client::builder();
 
     // disabling due to connection closed issue
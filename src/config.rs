@@ -1,3 +1,4 @@
+use crate::policy::SecurityPolicy;
 use anyhow::Context;
 use anyhow::Result;
 use chrono::prelude::*;
@@ -24,6 +25,9 @@ pub struct Config {
     pub aws_session_token: Option<String>,
     pub aws_session_expiration: Option<DateTime<FixedOffset>>,
     pub roles: HashMap<String, String>,
+    // rules applied by --exit_code
+    #[serde(default)]
+    pub security_policy: SecurityPolicy,
 }
 
 impl Config {
@@ -112,10 +116,10 @@ impl Config {
             return true;
         }
 
-        return match self.aws_session_expiration {
+        match self.aws_session_expiration {
             Some(x) => Utc::now().timestamp_millis() < x.timestamp_millis(),
             None => false,
-        };
+        }
     }
 }
 
@@ -168,7 +172,7 @@ fn set_default_aws_credentials(cfg: &mut Config) -> Result<()> {
             let raw_line = line_res?;
             let line = raw_line.trim();
             debug!("Parsing line {}", &line);
-            let mut caps = profile_regex.captures(&line);
+            let mut caps = profile_regex.captures(line);
             if let Some(profile_cap) = caps {
                 if let Some(profile) = current_profile {
                     debug!("Added profile {} cfg {:?}", &profile, &profile_cfg);
@@ -178,13 +182,10 @@ fn set_default_aws_credentials(cfg: &mut Config) -> Result<()> {
                 current_profile = Some(profile_cap.get(1).unwrap().as_str().to_owned());
                 continue;
             }
-            caps = kv_regex.captures(&line);
+            caps = kv_regex.captures(line);
             if let Some(kv_cap) = caps {
                 let k = kv_cap.get(1).unwrap().as_str().to_owned();
-                let v = match kv_cap.get(2) {
-                    Some(v) => Some(v.as_str().to_owned()),
-                    None => None,
-                };
+                let v = kv_cap.get(2).map(|v| v.as_str().to_owned());
                 profile_cfg.insert(k, v);
             }
         } else {
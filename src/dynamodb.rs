@@ -0,0 +1,364 @@
+use crate::ecs::ScanOutcome;
+use crate::error;
+use crate::image::Image;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusoto_dynamodb::{
+    AttributeValue, BatchWriteItemInput, DynamoDb, DynamoDbClient, PutRequest, QueryInput,
+    WriteRequest,
+};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::delay_for;
+
+// BatchWriteItem accepts at most 25 put requests
+const BATCH_WRITE_MAX_ITEMS: usize = 25;
+// unprocessed items are resent with exponential backoff, 50ms doubling up to 8 attempts
+const BATCH_WRITE_MAX_ATTEMPTS: u32 = 8;
+const BATCH_WRITE_BASE_DELAY: Duration = Duration::from_millis(50);
+
+type Item = HashMap<String, AttributeValue>;
+
+fn to_attribute_value(value: Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue {
+            null: Some(true),
+            ..Default::default()
+        },
+        Value::Bool(b) => AttributeValue {
+            bool: Some(b),
+            ..Default::default()
+        },
+        Value::Number(n) => AttributeValue {
+            n: Some(n.to_string()),
+            ..Default::default()
+        },
+        Value::String(s) => AttributeValue {
+            s: Some(s),
+            ..Default::default()
+        },
+        Value::Array(values) => AttributeValue {
+            l: Some(values.into_iter().map(to_attribute_value).collect()),
+            ..Default::default()
+        },
+        Value::Object(fields) => AttributeValue {
+            m: Some(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (k, to_attribute_value(v)))
+                    .collect(),
+            ),
+            ..Default::default()
+        },
+    }
+}
+
+fn from_attribute_value(value: AttributeValue) -> Value {
+    if let Some(s) = value.s {
+        Value::String(s)
+    } else if let Some(n) = value.n {
+        serde_json::from_str::<Number>(&n)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    } else if let Some(b) = value.bool {
+        Value::Bool(b)
+    } else if let Some(l) = value.l {
+        Value::Array(l.into_iter().map(from_attribute_value).collect())
+    } else if let Some(m) = value.m {
+        Value::Object(
+            m.into_iter()
+                .map(|(k, v)| (k, from_attribute_value(v)))
+                .collect::<Map<_, _>>(),
+        )
+    } else {
+        Value::Null
+    }
+}
+
+fn string_value(s: String) -> AttributeValue {
+    AttributeValue {
+        s: Some(s),
+        ..Default::default()
+    }
+}
+
+// partition key cluster_arn, sort key sk = scanned_at#service_name#container_name so
+// each export adds a new snapshot, every other Image field is stored under its own name
+fn image_to_item(image: &Image, scanned_at: &str) -> Result<Item> {
+    let mut item: Item = to_attribute_value(serde_json::to_value(image)?)
+        .m
+        .unwrap_or_default();
+    item.insert(
+        "sk".to_owned(),
+        string_value(format!(
+            "{}#{}#{}",
+            scanned_at, image.service_name, image.container_name
+        )),
+    );
+    item.insert("scanned_at".to_owned(), string_value(scanned_at.to_owned()));
+    Ok(item)
+}
+
+fn item_to_image(mut item: Item) -> Result<Image> {
+    item.remove("sk");
+    item.remove("scanned_at");
+    let fields = item
+        .into_iter()
+        .map(|(k, v)| (k, from_attribute_value(v)))
+        .collect::<Map<_, _>>();
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+fn timestamp(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// returns the number of images written
+pub async fn export_to_dynamodb(
+    dynamo_client: &DynamoDbClient,
+    table: &str,
+    scan: &ScanOutcome,
+) -> Result<usize> {
+    let scanned_at = timestamp(Utc::now());
+    let write_requests = scan
+        .clusters
+        .values()
+        .flat_map(|scan_result| scan_result.images.iter())
+        .map(|image| {
+            Ok(WriteRequest {
+                put_request: Some(PutRequest {
+                    item: image_to_item(image, &scanned_at)?,
+                }),
+                delete_request: None,
+            })
+        })
+        .collect::<Result<Vec<WriteRequest>>>()?;
+
+    for batch in write_requests.chunks(BATCH_WRITE_MAX_ITEMS) {
+        let mut request_items: HashMap<String, Vec<WriteRequest>> = HashMap::new();
+        request_items.insert(table.to_owned(), batch.to_vec());
+        // throttled writes come back as unprocessed items, send them again
+        let mut attempt = 0;
+        while !request_items.is_empty() {
+            if attempt == BATCH_WRITE_MAX_ATTEMPTS {
+                let unprocessed: usize = request_items.values().map(Vec::len).sum();
+                return Err(anyhow!(
+                    "{} items still unprocessed after {} attempts to write to {}",
+                    unprocessed,
+                    BATCH_WRITE_MAX_ATTEMPTS,
+                    table
+                ));
+            }
+            if attempt > 0 {
+                delay_for(BATCH_WRITE_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+            }
+            attempt += 1;
+            let batch_write_req = BatchWriteItemInput {
+                request_items,
+                ..Default::default()
+            };
+            let batch_write_res = dynamo_client
+                .batch_write_item(batch_write_req)
+                .await
+                .map_err(error::from_rusoto)?;
+            request_items = batch_write_res.unprocessed_items.unwrap_or_default();
+            request_items.retain(|_, requests| !requests.is_empty());
+        }
+    }
+    Ok(write_requests.len())
+}
+
+// images of every export since the given time, oldest scan first
+pub async fn query_images_for_cluster(
+    dynamo_client: &DynamoDbClient,
+    table: &str,
+    cluster_arn: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<Image>> {
+    let mut values: Item = HashMap::new();
+    values.insert(
+        ":cluster_arn".to_owned(),
+        string_value(cluster_arn.to_owned()),
+    );
+    values.insert(":since".to_owned(), string_value(timestamp(since)));
+
+    let mut images: Vec<Image> = Vec::new();
+    let mut exclusive_start_key: Option<Item> = None;
+    loop {
+        let query_req = QueryInput {
+            table_name: table.to_owned(),
+            // RFC 3339 timestamps in UTC sort lexicographically
+            key_condition_expression: Some(
+                "cluster_arn = :cluster_arn AND sk >= :since".to_owned(),
+            ),
+            expression_attribute_values: Some(values.clone()),
+            exclusive_start_key,
+            ..Default::default()
+        };
+        let query_res = dynamo_client
+            .query(query_req)
+            .await
+            .map_err(error::from_rusoto)?;
+        for item in query_res.items.unwrap_or_default() {
+            images.push(item_to_image(item)?);
+        }
+        if query_res.last_evaluated_key.is_none() {
+            break;
+        }
+        exclusive_start_key = query_res.last_evaluated_key;
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::{get_images_of_clusters, ScanOptions};
+    use crate::test_helpers::{
+        block_on, container, service, task_definition, MockDynamoDbClient, MockEcsClient,
+    };
+    use serde_json::json;
+
+    const PROD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+    const WEB_TASK_DEFINITION: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3";
+
+    fn scan_of(service_count: usize) -> ScanOutcome {
+        let services = (0..service_count)
+            .map(|i| service(&format!("web-{:02}", i), WEB_TASK_DEFINITION))
+            .collect();
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, services)
+            .with_task_definition(
+                WEB_TASK_DEFINITION,
+                task_definition(vec![container("nginx", "nginx:1.19")]),
+            );
+        block_on(get_images_of_clusters(
+            &mock.client(),
+            &ScanOptions::default(),
+        ))
+        .unwrap()
+    }
+
+    fn query_since(dynamo: &MockDynamoDbClient, since: DateTime<Utc>) -> Vec<Image> {
+        block_on(query_images_for_cluster(
+            &dynamo.client(),
+            "images",
+            PROD_ARN,
+            since,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn exported_images_are_read_back() {
+        let scan = scan_of(2);
+        let dynamo = MockDynamoDbClient::default();
+
+        let written = block_on(export_to_dynamodb(&dynamo.client(), "images", &scan)).unwrap();
+
+        assert_eq!(written, 2);
+        let item = &dynamo.items()[0];
+        assert_eq!(item["cluster_arn"], json!({ "S": PROD_ARN }));
+        let scanned_at = item["scanned_at"]["S"].as_str().unwrap();
+        assert_eq!(
+            item["sk"],
+            json!({ "S": format!("{}#web-00#nginx", scanned_at) })
+        );
+        assert_eq!(item["image_name"], json!({ "S": "nginx:1.19" }));
+        assert_eq!(item["enriched_image_name"], json!({ "NULL": true }));
+        let read_back = query_since(&dynamo, Utc::now() - chrono::Duration::hours(1));
+        assert_eq!(
+            serde_json::to_value(&read_back).unwrap(),
+            serde_json::to_value(&scan.clusters[PROD_ARN].images).unwrap()
+        );
+    }
+
+    #[test]
+    fn query_skips_images_scanned_before_since() {
+        let dynamo = MockDynamoDbClient::default();
+        block_on(export_to_dynamodb(&dynamo.client(), "images", &scan_of(2))).unwrap();
+
+        assert!(query_since(&dynamo, Utc::now() + chrono::Duration::hours(1)).is_empty());
+    }
+
+    #[test]
+    fn writes_are_batched_by_25() {
+        let dynamo = MockDynamoDbClient::default();
+
+        let written =
+            block_on(export_to_dynamodb(&dynamo.client(), "images", &scan_of(30))).unwrap();
+
+        assert_eq!(written, 30);
+        let batch_sizes: Vec<usize> = dynamo
+            .requests("BatchWriteItem")
+            .iter()
+            .map(|request| request["RequestItems"]["images"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(batch_sizes, vec![25, 5]);
+        assert_eq!(dynamo.items().len(), 30);
+    }
+
+    #[test]
+    fn unprocessed_items_are_sent_again() {
+        let dynamo = MockDynamoDbClient::default().throttle_writes(1);
+
+        block_on(export_to_dynamodb(&dynamo.client(), "images", &scan_of(2))).unwrap();
+
+        assert_eq!(dynamo.call_count("BatchWriteItem"), 2);
+        assert_eq!(dynamo.items().len(), 2);
+    }
+
+    #[test]
+    fn every_export_keeps_its_own_snapshot() {
+        let dynamo = MockDynamoDbClient::default();
+        let before = Utc::now() - chrono::Duration::hours(1);
+        block_on(export_to_dynamodb(&dynamo.client(), "images", &scan_of(2))).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        block_on(export_to_dynamodb(&dynamo.client(), "images", &scan_of(2))).unwrap();
+
+        assert_eq!(dynamo.items().len(), 4);
+        let read_back = query_since(&dynamo, before);
+        let services: Vec<&str> = read_back
+            .iter()
+            .map(|image| image.service_name.as_str())
+            .collect();
+        assert_eq!(services, vec!["web-00", "web-01", "web-00", "web-01"]);
+    }
+
+    #[test]
+    fn still_throttled_writes_fail_after_the_last_attempt() {
+        let dynamo = MockDynamoDbClient::default().throttle_writes(8);
+        let scan = scan_of(2);
+
+        let error = block_on(async {
+            // the backoff waits 6.35s in total, let the paused clock skip it
+            tokio::time::pause();
+            export_to_dynamodb(&dynamo.client(), "images", &scan).await
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "2 items still unprocessed after 8 attempts to write to images"
+        );
+        assert_eq!(dynamo.call_count("BatchWriteItem"), 8);
+        assert!(dynamo.items().is_empty());
+    }
+
+    #[test]
+    fn writes_throttled_once_less_than_the_cap_succeed() {
+        let dynamo = MockDynamoDbClient::default().throttle_writes(7);
+        let scan = scan_of(2);
+
+        let written = block_on(async {
+            tokio::time::pause();
+            export_to_dynamodb(&dynamo.client(), "images", &scan).await
+        })
+        .unwrap();
+
+        assert_eq!(written, 2);
+        assert_eq!(dynamo.call_count("BatchWriteItem"), 8);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::arn::Partition;
+use crate::error;
+use crate::image::{parse_image_reference, Image};
+
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use rusoto_core::{Region, RusotoError};
+use rusoto_ecr::{DescribeImagesError, DescribeImagesRequest, Ecr, EcrClient, ImageIdentifier};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// distinct images described at the same time by enrich_images
+const ENRICH_IMAGES_CONCURRENCY: usize = 10;
+
+// ECR clients by region, an image is described in the region of its registry
+#[derive(Clone)]
+pub struct EcrClients {
+    new_client: Arc<dyn Fn(Region) -> EcrClient + Send + Sync>,
+}
+
+impl EcrClients {
+    pub fn new(new_client: impl Fn(Region) -> EcrClient + Send + Sync + 'static) -> Self {
+        EcrClients {
+            new_client: Arc::new(new_client),
+        }
+    }
+
+    pub fn client_in(&self, region: &str) -> Result<EcrClient> {
+        let region =
+            Region::from_str(region).with_context(|| format!("Unknown ECR region {}", region))?;
+        Ok((self.new_client)(region))
+    }
+}
+
+// <account>.dkr.ecr.<region>.amazonaws.com[.cn]/<repository>
+#[derive(Debug, PartialEq)]
+pub struct EcrRepository<'a> {
+    pub registry: &'a str,
+    pub registry_id: &'a str,
+    pub region: &'a str,
+    pub repository_name: &'a str,
+}
+
+pub fn parse_ecr_repository(repository: &str) -> Option<EcrRepository<'_>> {
+    let idx = repository.find('/')?;
+    let (registry, repository_name) = (&repository[..idx], &repository[idx + 1..]);
+    let host_parts: Vec<&str> = registry.split('.').collect();
+    match host_parts.as_slice() {
+        [registry_id, "dkr", "ecr", region, "amazonaws", ..] => Some(EcrRepository {
+            registry,
+            registry_id,
+            region,
+            repository_name,
+        }),
+        _ => None,
+    }
+}
+
+// repository page in the console of the registry partition, None for non ECR images
+pub fn image_to_ecr_console_url(image: &Image) -> Option<String> {
+    let reference = parse_image_reference(&image.image_name);
+    let repository = parse_ecr_repository(reference.repository)?;
+    let partition = Partition::from_region(repository.region);
+    Some(format!(
+        "https://{}.{}/ecr/repositories/private/{}/{}?region={}",
+        repository.region,
+        partition.console_host(),
+        repository.registry_id,
+        repository.repository_name,
+        repository.region
+    ))
+}
+
+// canonical repository@digest form of a tagged ECR image, None for other registries
+pub async fn resolve_digest(ecr_client: &EcrClient, image_name: &str) -> Result<Option<String>> {
+    let reference = parse_image_reference(image_name);
+    let repository = match parse_ecr_repository(reference.repository) {
+        Some(repository) => repository,
+        None => return Ok(None),
+    };
+    if let Some(digest) = reference.digest {
+        return Ok(Some(format!("{}@{}", reference.repository, digest)));
+    }
+
+    let describe_images_req = DescribeImagesRequest {
+        registry_id: Some(repository.registry_id.to_owned()),
+        repository_name: repository.repository_name.to_owned(),
+        image_ids: Some(vec![ImageIdentifier {
+            image_tag: Some(reference.tag.unwrap_or("latest").to_owned()),
+            image_digest: None,
+        }]),
+        ..Default::default()
+    };
+    let describe_images_res = match ecr_client.describe_images(describe_images_req).await {
+        Ok(res) => res,
+        Err(RusotoError::Service(DescribeImagesError::ImageNotFound(_)))
+        | Err(RusotoError::Service(DescribeImagesError::RepositoryNotFound(_))) => {
+            warn!("Image {} not found in ECR", image_name);
+            return Ok(None);
+        }
+        Err(e) => return Err(error::from_rusoto(e)),
+    };
+
+    let digest = describe_images_res
+        .image_details
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|details| details.image_digest);
+    Ok(digest.map(|digest| format!("{}@{}", reference.repository, digest)))
+}
+
+async fn resolve_digest_in_registry_region(
+    ecr_clients: &EcrClients,
+    image_name: &str,
+) -> Result<Option<String>> {
+    let reference = parse_image_reference(image_name);
+    let region = match parse_ecr_repository(reference.repository) {
+        Some(repository) => repository.region,
+        None => return Ok(None),
+    };
+    let ecr_client = ecr_clients.client_in(region)?;
+    resolve_digest(&ecr_client, image_name).await
+}
+
+// fills enriched_image_name on ECR images, each distinct image is looked up once.
+// A failed lookup, e.g. AccessDenied on another account's repository, only leaves
+// that image without a digest.
+pub async fn enrich_images(ecr_clients: &EcrClients, images: &mut [Image]) {
+    let mut image_names: Vec<&str> = images.iter().map(|i| i.image_name.as_str()).collect();
+    image_names.sort_unstable();
+    image_names.dedup();
+
+    let mut resolved: HashMap<String, Option<String>> = HashMap::new();
+    for image_names_chunk in image_names.chunks(ENRICH_IMAGES_CONCURRENCY) {
+        let resolve_futures = image_names_chunk.iter().map(|image_name| async move {
+            let enriched = resolve_digest_in_registry_region(ecr_clients, image_name)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!("Could not resolve the digest of {}: {:#}", image_name, err);
+                    None
+                });
+            ((*image_name).to_owned(), enriched)
+        });
+        resolved.extend(join_all(resolve_futures).await);
+    }
+
+    for image in images.iter_mut() {
+        image.enriched_image_name = resolved.get(&image.image_name).cloned().flatten();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, MockEcrClient};
+
+    const ECR_WEB: &str = "123456789012.dkr.ecr.eu-west-1.amazonaws.com/team/web";
+
+    fn images_named(image_names: &[&str]) -> Vec<Image> {
+        image_names
+            .iter()
+            .map(|image_name| Image {
+                image_name: (*image_name).to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ecr_repository_of_registry_host() {
+        assert_eq!(
+            parse_ecr_repository(ECR_WEB),
+            Some(EcrRepository {
+                registry: "123456789012.dkr.ecr.eu-west-1.amazonaws.com",
+                registry_id: "123456789012",
+                region: "eu-west-1",
+                repository_name: "team/web",
+            })
+        );
+        assert_eq!(parse_ecr_repository("nginx"), None);
+        assert_eq!(parse_ecr_repository("registry.local:5000/team/web"), None);
+    }
+
+    #[test]
+    fn enrich_looks_up_each_distinct_ecr_image_once() {
+        let ecr = MockEcrClient::default().with_image("team/web", "1.19", "sha256:abc");
+        let tagged = format!("{}:1.19", ECR_WEB);
+        let mut images = images_named(&[&tagged, &tagged, "nginx:1.19"]);
+        block_on(enrich_images(&ecr.clients(), &mut images));
+
+        let expected = Some(format!("{}@sha256:abc", ECR_WEB));
+        assert_eq!(images[0].enriched_image_name, expected);
+        assert_eq!(images[1].enriched_image_name, expected);
+        assert_eq!(images[2].enriched_image_name, None);
+        assert_eq!(ecr.call_count("DescribeImages"), 1);
+    }
+
+    #[test]
+    fn enrich_keeps_pinned_digests_and_unknown_tags() {
+        let ecr = MockEcrClient::default();
+        let pinned = format!("{}@sha256:def", ECR_WEB);
+        let unknown = format!("{}:2.0", ECR_WEB);
+        let mut images = images_named(&[&pinned, &unknown]);
+        block_on(enrich_images(&ecr.clients(), &mut images));
+
+        assert_eq!(images[0].enriched_image_name, Some(pinned));
+        assert_eq!(images[1].enriched_image_name, None);
+        assert_eq!(ecr.call_count("DescribeImages"), 1);
+    }
+
+    #[test]
+    fn enrich_describes_images_in_the_region_of_their_registry() {
+        let ecr = MockEcrClient::default()
+            .with_image("team/web", "1.19", "sha256:abc")
+            .with_image("team/api", "2.0", "sha256:def");
+        let web = format!("{}:1.19", ECR_WEB);
+        let api = "123456789012.dkr.ecr.us-east-1.amazonaws.com/team/api:2.0";
+        let mut images = images_named(&[&web, api]);
+        block_on(enrich_images(&ecr.clients(), &mut images));
+
+        let regions: Vec<String> = ecr.calls().into_iter().map(|call| call.region).collect();
+        assert_eq!(regions, vec!["eu-west-1", "us-east-1"]);
+        assert_eq!(
+            images[1].enriched_image_name.as_deref(),
+            Some("123456789012.dkr.ecr.us-east-1.amazonaws.com/team/api@sha256:def")
+        );
+    }
+
+    #[test]
+    fn failed_lookups_leave_only_their_image_without_digest() {
+        let ecr = MockEcrClient::default()
+            .with_image("team/web", "1.19", "sha256:abc")
+            .fail_next("DescribeImages", 1, "AccessDeniedException");
+        let denied = "012345678901.dkr.ecr.eu-west-1.amazonaws.com/team/api:2.0";
+        let web = format!("{}:1.19", ECR_WEB);
+        let unknown_region = "123456789012.dkr.ecr.moon-base-1.amazonaws.com/team/web:1.19";
+        // the images are looked up in name order, the denied account sorts first
+        let mut images = images_named(&[&web, denied, unknown_region]);
+        block_on(enrich_images(&ecr.clients(), &mut images));
+
+        assert_eq!(
+            images[0].enriched_image_name,
+            Some(format!("{}@sha256:abc", ECR_WEB))
+        );
+        assert_eq!(images[1].enriched_image_name, None);
+        assert_eq!(images[2].enriched_image_name, None);
+        assert_eq!(ecr.call_count("DescribeImages"), 2);
+    }
+
+    #[test]
+    fn enrich_describes_images_in_bounded_batches() {
+        let ecr = (0..25).fold(MockEcrClient::default(), |ecr, i| {
+            ecr.with_image("team/web", &format!("1.{}", i), &format!("sha256:{:02}", i))
+        });
+        let names: Vec<String> = (0..25).map(|i| format!("{}:1.{}", ECR_WEB, i)).collect();
+        let mut images = images_named(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        block_on(enrich_images(&ecr.clients(), &mut images));
+
+        assert_eq!(ecr.call_count("DescribeImages"), 25);
+        assert!(images
+            .iter()
+            .all(|image| image.enriched_image_name.is_some()));
+    }
+
+    #[test]
+    fn console_url_in_the_partition_of_the_registry() {
+        let console_url = |image_name: &str| {
+            image_to_ecr_console_url(&Image {
+                image_name: image_name.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(
+            console_url(&format!("{}:1.19", ECR_WEB)).as_deref(),
+            Some("https://eu-west-1.console.aws.amazon.com/ecr/repositories/private/123456789012/team/web?region=eu-west-1")
+        );
+        assert_eq!(
+            console_url("123456789012.dkr.ecr.us-gov-west-1.amazonaws.com/web:1").as_deref(),
+            Some(
+                "https://us-gov-west-1.console.amazonaws-us-gov.com/ecr/repositories/private/123456789012/web?region=us-gov-west-1"
+            )
+        );
+        assert_eq!(
+            console_url("123456789012.dkr.ecr.cn-north-1.amazonaws.com.cn/web:1").as_deref(),
+            Some("https://cn-north-1.console.amazonaws.cn/ecr/repositories/private/123456789012/web?region=cn-north-1")
+        );
+        assert_eq!(console_url("nginx:1.19"), None);
+    }
+}
@@ -1,123 +1,755 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 use std::vec::Vec;
 
+use crate::arn;
 use crate::client::HttpClient;
 use crate::credentials::Credentials;
+use crate::ecr::{self, EcrClients};
+use crate::error::{self, EcsError};
+use crate::iam::{self, PermissionsCheck};
+use crate::image::{Image, LaunchTypeSource, ServiceImage};
+use crate::latency::{ApiLatencyReport, ApiRecorder, OperationLatency};
+use crate::processor::{self, ImageProcessor};
 use anyhow::Result;
 
-use futures::future::join_all;
+use futures::channel::mpsc;
+use futures::future::{join, join_all};
+use futures::{Future, StreamExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::time::{delay_for, Instant};
+use tracing::Instrument;
 
 use rusoto_core::region::Region;
-use rusoto_credential::StaticProvider;
+use rusoto_credential::{ChainProvider, StaticProvider};
 
 use rusoto_ecs::{
-    DescribeServicesRequest, DescribeTaskDefinitionRequest, Ecs, EcsClient, ListClustersRequest,
-    ListServicesRequest,
+    Cluster, ContainerInstance, DescribeClustersRequest, DescribeContainerInstancesRequest,
+    DescribeServicesRequest, DescribeTaskDefinitionRequest, DescribeTasksRequest, Ecs, EcsClient,
+    ListClustersRequest, ListContainerInstancesRequest, ListServicesRequest,
+    ListTaskDefinitionFamiliesRequest, ListTasksRequest, Service, Tag, Task, TaskDefinition,
 };
 
+const DESCRIBE_SERVICES_MAX_SERVICES: usize = 10;
+const DESCRIBE_CLUSTERS_MAX_CLUSTERS: usize = 100;
+const DESCRIBE_CONTAINER_INSTANCES_MAX_INSTANCES: usize = 100;
+const DESCRIBE_TASKS_MAX_TASKS: usize = 100;
+// task definition families described concurrently by find_task_definitions_using_image
+const DESCRIBE_TASK_DEFINITIONS_CONCURRENCY: usize = 10;
+// regions with an ecs-fips endpoint
+const FIPS_REGIONS: [&str; 6] = [
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+    "us-gov-east-1",
+    "us-gov-west-1",
+];
+
 #[derive(Debug)]
-pub struct Image {
-    pub image_name: String,
-    pub task_definition_name: String,
+pub struct ClusterDetail {
+    pub cluster_arn: String,
+    pub cluster_name: String,
+    pub status: Option<String>,
+    pub registered_container_instances_count: i64,
+    pub running_tasks_count: i64,
+    pub pending_tasks_count: i64,
+    pub active_services_count: i64,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl From<Cluster> for ClusterDetail {
+    fn from(cluster: Cluster) -> Self {
+        let cluster_arn = cluster.cluster_arn.unwrap_or_default();
+        ClusterDetail {
+            cluster_name: cluster
+                .cluster_name
+                .unwrap_or_else(|| arn::cluster_short_name(&cluster_arn).to_owned()),
+            cluster_arn,
+            status: cluster.status,
+            registered_container_instances_count: cluster
+                .registered_container_instances_count
+                .unwrap_or(0),
+            running_tasks_count: cluster.running_tasks_count.unwrap_or(0),
+            pending_tasks_count: cluster.pending_tasks_count.unwrap_or(0),
+            active_services_count: cluster.active_services_count.unwrap_or(0),
+            tags: tags_to_map(cluster.tags.as_deref()),
+        }
+    }
+}
+
+pub(crate) fn tags_to_map(tags: Option<&[Tag]>) -> BTreeMap<String, String> {
+    tags.unwrap_or_default()
+        .iter()
+        .filter_map(|tag| {
+            let value = tag.value.clone().unwrap_or_default();
+            tag.key.clone().map(|k| (k, value))
+        })
+        .collect()
+}
+
+// ECS Anywhere server registered as a container instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalInstanceInfo {
+    // SSM managed instance id, mi-...
+    pub instance_id: String,
+    pub agent_version: String,
+    pub connected_status: bool,
+    // ecs.os-type attribute, e.g. linux or windows
+    pub platform_type: String,
+}
+
+impl ExternalInstanceInfo {
+    // external instances are SSM managed instances instead of EC2 ones
+    pub fn from_container_instance(container_instance: &ContainerInstance) -> Option<Self> {
+        let instance_id = container_instance.ec_2_instance_id.as_deref()?;
+        if !instance_id.starts_with("mi-") {
+            return None;
+        }
+        let platform_type = container_instance
+            .attributes
+            .iter()
+            .flatten()
+            .find(|attribute| attribute.name == "ecs.os-type")
+            .and_then(|attribute| attribute.value.clone())
+            .unwrap_or_default();
+        Some(ExternalInstanceInfo {
+            instance_id: instance_id.to_owned(),
+            agent_version: container_instance
+                .version_info
+                .as_ref()
+                .and_then(|version_info| version_info.agent_version.clone())
+                .unwrap_or_default(),
+            connected_status: container_instance.agent_connected.unwrap_or(false),
+            platform_type,
+        })
+    }
+}
+
+// running task, including standalone tasks started by RunTask
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub task_arn: String,
+    pub task_definition_arn: String,
+    // service:{name} for service tasks, family:{name} for standalone ones
+    pub group: Option<String>,
+    pub started_by: Option<String>,
+    // e.g. FARGATE_SPOT, None for tasks placed by launch type
+    pub task_capacity_provider: Option<String>,
+    pub task_launch_type: Option<String>,
+}
+
+impl From<&Task> for TaskInfo {
+    fn from(task: &Task) -> Self {
+        TaskInfo {
+            task_arn: task.task_arn.clone().unwrap_or_default(),
+            task_definition_arn: task.task_definition_arn.clone().unwrap_or_default(),
+            group: task.group.clone(),
+            started_by: task.started_by.clone(),
+            task_capacity_provider: task.capacity_provider_name.clone(),
+            task_launch_type: task.launch_type.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ClusterFilter {
+    pub cluster_include_patterns: Vec<String>,
+    // excludes take precedence over includes
+    pub cluster_exclude_patterns: Vec<String>,
+}
+
+struct CompiledClusterFilter {
+    includes: Vec<Regex>,
+    excludes: Vec<Regex>,
+}
+
+impl ClusterFilter {
+    fn compile(&self) -> Result<CompiledClusterFilter> {
+        Ok(CompiledClusterFilter {
+            includes: compile_patterns(&self.cluster_include_patterns)?,
+            excludes: compile_patterns(&self.cluster_exclude_patterns)?,
+        })
+    }
+}
+
+impl CompiledClusterFilter {
+    fn matches(&self, cluster_arn: &str) -> bool {
+        if self.excludes.iter().any(|re| re.is_match(cluster_arn)) {
+            return false;
+        }
+        self.includes.is_empty() || self.includes.iter().any(|re| re.is_match(cluster_arn))
+    }
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|_| EcsError::InvalidFilterPattern(pattern.clone()).into())
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchedulingStrategy {
+    Replica,
+    Daemon,
+}
+
+impl SchedulingStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SchedulingStrategy::Replica => "REPLICA",
+            SchedulingStrategy::Daemon => "DAEMON",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LaunchType {
+    Ec2,
+    Fargate,
+    External,
+}
+
+impl LaunchType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LaunchType::Ec2 => "EC2",
+            LaunchType::Fargate => "FARGATE",
+            LaunchType::External => "EXTERNAL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskDefinitionStatus {
+    Active,
+    Inactive,
+    DeleteInProgress,
+}
+
+impl TaskDefinitionStatus {
+    pub fn from_status(status: &str) -> Option<Self> {
+        match status {
+            "ACTIVE" => Some(TaskDefinitionStatus::Active),
+            "INACTIVE" => Some(TaskDefinitionStatus::Inactive),
+            "DELETE_IN_PROGRESS" => Some(TaskDefinitionStatus::DeleteInProgress),
+            _ => None,
+        }
+    }
+
+    pub fn of(task_definition: &TaskDefinition) -> Option<Self> {
+        task_definition
+            .status
+            .as_deref()
+            .and_then(TaskDefinitionStatus::from_status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterScanOptions {
+    pub scheduling_strategy: Option<SchedulingStrategy>,
+    pub launch_type: Option<LaunchType>,
+    // page size for ListClusters and ListServices, small pages help against throttling
+    pub max_results: Option<i64>,
+    // draining services are being decommissioned and no longer serve traffic
+    pub exclude_draining: bool,
+}
+
+impl Default for ClusterScanOptions {
+    fn default() -> Self {
+        ClusterScanOptions {
+            scheduling_strategy: None,
+            launch_type: None,
+            max_results: None,
+            exclude_draining: true,
+        }
+    }
+}
+
+impl ClusterScanOptions {
+    fn validated_max_results(&self) -> Result<Option<i64>> {
+        match self.max_results {
+            Some(max_results) if !(1..=100).contains(&max_results) => {
+                Err(EcsError::InvalidMaxResults(max_results))?
+            }
+            max_results => Ok(max_results),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ThrottleStrategy {
+    #[default]
+    AbortAll,
+    SkipCluster,
+    WaitAndRetry(Duration),
+}
+
+#[derive(Default, Clone)]
+pub struct ScanOptions {
+    pub filter: ClusterFilter,
+    pub cluster_options: ClusterScanOptions,
+    pub on_throttle: ThrottleStrategy,
+    // resolve tagged ECR images to their digest while scanning
+    pub enrich_with_ecr_details: Option<EcrClients>,
+    // run on every image once its cluster is scanned
+    pub processors: Vec<Arc<dyn ImageProcessor>>,
+    // simulate the required IAM actions before listing anything
+    pub preflight_check: Option<PermissionsCheck>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClusterScanStatus {
+    Scanned,
+    // excluded by the cluster filter
+    Skipped,
+    Error(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterScanResult {
+    pub status: ClusterScanStatus,
+    pub services: Vec<ServiceImage>,
+    pub images: Vec<Image>,
+    pub scan_duration: Duration,
+    // number of ECS API calls issued while scanning this cluster
+    pub api_calls_made: u32,
+    pub api_latency: Vec<OperationLatency>,
+    // services whose image could not be resolved, the rest of the cluster is still scanned
+    #[serde(default)]
+    pub service_errors: Vec<ServiceScanError>,
+    // scan retries per ECS operation whose throttling triggered them
+    #[serde(default)]
+    pub retry_counts: BTreeMap<String, u32>,
+    // described task definitions of the services, with every container definition.
+    // Not serialized, rusoto only derives Serialize for them in tests, so clusters
+    // restored from a checkpoint come back without them.
+    #[serde(skip)]
+    pub task_definitions: Vec<TaskDefinition>,
+    // every container of the services, images only keeps the last one of each task definition
+    #[serde(default)]
+    pub containers: Vec<Image>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanError {
+    pub cluster: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceScanError {
     pub service_name: String,
+    pub cluster: String,
+    pub error: String,
 }
 
-pub async fn get_image_of_task_definition(
+#[derive(Debug, Serialize)]
+pub struct ScanOutcome {
+    pub clusters: BTreeMap<String, ClusterScanResult>,
+    pub errors: Vec<ScanError>,
+    pub service_errors: Vec<ServiceScanError>,
+    pub latency: ApiLatencyReport,
+    pub total_retries: u32,
+}
+
+impl ClusterScanResult {
+    fn with_status(status: ClusterScanStatus) -> Self {
+        ClusterScanResult {
+            status,
+            services: Vec::new(),
+            images: Vec::new(),
+            scan_duration: Duration::default(),
+            api_calls_made: 0,
+            api_latency: Vec::new(),
+            service_errors: Vec::new(),
+            retry_counts: BTreeMap::new(),
+            task_definitions: Vec::new(),
+            containers: Vec::new(),
+        }
+    }
+}
+
+pub(crate) async fn describe_task_definition(
     ecs_client: &EcsClient,
     task_definition: String,
-    service_name: String,
-) -> Result<Option<Image>> {
+    recorder: &ApiRecorder,
+) -> Result<Option<TaskDefinition>> {
+    Ok(
+        describe_task_definition_with_tags(ecs_client, task_definition, recorder)
+            .await?
+            .map(|(td, _)| td),
+    )
+}
+
+// tags come back next to the task definition in the same response
+async fn describe_task_definition_with_tags(
+    ecs_client: &EcsClient,
+    task_definition: String,
+    recorder: &ApiRecorder,
+) -> Result<Option<(TaskDefinition, BTreeMap<String, String>)>> {
     let task_definition_req = DescribeTaskDefinitionRequest {
         task_definition,
-        include: None,
+        include: Some(vec!["TAGS".into()]),
     };
-    let task_definition_res = ecs_client
-        .describe_task_definition(task_definition_req)
+    let task_definition_res = recorder
+        .record(
+            "describe_task_definition",
+            ecs_client.describe_task_definition(task_definition_req),
+        )
         .await?;
+    let tags = tags_to_map(task_definition_res.tags.as_deref());
+    Ok(task_definition_res.task_definition.map(|td| (td, tags)))
+}
 
-    Ok(task_definition_res.task_definition.and_then(move |td| {
-        td.clone()
-            .task_definition_arn
-            .and_then(move |td_arn| {
-                td.container_definitions
-                    .and_then(|cds| cds.last().map(|cd| (td_arn, cd.clone())))
-            })
-            .and_then(move |(td_arn, cd)| {
-                cd.image.map(|i| Image {
-                    image_name: i,
-                    task_definition_name: td_arn,
-                    service_name,
-                })
-            })
+pub async fn get_image_of_task_definition(
+    ecs_client: &EcsClient,
+    task_definition: String,
+    cluster_arn: String,
+    service_name: String,
+) -> Result<Option<Image>> {
+    let task_definition =
+        describe_task_definition_with_tags(ecs_client, task_definition, &ApiRecorder::default())
+            .await?;
+    Ok(task_definition.and_then(|(td, tags)| {
+        image_of_task_definition(&td, cluster_arn, service_name).map(|image| Image {
+            task_definition_tags: tags,
+            ..image
+        })
     }))
 }
 
-async fn get_images_of_services(
+// services may still point at a deregistered revision while a deployment rolls out
+fn image_of_task_definition(
+    task_definition: &TaskDefinition,
+    cluster_arn: String,
+    service_name: String,
+) -> Option<Image> {
+    match TaskDefinitionStatus::of(task_definition) {
+        Some(TaskDefinitionStatus::Inactive) | Some(TaskDefinitionStatus::DeleteInProgress) => {
+            Some(Image::deregistered(
+                task_definition,
+                cluster_arn,
+                service_name,
+            ))
+        }
+        _ => Image::from_task_definition(task_definition, cluster_arn, service_name),
+    }
+}
+
+// deregistered task definitions only get the placeholder image, see image_of_task_definition
+fn containers_of_task_definition(
+    task_definition: &TaskDefinition,
+    cluster_arn: &str,
+    service_name: &str,
+) -> Vec<Image> {
+    match TaskDefinitionStatus::of(task_definition) {
+        Some(TaskDefinitionStatus::Inactive) | Some(TaskDefinitionStatus::DeleteInProgress) => {
+            Vec::new()
+        }
+        _ => Image::containers_of_task_definition(task_definition, cluster_arn, service_name),
+    }
+}
+
+struct ServiceScan {
+    service: ServiceImage,
+    image: Option<Image>,
+    containers: Vec<Image>,
+    task_definition: Option<TaskDefinition>,
+}
+
+async fn get_image_of_service(
     ecs_client: &EcsClient,
-    service_arns: Vec<String>,
-    cluster_name: String,
-) -> Result<Vec<Image>> {
-    let mut images: Vec<Image> = Vec::new();
-    let describe_services_req = DescribeServicesRequest {
-        cluster: Some(cluster_name.clone()),
-        services: service_arns,
-        include: None,
+    service: Service,
+    task_definition: String,
+    service_name: String,
+    recorder: &ApiRecorder,
+) -> Result<ServiceScan> {
+    let (task_definition, task_definition_tags) =
+        match describe_task_definition_with_tags(ecs_client, task_definition, recorder).await? {
+            Some((td, tags)) => (Some(td), tags),
+            None => (None, BTreeMap::new()),
+        };
+    if service.status.as_deref() == Some("ACTIVE")
+        && task_definition.as_ref().and_then(TaskDefinitionStatus::of)
+            == Some(TaskDefinitionStatus::Inactive)
+    {
+        tracing::warn!(
+            service = %service_name,
+            "active service references an inactive task definition"
+        );
+    }
+    let service_image = ServiceImage::from_service(&service, task_definition.as_ref());
+    let cluster_arn = service.cluster_arn.unwrap_or_default();
+    let service_arn = service.service_arn.unwrap_or_default();
+    let with_service = |image: Image| Image {
+        service_arn: service_arn.clone(),
+        task_definition_tags: task_definition_tags.clone(),
+        ..image
     };
+    let containers = task_definition
+        .iter()
+        .flat_map(|td| containers_of_task_definition(td, &cluster_arn, &service_name))
+        .map(with_service)
+        .collect();
+    let image = task_definition
+        .as_ref()
+        .and_then(|td| image_of_task_definition(td, cluster_arn.clone(), service_name))
+        .map(with_service);
+    Ok(ServiceScan {
+        service: service_image,
+        image,
+        containers,
+        task_definition,
+    })
+}
+
+async fn describe_services(
+    ecs_client: &EcsClient,
+    service_arns: &[String],
+    cluster_name: &str,
+    recorder: &ApiRecorder,
+) -> Result<Vec<Service>> {
+    let mut services: Vec<Service> = Vec::new();
 
-    let describe_services_res = ecs_client.describe_services(describe_services_req).await?;
+    // DescribeServices rejects requests with more than 10 services
+    for service_arns_chunk in service_arns.chunks(DESCRIBE_SERVICES_MAX_SERVICES) {
+        let describe_services_req = DescribeServicesRequest {
+            cluster: Some(cluster_name.to_owned()),
+            services: service_arns_chunk.to_vec(),
+            include: Some(vec!["TAGS".into()]),
+        };
 
-    if let Some(services) = describe_services_res.services {
-        let task_definitions: Vec<(String, String)> = services
-            .into_iter()
-            .filter_map(|service| {
-                service
-                    .clone()
-                    .task_definition
-                    .and_then(|td| service.service_name.map(|sn| (td, sn)))
-            })
-            .collect();
-        let get_images_futures = task_definitions
-            .into_iter()
-            .map(|(td, sn)| get_image_of_task_definition(ecs_client, td, sn));
+        let describe_services_res = recorder
+            .record(
+                "describe_services",
+                ecs_client.describe_services(describe_services_req),
+            )
+            .await?;
+        if let Some(described_services) = describe_services_res.services {
+            services.extend(described_services);
+        }
+    }
+    Ok(services)
+}
+
+// every service of the cluster, as returned by DescribeServices
+pub async fn get_services_of_a_cluster(
+    ecs_client: &EcsClient,
+    cluster_name: &str,
+) -> Result<Vec<Service>> {
+    let recorder = ApiRecorder::default();
+    let mut next_token: Option<String> = None;
+    let mut services: Vec<Service> = Vec::new();
 
-        let get_images_results = join_all(get_images_futures).await;
+    loop {
+        let list_services_req = ListServicesRequest {
+            cluster: Some(cluster_name.to_owned()),
+            next_token,
+            ..Default::default()
+        };
+        let list_services_res = recorder
+            .record("list_services", ecs_client.list_services(list_services_req))
+            .await?;
+        if let Some(service_arns) = list_services_res.service_arns {
+            services.extend(
+                describe_services(ecs_client, &service_arns, cluster_name, &recorder).await?,
+            );
+        }
+        if list_services_res.next_token.is_none() {
+            break;
+        }
+        next_token = list_services_res.next_token;
+    }
+    Ok(services)
+}
 
-        let get_images_result: Result<Vec<Option<Image>>> =
-            get_images_results.into_iter().collect();
+// same as the services-stable waiter: only the PRIMARY deployment is left and it is fully running,
+// right after a forced deployment the old tasks alone would otherwise satisfy the counts
+fn is_steady(service: &Service) -> bool {
+    service.deployments.as_ref().map(Vec::len) == Some(1)
+        && service.running_count == service.desired_count
+        && service.pending_count == Some(0)
+}
 
-        let some_images: Vec<Image> = get_images_result?
+// polls DescribeServices until all desired tasks run, e.g. after a forced deployment
+pub async fn wait_for_steady_state(
+    ecs_client: &EcsClient,
+    cluster: &str,
+    service: &str,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<ServiceImage> {
+    let recorder = ApiRecorder::default();
+    let started_at = Instant::now();
+    loop {
+        let described = describe_services(ecs_client, &[service.to_owned()], cluster, &recorder)
+            .await?
             .into_iter()
-            .filter_map(|image_opt| image_opt)
-            .collect();
-        images.extend(some_images);
+            .next()
+            .ok_or_else(|| anyhow!("Service {} not found in cluster {}", service, cluster))?;
+        if is_steady(&described) {
+            return Ok(ServiceImage::from_service(&described, None));
+        }
+        if started_at.elapsed() + poll_interval > timeout {
+            Err(EcsError::DeploymentTimeout {
+                service: service.to_owned(),
+                desired_count: described.desired_count.unwrap_or(0),
+                running_count: described.running_count.unwrap_or(0),
+                pending_count: described.pending_count.unwrap_or(0),
+            })?;
+        }
+        delay_for(poll_interval).await;
+    }
+}
+
+// the launch type of services on a capacity provider strategy is derived from the strategy
+fn runs_on(service: &Service, launch_type: LaunchType) -> bool {
+    LaunchTypeSource::of(service).is_some_and(|source| source.as_str() == launch_type.as_str())
+}
+
+fn is_draining(service: &Service) -> bool {
+    service.status.as_deref() == Some("DRAINING")
+}
+
+// ARNs of the services being drained in the cluster
+pub async fn find_draining_services(ecs_client: &EcsClient, cluster: &str) -> Result<Vec<String>> {
+    let services = get_services_of_a_cluster(ecs_client, cluster).await?;
+    Ok(services
+        .into_iter()
+        .filter(is_draining)
+        .filter_map(|service| service.service_arn)
+        .collect())
+}
+
+#[derive(Default)]
+struct ScannedServices {
+    services: Vec<ServiceImage>,
+    images: Vec<Image>,
+    containers: Vec<Image>,
+    task_definitions: Vec<TaskDefinition>,
+    service_errors: Vec<ServiceScanError>,
+}
+
+async fn get_images_of_services(
+    ecs_client: &EcsClient,
+    service_arns: Vec<String>,
+    cluster_name: String,
+    options: &ClusterScanOptions,
+    recorder: &ApiRecorder,
+) -> Result<ScannedServices> {
+    let mut services =
+        describe_services(ecs_client, &service_arns, &cluster_name, recorder).await?;
+    if options.exclude_draining {
+        services.retain(|service| !is_draining(service));
+    }
+    if let Some(launch_type) = options.launch_type {
+        services.retain(|service| runs_on(service, launch_type));
+    }
+
+    let task_definitions: Vec<(Service, String, String)> = services
+        .into_iter()
+        .filter_map(|service| {
+            let td = service.task_definition.clone()?;
+            let sn = service.service_name.clone()?;
+            Some((service, td, sn))
+        })
+        .collect();
+    let get_images_futures = task_definitions
+        .into_iter()
+        .map(|(service, td, sn)| async move {
+            let res = get_image_of_service(ecs_client, service, td, sn.clone(), recorder).await;
+            (sn, res)
+        });
+
+    let get_images_results = join_all(get_images_futures).await;
+
+    let mut scanned = ScannedServices::default();
+    for (service_name, get_image_res) in get_images_results {
+        match get_image_res {
+            Ok(service_scan) => {
+                scanned.services.push(service_scan.service);
+                scanned.images.extend(service_scan.image);
+                scanned.containers.extend(service_scan.containers);
+                scanned
+                    .task_definitions
+                    .extend(service_scan.task_definition);
+            }
+            // throttling is handled for the whole cluster by the throttle strategy
+            Err(err) if error::is_throttled(&err) => return Err(err),
+            Err(err) => {
+                warn!("Could not get image of service {}: {:#}", service_name, err);
+                scanned.service_errors.push(ServiceScanError {
+                    service_name,
+                    cluster: cluster_name.clone(),
+                    error: format!("{:#}", err),
+                });
+            }
+        }
     }
-    Ok(images)
+    Ok(scanned)
 }
 
 pub async fn get_images_of_a_cluster(
     ecs_client: &EcsClient,
     cluster_name: String,
-) -> Result<(String, Vec<Image>)> {
+    options: &ClusterScanOptions,
+) -> Result<(String, ClusterScanResult)> {
+    scan_services_of_cluster(ecs_client, cluster_name, options, &ApiRecorder::default()).await
+}
+
+// the recorder outlives a throttled attempt, so retried calls are still accounted for
+async fn scan_services_of_cluster(
+    ecs_client: &EcsClient,
+    cluster_name: String,
+    options: &ClusterScanOptions,
+    recorder: &ApiRecorder,
+) -> Result<(String, ClusterScanResult)> {
+    let max_results = options.validated_max_results()?;
+    let started_at = Instant::now();
     let mut next_token: Option<String> = None;
 
+    let mut all_services: Vec<ServiceImage> = Vec::new();
     let mut all_images: Vec<Image> = Vec::new();
+    let mut all_service_errors: Vec<ServiceScanError> = Vec::new();
+    let mut all_containers: Vec<Image> = Vec::new();
+    let mut all_task_definitions: Vec<TaskDefinition> = Vec::new();
 
     loop {
         let list_services_req = ListServicesRequest {
-            max_results: None,
+            max_results,
             next_token,
             cluster: Some(cluster_name.clone()),
+            // filtered after DescribeServices, ECS would leave out capacity provider services
             launch_type: None,
-            scheduling_strategy: None,
+            scheduling_strategy: options
+                .scheduling_strategy
+                .map(|strategy| strategy.as_str().to_owned()),
         };
 
-        let list_services_res = ecs_client.list_services(list_services_req).await?;
+        let list_services_res = recorder
+            .record("list_services", ecs_client.list_services(list_services_req))
+            .await?;
         if let Some(service_arns) = list_services_res.service_arns {
             if !service_arns.is_empty() {
-                let got_images =
-                    get_images_of_services(ecs_client, service_arns, cluster_name.clone()).await?;
-                all_images.extend(got_images);
+                let scanned = get_images_of_services(
+                    ecs_client,
+                    service_arns,
+                    cluster_name.clone(),
+                    options,
+                    recorder,
+                )
+                .await?;
+                all_services.extend(scanned.services);
+                all_images.extend(scanned.images);
+                all_containers.extend(scanned.containers);
+                all_task_definitions.extend(scanned.task_definitions);
+                all_service_errors.extend(scanned.service_errors);
             }
         }
         if list_services_res.next_token.is_none() {
@@ -126,19 +758,50 @@ pub async fn get_images_of_a_cluster(
         next_token = list_services_res.next_token;
     }
 
-    Ok((cluster_name.clone(), all_images))
+    // keep the output stable between two scans of an unchanged cluster
+    all_services.sort_by(|a, b| a.service_name.cmp(&b.service_name));
+    all_images.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    all_containers.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+
+    let scan_result = ClusterScanResult {
+        status: ClusterScanStatus::Scanned,
+        services: all_services,
+        images: all_images,
+        scan_duration: started_at.elapsed(),
+        api_calls_made: recorder.call_count(),
+        api_latency: recorder.latencies(&cluster_name),
+        service_errors: all_service_errors,
+        retry_counts: recorder.retry_counts(),
+        task_definitions: all_task_definitions,
+        containers: all_containers,
+    };
+    Ok((cluster_name, scan_result))
 }
 
+#[deprecated(note = "use get_cluster_details, which also returns the cluster ARNs")]
 pub async fn get_clusters(ecs_client: &EcsClient) -> Result<Vec<String>> {
+    list_cluster_arns(ecs_client, None, &ApiRecorder::default()).await
+}
+
+async fn list_cluster_arns(
+    ecs_client: &EcsClient,
+    max_results: Option<i64>,
+    recorder: &ApiRecorder,
+) -> Result<Vec<String>> {
     let mut clusters: Vec<String> = Vec::new();
 
     let mut list_clusters_req = ListClustersRequest {
-        max_results: None,
+        max_results,
         next_token: None,
     };
 
     loop {
-        let list_clusters_res = ecs_client.list_clusters(list_clusters_req.clone()).await?;
+        let list_clusters_res = recorder
+            .record(
+                "list_clusters",
+                ecs_client.list_clusters(list_clusters_req.clone()),
+            )
+            .await?;
         if let Some(cluster_arns) = list_clusters_res.cluster_arns {
             clusters.extend(cluster_arns);
         }
@@ -151,42 +814,2643 @@ pub async fn get_clusters(ecs_client: &EcsClient) -> Result<Vec<String>> {
     Ok(clusters)
 }
 
-pub async fn get_images_of_clusters(
+pub async fn get_cluster_details(ecs_client: &EcsClient) -> Result<Vec<ClusterDetail>> {
+    let cluster_arns = list_cluster_arns(ecs_client, None, &ApiRecorder::default()).await?;
+    let mut details: Vec<ClusterDetail> = Vec::new();
+
+    for cluster_arns_chunk in cluster_arns.chunks(DESCRIBE_CLUSTERS_MAX_CLUSTERS) {
+        let describe_clusters_req = DescribeClustersRequest {
+            clusters: Some(cluster_arns_chunk.to_vec()),
+            include: Some(vec!["TAGS".to_owned()]),
+        };
+        let describe_clusters_res = ecs_client
+            .describe_clusters(describe_clusters_req)
+            .await
+            .map_err(error::from_rusoto)?;
+        if let Some(clusters) = describe_clusters_res.clusters {
+            details.extend(clusters.into_iter().map(ClusterDetail::from));
+        }
+    }
+    Ok(details)
+}
+
+pub async fn get_container_instances(
     ecs_client: &EcsClient,
-    cluster_includes: &Vec<String>,
-) -> Result<HashMap<String, Vec<Image>>> {
-    let clusters = get_clusters(ecs_client).await?;
-    debug!("Got clusters {:?}", clusters);
+    cluster_name: &str,
+) -> Result<Vec<ContainerInstance>> {
+    let mut container_instances: Vec<ContainerInstance> = Vec::new();
+    let mut next_token: Option<String> = None;
 
-    let get_clusters_images_futures = clusters
-        .into_iter()
-        .filter(|cluster_arn| {
-            cluster_includes.is_empty()
-                || cluster_includes
+    loop {
+        let list_container_instances_req = ListContainerInstancesRequest {
+            cluster: Some(cluster_name.to_owned()),
+            next_token,
+            ..Default::default()
+        };
+        let list_container_instances_res = ecs_client
+            .list_container_instances(list_container_instances_req)
+            .await
+            .map_err(error::from_rusoto)?;
+        let container_instance_arns = list_container_instances_res
+            .container_instance_arns
+            .unwrap_or_default();
+        for arns_chunk in container_instance_arns.chunks(DESCRIBE_CONTAINER_INSTANCES_MAX_INSTANCES)
+        {
+            let describe_container_instances_req = DescribeContainerInstancesRequest {
+                cluster: Some(cluster_name.to_owned()),
+                container_instances: arns_chunk.to_vec(),
+                include: None,
+            };
+            let describe_container_instances_res = ecs_client
+                .describe_container_instances(describe_container_instances_req)
+                .await
+                .map_err(error::from_rusoto)?;
+            container_instances.extend(
+                describe_container_instances_res
+                    .container_instances
+                    .unwrap_or_default(),
+            );
+        }
+        if list_container_instances_res.next_token.is_none() {
+            break;
+        }
+        next_token = list_container_instances_res.next_token;
+    }
+    Ok(container_instances)
+}
+
+pub async fn get_running_tasks(
+    ecs_client: &EcsClient,
+    cluster_name: &str,
+) -> Result<Vec<TaskInfo>> {
+    let mut tasks: Vec<TaskInfo> = Vec::new();
+    let mut next_token: Option<String> = None;
+
+    loop {
+        let list_tasks_req = ListTasksRequest {
+            cluster: Some(cluster_name.to_owned()),
+            desired_status: Some("RUNNING".to_owned()),
+            next_token,
+            ..Default::default()
+        };
+        let list_tasks_res = ecs_client
+            .list_tasks(list_tasks_req)
+            .await
+            .map_err(error::from_rusoto)?;
+        let task_arns = list_tasks_res.task_arns.unwrap_or_default();
+        for arns_chunk in task_arns.chunks(DESCRIBE_TASKS_MAX_TASKS) {
+            let describe_tasks_req = DescribeTasksRequest {
+                cluster: Some(cluster_name.to_owned()),
+                tasks: arns_chunk.to_vec(),
+                include: None,
+            };
+            let describe_tasks_res = ecs_client
+                .describe_tasks(describe_tasks_req)
+                .await
+                .map_err(error::from_rusoto)?;
+            tasks.extend(
+                describe_tasks_res
+                    .tasks
                     .iter()
-                    .find(|ci| cluster_arn.contains(*ci))
-                    .is_some()
-        })
-        .map(|cluster_arn| get_images_of_a_cluster(ecs_client, cluster_arn));
+                    .flatten()
+                    .map(TaskInfo::from),
+            );
+        }
+        if list_tasks_res.next_token.is_none() {
+            break;
+        }
+        next_token = list_tasks_res.next_token;
+    }
+    Ok(tasks)
+}
+
+pub async fn get_external_instances(
+    ecs_client: &EcsClient,
+    cluster_name: &str,
+) -> Result<Vec<ExternalInstanceInfo>> {
+    let container_instances = get_container_instances(ecs_client, cluster_name).await?;
+    Ok(container_instances
+        .iter()
+        .filter_map(ExternalInstanceInfo::from_container_instance)
+        .collect())
+}
+
+// latest ACTIVE revision of every family whose containers use exactly image_name
+pub async fn find_task_definitions_using_image(
+    ecs_client: &EcsClient,
+    image_name: &str,
+    family_prefix: Option<&str>,
+) -> Result<Vec<String>> {
+    let recorder = ApiRecorder::default();
+    let mut next_token: Option<String> = None;
+    let mut families: Vec<String> = Vec::new();
+    loop {
+        let list_families_req = ListTaskDefinitionFamiliesRequest {
+            family_prefix: family_prefix.map(str::to_owned),
+            status: Some("ACTIVE".to_owned()),
+            next_token,
+            ..Default::default()
+        };
+        let list_families_res = recorder
+            .record(
+                "list_task_definition_families",
+                ecs_client.list_task_definition_families(list_families_req),
+            )
+            .await?;
+        families.extend(list_families_res.families.unwrap_or_default());
+        if list_families_res.next_token.is_none() {
+            break;
+        }
+        next_token = list_families_res.next_token;
+    }
 
-    let get_clusters_images_res = join_all(get_clusters_images_futures).await;
+    let mut task_definition_arns: Vec<String> = Vec::new();
+    for families_chunk in families.chunks(DESCRIBE_TASK_DEFINITIONS_CONCURRENCY) {
+        // a bare family resolves to its latest ACTIVE revision
+        let describe_futures = families_chunk
+            .iter()
+            .map(|family| describe_task_definition(ecs_client, family.clone(), &recorder));
+        for task_definition in join_all(describe_futures).await {
+            let task_definition = match task_definition? {
+                Some(td) => td,
+                None => continue,
+            };
+            let uses_image = task_definition
+                .container_definitions
+                .iter()
+                .flatten()
+                .any(|cd| cd.image.as_deref() == Some(image_name));
+            if uses_image {
+                task_definition_arns.extend(task_definition.task_definition_arn);
+            }
+        }
+    }
+    Ok(task_definition_arns)
+}
 
-    let mut res = HashMap::new();
-    for cluster_images_res in get_clusters_images_res {
-        let cluster_images_tuple = cluster_images_res?;
-        let (cluster_name, images) = cluster_images_tuple;
-        res.insert(cluster_name, images);
+async fn post_process_images(options: &ScanOptions, images: &mut [Image]) -> Result<()> {
+    if let Some(ref ecr_clients) = options.enrich_with_ecr_details {
+        ecr::enrich_images(ecr_clients, images).await;
     }
+    processor::run_processors(&options.processors, images).await
+}
 
-    Ok(res)
+async fn scan_cluster(
+    ecs_client: &EcsClient,
+    cluster_arn: String,
+    options: &ScanOptions,
+) -> Result<(String, ClusterScanResult)> {
+    let recorder = ApiRecorder::default();
+    let res = scan_services_of_cluster(
+        ecs_client,
+        cluster_arn.clone(),
+        &options.cluster_options,
+        &recorder,
+    )
+    .await;
+    match res {
+        Err(err) if error::is_throttled(&err) => match options.on_throttle {
+            ThrottleStrategy::AbortAll => Err(err),
+            ThrottleStrategy::SkipCluster => {
+                warn!("Skipping throttled cluster {}", cluster_arn);
+                let status = ClusterScanStatus::Error(err.to_string());
+                Ok((cluster_arn, ClusterScanResult::with_status(status)))
+            }
+            ThrottleStrategy::WaitAndRetry(wait) => {
+                let operation = recorder.last_throttled_operation().unwrap_or("unknown");
+                recorder.record_retry(operation);
+                tracing::warn!(
+                    cluster = %cluster_arn,
+                    operation = %operation,
+                    "throttled, retrying in {:?}",
+                    wait
+                );
+                delay_for(wait).await;
+                let retry_res = scan_services_of_cluster(
+                    ecs_client,
+                    cluster_arn.clone(),
+                    &options.cluster_options,
+                    &recorder,
+                )
+                .await;
+                match retry_res {
+                    // a second throttle only fails this cluster, like SkipCluster
+                    Err(err) if error::is_throttled(&err) => {
+                        warn!("Cluster {} still throttled after retrying", cluster_arn);
+                        let status = ClusterScanStatus::Error(err.to_string());
+                        let scan_result = ClusterScanResult {
+                            retry_counts: recorder.retry_counts(),
+                            ..ClusterScanResult::with_status(status)
+                        };
+                        Ok((cluster_arn, scan_result))
+                    }
+                    retry_res => retry_res,
+                }
+            }
+        },
+        res => res,
+    }
 }
 
-pub fn build_ecs_client(client: Arc<HttpClient>, creds: Credentials) -> EcsClient {
-    let cred_provider = StaticProvider::new(
-        creds.aws_access_key,
-        creds.aws_secret_key,
-        Some(creds.aws_sts_token),
-        None,
-    );
-    EcsClient::new_with(client, cred_provider, Region::EuWest1)
+#[tracing::instrument(skip(ecs_client, options), fields(cluster_count))]
+pub async fn get_images_of_clusters(
+    ecs_client: &EcsClient,
+    options: &ScanOptions,
+) -> Result<ScanOutcome> {
+    scan_clusters(ecs_client, options, BTreeMap::new(), &|_, _| Ok(())).await
+}
+
+// Same as get_images_of_clusters, but hands every image of a cluster to the callback
+// as soon as that cluster is scanned. The callback runs alongside the remaining scans.
+pub async fn stream_images_of_clusters<F, Fut>(
+    ecs_client: &EcsClient,
+    options: &ScanOptions,
+    callback: F,
+) -> Result<ScanOutcome>
+where
+    F: Fn(String, Image) -> Fut + Send + Sync,
+    Fut: Future<Output = ()>,
+{
+    let (sender, mut receiver) = mpsc::unbounded::<(String, Image)>();
+    let scan = async move {
+        scan_clusters(
+            ecs_client,
+            options,
+            BTreeMap::new(),
+            &|cluster_name, scan_result| {
+                for image in scan_result.images.iter() {
+                    // the receiver only goes away once the scan is over
+                    let _ = sender.unbounded_send((cluster_name.to_owned(), image.clone()));
+                }
+                Ok(())
+            },
+        )
+        .await
+    };
+    let consume = async {
+        while let Some((cluster_name, image)) = receiver.next().await {
+            callback(cluster_name, image).await;
+        }
+    };
+    let (outcome, _) = join(scan, consume).await;
+    outcome
+}
+
+// Scans the matching clusters one at a time and sends their images through a bounded
+// channel, so at most one cluster plus buffer_size images are held in memory.
+// Scanning stops once the receiver is dropped. A buffer_size of 0 is treated as 1.
+pub fn scan_images_channel(
+    ecs_client: Arc<EcsClient>,
+    options: ScanOptions,
+    buffer_size: usize,
+) -> (
+    impl Future<Output = Result<()>>,
+    async_channel::Receiver<Image>,
+) {
+    // bounded channels panic on a zero capacity
+    let (sender, receiver) = async_channel::bounded(buffer_size.max(1));
+    let scan = async move {
+        let filter = options.filter.compile()?;
+        let max_results = options.cluster_options.validated_max_results()?;
+        let clusters = list_cluster_arns(&ecs_client, max_results, &ApiRecorder::default()).await?;
+
+        for cluster_arn in clusters.into_iter().filter(|c| filter.matches(c)) {
+            if sender.is_closed() {
+                break;
+            }
+            let (cluster_name, mut scan_result) =
+                scan_cluster(&ecs_client, cluster_arn, &options).await?;
+            post_process_images(&options, &mut scan_result.images).await?;
+            for image in scan_result.images {
+                if sender.send(image).await.is_err() {
+                    debug!("Receiver dropped, stopping scan at {}", cluster_name);
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    };
+    (scan, receiver)
+}
+
+// preflight check and cluster listing, returns the (included, skipped) cluster ARNs
+async fn list_scan_targets(
+    ecs_client: &EcsClient,
+    options: &ScanOptions,
+    recorder: &ApiRecorder,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let filter = options.filter.compile()?;
+    if let Some(ref check) = options.preflight_check {
+        let report = iam::check_required_permissions(
+            &check.iam_client,
+            &check.principal_arn,
+            options.enrich_with_ecr_details.is_some(),
+        )
+        .await?;
+        if !report.denied.is_empty() {
+            Err(EcsError::InsufficientPermissions(report.denied))?;
+        }
+    }
+    let max_results = options.cluster_options.validated_max_results()?;
+    let clusters = list_cluster_arns(ecs_client, max_results, recorder).await?;
+    debug!("Got clusters {:?}", clusters);
+    tracing::Span::current().record("cluster_count", &clusters.len());
+
+    Ok(clusters
+        .into_iter()
+        .partition(|cluster_arn| filter.matches(cluster_arn)))
+}
+
+fn collect_outcome(
+    skipped_clusters: Vec<String>,
+    completed: BTreeMap<String, ClusterScanResult>,
+    scanned: Vec<Result<(String, ClusterScanResult)>>,
+    recorder: &ApiRecorder,
+) -> Result<ScanOutcome> {
+    // every listed cluster gets an entry, even if filtered out or without services
+    let mut res: BTreeMap<String, ClusterScanResult> = skipped_clusters
+        .into_iter()
+        .map(|cluster_arn| {
+            (
+                cluster_arn,
+                ClusterScanResult::with_status(ClusterScanStatus::Skipped),
+            )
+        })
+        .collect();
+    res.extend(completed);
+    let mut errors: Vec<ScanError> = Vec::new();
+    for cluster_images_res in scanned {
+        let (cluster_name, scan_result) = cluster_images_res?;
+        if let ClusterScanStatus::Error(ref error) = scan_result.status {
+            errors.push(ScanError {
+                cluster: cluster_name.clone(),
+                error: error.clone(),
+            });
+        }
+        res.insert(cluster_name, scan_result);
+    }
+
+    let mut latency = ApiLatencyReport {
+        operations: recorder.latencies(""),
+    };
+    for scan_result in res.values() {
+        latency
+            .operations
+            .extend(scan_result.api_latency.iter().cloned());
+    }
+
+    let service_errors = res
+        .values()
+        .flat_map(|scan_result| scan_result.service_errors.iter().cloned())
+        .collect();
+
+    let total_retries = res
+        .values()
+        .flat_map(|scan_result| scan_result.retry_counts.values())
+        .sum();
+
+    Ok(ScanOutcome {
+        clusters: res,
+        errors,
+        service_errors,
+        latency,
+        total_retries,
+    })
+}
+
+// Scans every cluster matching the filter except the already completed ones,
+// calling on_cluster_scanned as soon as each cluster is done
+pub(crate) async fn scan_clusters(
+    ecs_client: &EcsClient,
+    options: &ScanOptions,
+    completed: BTreeMap<String, ClusterScanResult>,
+    on_cluster_scanned: &(dyn Fn(&str, &ClusterScanResult) -> Result<()> + Sync),
+) -> Result<ScanOutcome> {
+    let recorder = ApiRecorder::default();
+    let (included_clusters, skipped_clusters) =
+        list_scan_targets(ecs_client, options, &recorder).await?;
+
+    let get_clusters_images_futures = included_clusters
+        .into_iter()
+        .filter(|cluster_arn| !completed.contains_key(cluster_arn))
+        .map(|cluster_arn| {
+            // child of the caller's span, so cluster scans keep its trace context
+            let span = tracing::info_span!("scan_cluster", cluster = %cluster_arn);
+            async move {
+                let (cluster_name, mut scan_result) =
+                    scan_cluster(ecs_client, cluster_arn, options).await?;
+                post_process_images(options, &mut scan_result.images).await?;
+                on_cluster_scanned(&cluster_name, &scan_result)?;
+                Ok((cluster_name, scan_result))
+            }
+            .instrument(span)
+        });
+
+    let get_clusters_images_res: Vec<Result<(String, ClusterScanResult)>> =
+        join_all(get_clusters_images_futures).await;
+
+    collect_outcome(
+        skipped_clusters,
+        completed,
+        get_clusters_images_res,
+        &recorder,
+    )
+}
+
+// Same as get_images_of_clusters, but each cluster is scanned in its own tokio task
+// so the threaded runtime can spread response deserialization across cores
+#[tracing::instrument(skip(ecs_client, options), fields(cluster_count))]
+pub async fn get_images_of_clusters_spawned(
+    ecs_client: Arc<EcsClient>,
+    options: Arc<ScanOptions>,
+) -> Result<ScanOutcome> {
+    let recorder = ApiRecorder::default();
+    let (included_clusters, skipped_clusters) =
+        list_scan_targets(&ecs_client, &options, &recorder).await?;
+
+    let scan_handles = included_clusters.into_iter().map(|cluster_arn| {
+        let ecs_client = ecs_client.clone();
+        let options = options.clone();
+        let span = tracing::info_span!("scan_cluster", cluster = %cluster_arn);
+        tokio::spawn(
+            async move {
+                let (cluster_name, mut scan_result) =
+                    scan_cluster(&ecs_client, cluster_arn, &options).await?;
+                post_process_images(&options, &mut scan_result.images).await?;
+                Ok((cluster_name, scan_result))
+            }
+            .instrument(span),
+        )
+    });
+
+    let get_clusters_images_res: Vec<Result<(String, ClusterScanResult)>> = join_all(scan_handles)
+        .await
+        .into_iter()
+        .map(|joined| joined.map_err(anyhow::Error::from).and_then(|res| res))
+        .collect();
+
+    collect_outcome(
+        skipped_clusters,
+        BTreeMap::new(),
+        get_clusters_images_res,
+        &recorder,
+    )
+}
+
+// clusters whose scan needed more than threshold retries
+pub fn high_retry_clusters(outcome: &ScanOutcome, threshold: u32) -> Vec<&str> {
+    outcome
+        .clusters
+        .iter()
+        .filter(|(_, scan_result)| scan_result.retry_counts.values().sum::<u32>() > threshold)
+        .map(|(cluster, _)| cluster.as_str())
+        .collect()
+}
+
+// headline numbers of a scan, for notifications
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FleetSummary {
+    pub cluster_count: usize,
+    pub scanned_cluster_count: usize,
+    pub service_count: usize,
+    pub image_count: usize,
+    pub error_count: usize,
+}
+
+impl FleetSummary {
+    pub fn from_outcome(outcome: &ScanOutcome) -> Self {
+        let scanned: Vec<&ClusterScanResult> = outcome
+            .clusters
+            .values()
+            .filter(|scan_result| scan_result.status == ClusterScanStatus::Scanned)
+            .collect();
+        FleetSummary {
+            cluster_count: outcome.clusters.len(),
+            scanned_cluster_count: scanned.len(),
+            service_count: scanned.iter().map(|r| r.services.len()).sum(),
+            image_count: scanned.iter().map(|r| r.images.len()).sum(),
+            error_count: outcome.errors.len() + outcome.service_errors.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServiceRef {
+    pub cluster: String,
+    pub service_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateDeployment {
+    pub image_name: String,
+    pub clusters: Vec<String>,
+    pub services: Vec<ServiceRef>,
+}
+
+// images deployed with the exact same name in more than one cluster
+pub fn find_duplicate_image_deployments(
+    scan: &BTreeMap<String, ClusterScanResult>,
+) -> Vec<DuplicateDeployment> {
+    let mut deployments: BTreeMap<&str, DuplicateDeployment> = BTreeMap::new();
+    for (cluster, result) in scan {
+        for image in result.images.iter().filter(|image| !image.is_placeholder()) {
+            let deployment =
+                deployments
+                    .entry(&image.image_name)
+                    .or_insert_with(|| DuplicateDeployment {
+                        image_name: image.image_name.clone(),
+                        clusters: Vec::new(),
+                        services: Vec::new(),
+                    });
+            if !deployment.clusters.contains(cluster) {
+                deployment.clusters.push(cluster.clone());
+            }
+            deployment.services.push(ServiceRef {
+                cluster: cluster.clone(),
+                service_name: image.service_name.clone(),
+            });
+        }
+    }
+    deployments
+        .into_values()
+        .filter(|deployment| deployment.clusters.len() > 1)
+        .collect()
+}
+
+pub fn slowest_clusters(
+    results: &BTreeMap<String, ClusterScanResult>,
+    n: usize,
+) -> Vec<(&str, Duration)> {
+    let mut durations: Vec<(&str, Duration)> = results
+        .iter()
+        .map(|(cluster, result)| (cluster.as_str(), result.scan_duration))
+        .collect();
+    durations.sort_by_key(|d| std::cmp::Reverse(d.1));
+    durations.truncate(n);
+    durations
+}
+
+pub fn build_ecs_client(client: Arc<HttpClient>, creds: Credentials) -> EcsClient {
+    let cred_provider = StaticProvider::new(
+        creds.aws_access_key,
+        creds.aws_secret_key,
+        creds.aws_sts_token,
+        None,
+    );
+    EcsClient::new_with(client, cred_provider, Region::EuWest1)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum EndpointVariant {
+    // ecs.{region}.amazonaws.com
+    #[default]
+    Standard,
+    // ecs.{region}.api.aws, IPv4 and IPv6
+    DualStack,
+    // ecs-fips.{region}.amazonaws.com
+    Fips,
+}
+
+#[derive(Debug, Clone)]
+pub struct EcsClientBuilder {
+    pub region: Region,
+    pub endpoint_variant: EndpointVariant,
+}
+
+impl EcsClientBuilder {
+    pub fn new(region: Region) -> Self {
+        EcsClientBuilder {
+            region,
+            endpoint_variant: EndpointVariant::default(),
+        }
+    }
+
+    pub fn endpoint_variant(mut self, endpoint_variant: EndpointVariant) -> Self {
+        self.endpoint_variant = endpoint_variant;
+        self
+    }
+
+    // region carrying the variant endpoint, the standard variant keeps rusoto's resolution
+    pub fn endpoint_region(&self) -> Result<Region> {
+        let name = self.region.name();
+        let endpoint = match self.endpoint_variant {
+            EndpointVariant::Standard => return Ok(self.region.clone()),
+            EndpointVariant::DualStack => format!(
+                "https://ecs.{}.{}",
+                name,
+                arn::Partition::from_region(name).dual_stack_dns_suffix()
+            ),
+            EndpointVariant::Fips if FIPS_REGIONS.contains(&name) => {
+                format!("https://ecs-fips.{}.amazonaws.com", name)
+            }
+            EndpointVariant::Fips => {
+                Err(EcsError::UnsupportedEndpoint(format!("FIPS in {}", name)))?
+            }
+        };
+        Ok(Region::Custom {
+            name: name.to_owned(),
+            endpoint,
+        })
+    }
+
+    pub fn build(&self, client: Arc<HttpClient>, creds: Credentials) -> Result<EcsClient> {
+        let cred_provider = StaticProvider::new(
+            creds.aws_access_key,
+            creds.aws_secret_key,
+            creds.aws_sts_token,
+            None,
+        );
+        Ok(EcsClient::new_with(
+            client,
+            cred_provider,
+            self.endpoint_region()?,
+        ))
+    }
+}
+
+// credentials from the environment, profiles, then container or instance metadata, like the AWS CLI
+pub fn build_ecs_client_with_chain(client: Arc<HttpClient>, region: Region) -> EcsClient {
+    EcsClient::new_with(client, ChainProvider::new(), region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::find_images_with_inactive_task_definition;
+    use crate::image::find_services_by_launch_type;
+    use crate::image::partition_by_scheduling_strategy;
+    use crate::image::service_account_id;
+    use crate::image::DEREGISTERED_IMAGE_NAME;
+    use crate::image::{find_services_in_network_mode, TaskDefinitionMetadata};
+    use crate::image::{EfsConfig, TaskVolume, VolumeMount};
+    use crate::test_helpers::MockEcrClient;
+    use crate::test_helpers::MockIamClient;
+    use crate::test_helpers::{block_on, container, service, task_definition, MockEcsClient};
+    use rusoto_credential::ProvideAwsCredentials;
+    use rusoto_ecs::Deployment;
+    use rusoto_ecs::{
+        ContainerDefinition, EFSVolumeConfiguration, HostVolumeProperties, MountPoint, Volume,
+    };
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const PROD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+    const STAGING_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/staging";
+
+    fn td_arn(name: &str) -> String {
+        format!(
+            "arn:aws:ecs:eu-west-1:123456789012:task-definition/{}",
+            name
+        )
+    }
+
+    fn two_cluster_mock() -> MockEcsClient {
+        MockEcsClient::with_clusters(vec![PROD_ARN, STAGING_ARN])
+            .with_services(PROD_ARN, vec![service("web", &td_arn("web:3"))])
+            .with_services(STAGING_ARN, vec![service("api", &td_arn("api:1"))])
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("api:1"),
+                task_definition(vec![container("api", "api:2")]),
+            )
+    }
+
+    fn scan_outcome(mock: &MockEcsClient, options: &ScanOptions) -> Result<ScanOutcome> {
+        block_on(get_images_of_clusters(&mock.client(), options))
+    }
+
+    fn scan_with_options(
+        mock: &MockEcsClient,
+        filter: &ClusterFilter,
+        options: &ClusterScanOptions,
+    ) -> BTreeMap<String, ClusterScanResult> {
+        let options = ScanOptions {
+            filter: filter.clone(),
+            cluster_options: options.clone(),
+            ..Default::default()
+        };
+        scan_outcome(mock, &options).unwrap().clusters
+    }
+
+    fn scan_with(
+        mock: &MockEcsClient,
+        filter: &ClusterFilter,
+    ) -> BTreeMap<String, ClusterScanResult> {
+        scan_with_options(mock, filter, &ClusterScanOptions::default())
+    }
+
+    fn scan(mock: &MockEcsClient) -> BTreeMap<String, ClusterScanResult> {
+        scan_with(mock, &ClusterFilter::default())
+    }
+
+    fn includes(patterns: &[&str]) -> ClusterFilter {
+        ClusterFilter {
+            cluster_include_patterns: patterns.iter().map(|p| (*p).to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scan_keeps_every_container_of_the_task_definitions() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![service("web", &td_arn("web:3"))])
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![
+                    container("log_router", "amazon/aws-for-fluent-bit:2.10.0"),
+                    container("web", "nginx:1.19"),
+                ]),
+            );
+        let results = scan(&mock);
+
+        let prod = &results[PROD_ARN];
+        assert_eq!(prod.images.len(), 1);
+        assert_eq!(prod.images[0].container_name, "web");
+        let containers: Vec<&str> = prod
+            .containers
+            .iter()
+            .map(|container| container.container_name.as_str())
+            .collect();
+        assert_eq!(containers, vec!["log_router", "web"]);
+        assert_eq!(prod.task_definitions.len(), 1);
+    }
+
+    #[test]
+    fn images_of_every_cluster() {
+        let mock = two_cluster_mock();
+        let results = scan(&mock);
+
+        assert_eq!(results.len(), 2);
+        let prod = &results[PROD_ARN].images;
+        assert_eq!(prod.len(), 1);
+        assert_eq!(prod[0].image_name, "nginx:1.19");
+        assert_eq!(prod[0].service_name, "web");
+        assert_eq!(prod[0].task_definition_name, td_arn("web:3"));
+        assert_eq!(results[STAGING_ARN].images[0].image_name, "api:2");
+
+        assert_eq!(mock.call_count("ListClusters"), 1);
+        assert_eq!(mock.call_count("ListServices"), 2);
+        assert_eq!(mock.call_count("DescribeServices"), 2);
+        assert_eq!(mock.call_count("DescribeTaskDefinition"), 2);
+    }
+
+    #[test]
+    fn cluster_includes_limit_the_scanned_clusters() {
+        let mock = two_cluster_mock();
+        let results = scan_with(&mock, &includes(&["prod"]));
+
+        assert_eq!(results[PROD_ARN].status, ClusterScanStatus::Scanned);
+        assert_eq!(results[STAGING_ARN].status, ClusterScanStatus::Skipped);
+        assert!(results[STAGING_ARN].images.is_empty());
+        assert_eq!(mock.call_count("ListServices"), 1);
+    }
+
+    #[test]
+    fn cluster_without_services_has_no_images() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN]);
+        let results = scan(&mock);
+
+        assert_eq!(results[PROD_ARN].status, ClusterScanStatus::Scanned);
+        assert!(results[PROD_ARN].images.is_empty());
+        assert_eq!(mock.call_count("DescribeServices"), 0);
+    }
+
+    #[test]
+    fn scan_results_report_duration_and_api_calls() {
+        let mock =
+            two_cluster_mock().with_services(PROD_ARN, vec![service("worker", &td_arn("api:1"))]);
+        let results = scan(&mock);
+
+        let prod = &results[PROD_ARN];
+        assert!(prod.scan_duration > Duration::from_secs(0));
+        // ListServices, DescribeServices and one DescribeTaskDefinition per service
+        assert_eq!(prod.api_calls_made, 4);
+        assert_eq!(results[STAGING_ARN].api_calls_made, 3);
+    }
+
+    #[test]
+    fn slowest_clusters_first() {
+        let result = |millis| ClusterScanResult {
+            scan_duration: Duration::from_millis(millis),
+            ..ClusterScanResult::with_status(ClusterScanStatus::Scanned)
+        };
+        let mut results = BTreeMap::new();
+        results.insert("fast".to_owned(), result(10));
+        results.insert("slow".to_owned(), result(300));
+        results.insert("medium".to_owned(), result(50));
+
+        assert_eq!(
+            slowest_clusters(&results, 2),
+            vec![
+                ("slow", Duration::from_millis(300)),
+                ("medium", Duration::from_millis(50))
+            ]
+        );
+    }
+
+    const PROD_EU_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/production-eu";
+    const PROD_LEGACY_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/production-legacy";
+
+    fn compiled(include: &[&str], exclude: &[&str]) -> CompiledClusterFilter {
+        ClusterFilter {
+            cluster_include_patterns: include.iter().map(|p| (*p).to_owned()).collect(),
+            cluster_exclude_patterns: exclude.iter().map(|p| (*p).to_owned()).collect(),
+        }
+        .compile()
+        .unwrap()
+    }
+
+    #[test]
+    fn empty_cluster_filter_matches_everything() {
+        let filter = compiled(&[], &[]);
+        assert!(filter.matches(PROD_ARN));
+        assert!(filter.matches(STAGING_ARN));
+    }
+
+    #[test]
+    fn include_pattern_matches_production_clusters_only() {
+        let filter = compiled(&["production-"], &[]);
+        assert!(filter.matches(PROD_EU_ARN));
+        assert!(filter.matches(PROD_LEGACY_ARN));
+        assert!(!filter.matches(PROD_ARN));
+        assert!(!filter.matches(STAGING_ARN));
+    }
+
+    #[test]
+    fn exclude_overrides_include() {
+        let filter = compiled(&["production-"], &["legacy$"]);
+        assert!(filter.matches(PROD_EU_ARN));
+        assert!(!filter.matches(PROD_LEGACY_ARN));
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let result = ClusterFilter {
+            cluster_include_patterns: vec!["production-(".to_owned()],
+            ..Default::default()
+        }
+        .compile();
+        match result.err().and_then(|e| e.downcast::<EcsError>().ok()) {
+            Some(EcsError::InvalidFilterPattern(pattern)) => assert_eq!(pattern, "production-("),
+            other => panic!("expected InvalidFilterPattern, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn excluded_clusters_are_not_scanned() {
+        let mock = two_cluster_mock();
+        let filter = ClusterFilter {
+            cluster_exclude_patterns: vec!["staging".to_owned()],
+            ..Default::default()
+        };
+        let results = scan_with(&mock, &filter);
+
+        assert_eq!(results[PROD_ARN].status, ClusterScanStatus::Scanned);
+        assert_eq!(results[STAGING_ARN].status, ClusterScanStatus::Skipped);
+        assert_eq!(mock.call_count("ListServices"), 1);
+    }
+
+    #[test]
+    fn describe_services_is_called_with_at_most_10_services() {
+        let services = (0..25)
+            .map(|i| service(&format!("service-{}", i), &td_arn("web:3")))
+            .collect();
+        let mock = two_cluster_mock().with_services(PROD_ARN, services);
+        let service_arns: Vec<String> = (0..25)
+            .map(|i| {
+                format!(
+                    "arn:aws:ecs:eu-west-1:123456789012:service/prod/service-{}",
+                    i
+                )
+            })
+            .collect();
+
+        let recorder = ApiRecorder::default();
+        let scanned = block_on(get_images_of_services(
+            &mock.client(),
+            service_arns,
+            PROD_ARN.to_owned(),
+            &ClusterScanOptions::default(),
+            &recorder,
+        ))
+        .unwrap();
+
+        assert_eq!(scanned.services.len(), 25);
+        assert_eq!(scanned.images.len(), 25);
+        assert!(scanned.service_errors.is_empty());
+        let chunk_sizes: Vec<usize> = mock
+            .requests("DescribeServices")
+            .iter()
+            .map(|request| request["services"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(chunk_sizes, vec![10, 10, 5]);
+        assert_eq!(mock.call_count("DescribeServices"), 3);
+        // the services of every chunk still get their task definition described
+        assert_eq!(mock.call_count("DescribeTaskDefinition"), 25);
+    }
+
+    fn cluster_arn(i: usize) -> String {
+        format!(
+            "arn:aws:ecs:eu-west-1:123456789012:cluster/cluster-{:03}",
+            i
+        )
+    }
+
+    #[test]
+    fn cluster_details_are_described_in_batches_of_100() {
+        let arns: Vec<String> = (0..250).map(cluster_arn).collect();
+        let mock = MockEcsClient::with_clusters(arns.iter().map(String::as_str).collect());
+
+        let details = block_on(get_cluster_details(&mock.client())).unwrap();
+
+        assert_eq!(details.len(), 250);
+        assert_eq!(details[0].cluster_arn, arns[0]);
+        assert_eq!(details[249].cluster_name, "cluster-249");
+        let batch_sizes: Vec<usize> = mock
+            .requests("DescribeClusters")
+            .iter()
+            .map(|request| request["clusters"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(batch_sizes, vec![100, 100, 50]);
+        // one ListClusters pagination, three pages of at most 100 ARNs
+        assert_eq!(mock.call_count("ListClusters"), 3);
+    }
+
+    #[test]
+    fn cluster_details_carry_counts_and_tags() {
+        let mock = MockEcsClient::default()
+            .with_cluster(Cluster {
+                cluster_arn: Some(PROD_ARN.to_owned()),
+                cluster_name: Some("prod".to_owned()),
+                status: Some("ACTIVE".to_owned()),
+                running_tasks_count: Some(4),
+                active_services_count: Some(2),
+                tags: Some(vec![Tag {
+                    key: Some("Environment".to_owned()),
+                    value: Some("production".to_owned()),
+                }]),
+                ..Default::default()
+            })
+            .with_cluster(Cluster {
+                cluster_arn: Some(STAGING_ARN.to_owned()),
+                ..Default::default()
+            });
+
+        let details = block_on(get_cluster_details(&mock.client())).unwrap();
+
+        assert_eq!(details.len(), 2);
+        assert_eq!(details[0].cluster_name, "prod");
+        assert_eq!(details[0].status.as_deref(), Some("ACTIVE"));
+        assert_eq!(details[0].running_tasks_count, 4);
+        assert_eq!(details[0].active_services_count, 2);
+        assert_eq!(details[0].pending_tasks_count, 0);
+        assert_eq!(
+            details[0].tags.get("Environment").map(String::as_str),
+            Some("production")
+        );
+        // the name falls back to the last ARN segment
+        assert_eq!(details[1].cluster_name, "staging");
+        assert!(details[1].tags.is_empty());
+        assert_eq!(
+            mock.requests("DescribeClusters")[0]["include"],
+            json!(["TAGS"])
+        );
+    }
+
+    fn daemon_service(name: &str, task_definition_arn: &str) -> Service {
+        Service {
+            scheduling_strategy: Some("DAEMON".to_owned()),
+            ..service(name, task_definition_arn)
+        }
+    }
+
+    fn replica_and_daemon_mock() -> MockEcsClient {
+        let replica = Service {
+            scheduling_strategy: Some("REPLICA".to_owned()),
+            ..service("web", &td_arn("web:3"))
+        };
+        MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(
+                PROD_ARN,
+                vec![replica, daemon_service("agent", &td_arn("agent:1"))],
+            )
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("agent:1"),
+                task_definition(vec![container("agent", "datadog/agent:7")]),
+            )
+    }
+
+    #[test]
+    fn daemon_strategy_returns_daemon_services_only() {
+        let mock = replica_and_daemon_mock();
+        let options = ClusterScanOptions {
+            scheduling_strategy: Some(SchedulingStrategy::Daemon),
+            ..Default::default()
+        };
+        let results = scan_with_options(&mock, &ClusterFilter::default(), &options);
+
+        let prod = &results[PROD_ARN];
+        let service_names: Vec<&str> = prod
+            .services
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+        assert_eq!(service_names, vec!["agent"]);
+        assert!(prod.services[0].is_daemon());
+        assert_eq!(prod.images.len(), 1);
+        assert_eq!(prod.images[0].image_name, "datadog/agent:7");
+        assert_eq!(
+            mock.requests("ListServices")[0]["schedulingStrategy"],
+            "DAEMON"
+        );
+    }
+
+    #[test]
+    fn no_strategy_returns_every_service() {
+        let results = scan(&replica_and_daemon_mock());
+
+        let (replicas, daemons) = partition_by_scheduling_strategy(&results[PROD_ARN].services);
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas[0].service_name, "web");
+        assert_eq!(daemons.len(), 1);
+        assert_eq!(daemons[0].service_name, "agent");
+    }
+
+    fn mixed_launch_type_mock() -> MockEcsClient {
+        let on_ec2 = Service {
+            launch_type: Some("EC2".to_owned()),
+            ..service("web", &td_arn("web:3"))
+        };
+        let on_fargate = Service {
+            launch_type: Some("FARGATE".to_owned()),
+            ..service("api", &td_arn("api:7"))
+        };
+        MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![on_ec2, on_fargate])
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("api:7"),
+                task_definition(vec![container("api", "api:2.0")]),
+            )
+    }
+
+    #[test]
+    fn fargate_launch_type_excludes_ec2_services() {
+        let mock = mixed_launch_type_mock();
+        let options = ClusterScanOptions {
+            launch_type: Some(LaunchType::Fargate),
+            ..Default::default()
+        };
+        let results = scan_with_options(&mock, &ClusterFilter::default(), &options);
+
+        let prod = &results[PROD_ARN];
+        let service_names: Vec<&str> = prod
+            .services
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+        assert_eq!(service_names, vec!["api"]);
+        assert_eq!(prod.images.len(), 1);
+        assert_eq!(prod.images[0].image_name, "api:2.0");
+    }
+
+    #[test]
+    fn unset_launch_type_returns_every_service() {
+        let results = scan(&mixed_launch_type_mock());
+
+        let services = &results[PROD_ARN].services;
+        assert_eq!(services.len(), 2);
+        let on_fargate = find_services_by_launch_type(services, LaunchType::Fargate);
+        assert_eq!(on_fargate.len(), 1);
+        assert_eq!(on_fargate[0].service_name, "api");
+        let on_ec2 = find_services_by_launch_type(services, LaunchType::Ec2);
+        assert_eq!(on_ec2.len(), 1);
+        assert_eq!(on_ec2[0].service_name, "web");
+    }
+
+    /// what the cli prints for each cluster, without the timing of the scan
+    fn scanned_images(results: &BTreeMap<String, ClusterScanResult>) -> String {
+        type Scanned<'a> = (&'a Vec<ServiceImage>, &'a Vec<Image>, &'a Vec<Image>);
+        let images: BTreeMap<&String, Scanned> = results
+            .iter()
+            .map(|(cluster, result)| {
+                let scanned = (&result.services, &result.images, &result.containers);
+                (cluster, scanned)
+            })
+            .collect();
+        serde_json::to_string(&images).unwrap()
+    }
+
+    #[test]
+    fn two_scans_serialize_identically() {
+        let worker = || service("worker", &td_arn("api:1"));
+        let admin = || service("admin", &td_arn("web:3"));
+        let first = scan(&two_cluster_mock().with_services(PROD_ARN, vec![worker(), admin()]));
+        // an unchanged cluster whose services come back from the api in another order
+        let second = scan(&two_cluster_mock().with_services(PROD_ARN, vec![admin(), worker()]));
+
+        assert_eq!(scanned_images(&first), scanned_images(&second));
+    }
+
+    // each map gets its own hash seed, any HashMap left in the output reorders its keys
+    fn labelled_and_tagged_mock() -> MockEcsClient {
+        let pairs = |n: usize| -> Vec<(String, String)> {
+            (0..n)
+                .map(|i| (format!("key-{}", i), format!("value-{}", i)))
+                .collect()
+        };
+        let tags = |n: usize| -> Vec<Tag> {
+            pairs(n)
+                .into_iter()
+                .map(|(key, value)| Tag {
+                    key: Some(key),
+                    value: Some(value),
+                })
+                .collect()
+        };
+        let labelled = ContainerDefinition {
+            docker_labels: Some(pairs(16).into_iter().collect()),
+            ..container("web", "nginx:1.19")
+        };
+        let meshed = TaskDefinition {
+            proxy_configuration: Some(rusoto_ecs::ProxyConfiguration {
+                type_: Some("APPMESH".to_owned()),
+                container_name: "envoy".to_owned(),
+                properties: Some(
+                    pairs(16)
+                        .into_iter()
+                        .map(|(name, value)| rusoto_ecs::KeyValuePair {
+                            name: Some(name),
+                            value: Some(value),
+                        })
+                        .collect(),
+                ),
+            }),
+            ..task_definition(vec![container("envoy", "envoy:1.16"), labelled])
+        };
+        let web = Service {
+            tags: Some(tags(16)),
+            ..service("web", &td_arn("web:3"))
+        };
+        MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![web])
+            .with_tagged_task_definition(&td_arn("web:3"), meshed, tags(16))
+    }
+
+    #[test]
+    fn two_scans_with_labels_and_tags_serialize_identically() {
+        let first = scan(&labelled_and_tagged_mock());
+        let second = scan(&labelled_and_tagged_mock());
+
+        let image = &first[PROD_ARN].images[0];
+        assert_eq!(image.labels.len(), 16);
+        assert_eq!(image.task_definition_tags.len(), 16);
+        assert_eq!(first[PROD_ARN].services[0].service_tags.len(), 16);
+        assert_eq!(scanned_images(&first), scanned_images(&second));
+    }
+
+    #[test]
+    fn cluster_images_sorted_by_service_container_and_image() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(
+                PROD_ARN,
+                vec![
+                    service("worker", &td_arn("web:3")),
+                    service("api", &td_arn("api:1")),
+                    service("admin", &td_arn("web:3")),
+                ],
+            )
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("api:1"),
+                task_definition(vec![container("api", "api:2.0")]),
+            );
+        let results = scan(&mock);
+
+        let keys: Vec<(&str, &str, &str)> = results[PROD_ARN]
+            .images
+            .iter()
+            .map(Image::sort_key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("admin", "web", "nginx:1.19"),
+                ("api", "api", "api:2.0"),
+                ("worker", "web", "nginx:1.19")
+            ]
+        );
+        let service_names: Vec<&str> = results[PROD_ARN]
+            .services
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+        assert_eq!(service_names, vec!["admin", "api", "worker"]);
+    }
+
+    #[test]
+    fn image_in_two_clusters_is_one_duplicate() {
+        let mock =
+            two_cluster_mock().with_services(PROD_ARN, vec![service("worker", &td_arn("api:1"))]);
+        let duplicates = find_duplicate_image_deployments(&scan(&mock));
+
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].image_name, "api:2");
+        assert_eq!(duplicates[0].clusters, vec![PROD_ARN, STAGING_ARN]);
+        assert_eq!(
+            duplicates[0].services,
+            vec![
+                ServiceRef {
+                    cluster: PROD_ARN.to_owned(),
+                    service_name: "worker".to_owned()
+                },
+                ServiceRef {
+                    cluster: STAGING_ARN.to_owned(),
+                    service_name: "api".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn image_in_one_cluster_is_not_a_duplicate() {
+        assert!(find_duplicate_image_deployments(&scan(&two_cluster_mock())).is_empty());
+    }
+
+    fn on_throttle(strategy: ThrottleStrategy) -> ScanOptions {
+        ScanOptions {
+            on_throttle: strategy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn abort_all_fails_the_scan_on_throttling() {
+        let mock = two_cluster_mock().fail_next("ListServices", 1, "ThrottlingException");
+
+        let err = scan_outcome(&mock, &on_throttle(ThrottleStrategy::AbortAll)).unwrap_err();
+        assert!(error::is_throttled(&err));
+    }
+
+    #[test]
+    fn skip_cluster_records_one_error() {
+        let mock = two_cluster_mock().fail_next("ListServices", 1, "ThrottlingException");
+        let outcome = scan_outcome(&mock, &on_throttle(ThrottleStrategy::SkipCluster)).unwrap();
+
+        assert_eq!(outcome.errors.len(), 1);
+        let throttled = &outcome.errors[0].cluster;
+        assert!(matches!(
+            outcome.clusters[throttled].status,
+            ClusterScanStatus::Error(_)
+        ));
+        let scanned: Vec<&String> = outcome
+            .clusters
+            .iter()
+            .filter(|(_, result)| result.status == ClusterScanStatus::Scanned)
+            .map(|(cluster, _)| cluster)
+            .collect();
+        assert_eq!(scanned.len(), 1);
+        assert_ne!(scanned[0], throttled);
+    }
+
+    #[test]
+    fn wait_and_retry_recovers_from_one_throttle() {
+        let mock = two_cluster_mock().fail_next("ListServices", 1, "ThrottlingException");
+        let options = on_throttle(ThrottleStrategy::WaitAndRetry(Duration::from_millis(10)));
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.clusters[PROD_ARN].images.len(), 1);
+        assert_eq!(outcome.clusters[STAGING_ARN].images.len(), 1);
+        // two clusters and the retry of the throttled one
+        assert_eq!(mock.call_count("ListServices"), 3);
+    }
+
+    #[test]
+    fn images_carry_the_short_cluster_name() {
+        let results = scan(&two_cluster_mock());
+
+        assert_eq!(results[PROD_ARN].images[0].cluster_name, "prod");
+        assert_eq!(results[PROD_ARN].images[0].cluster_arn, PROD_ARN);
+        assert_eq!(results[STAGING_ARN].images[0].cluster_name, "staging");
+    }
+
+    #[test]
+    fn latency_is_recorded_under_rusoto_method_names() {
+        let outcome = scan_outcome(&two_cluster_mock(), &ScanOptions::default()).unwrap();
+
+        let mut operations: Vec<(&str, &str, u32)> = outcome
+            .latency
+            .operations
+            .iter()
+            .map(|op| {
+                (
+                    op.operation_name.as_str(),
+                    op.cluster.as_str(),
+                    op.call_count,
+                )
+            })
+            .collect();
+        operations.sort_unstable();
+        assert_eq!(
+            operations,
+            vec![
+                ("describe_services", PROD_ARN, 1),
+                ("describe_services", STAGING_ARN, 1),
+                ("describe_task_definition", PROD_ARN, 1),
+                ("describe_task_definition", STAGING_ARN, 1),
+                ("list_clusters", "", 1),
+                ("list_services", PROD_ARN, 1),
+                ("list_services", STAGING_ARN, 1),
+            ]
+        );
+        assert_eq!(outcome.clusters[PROD_ARN].api_latency.len(), 3);
+    }
+
+    const ECR_WEB: &str = "123456789012.dkr.ecr.eu-west-1.amazonaws.com/web";
+
+    #[test]
+    fn scan_resolves_ecr_images_to_their_digest() {
+        let mock = two_cluster_mock().with_task_definition(
+            &td_arn("web:3"),
+            task_definition(vec![container("web", &format!("{}:1.19", ECR_WEB))]),
+        );
+        let ecr = MockEcrClient::default().with_image("web", "1.19", "sha256:abc");
+        let options = ScanOptions {
+            enrich_with_ecr_details: Some(ecr.clients()),
+            ..Default::default()
+        };
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        let prod_image = &outcome.clusters[PROD_ARN].images[0];
+        assert_eq!(
+            prod_image.enriched_image_name,
+            Some(format!("{}@sha256:abc", ECR_WEB))
+        );
+        let staging_image = &outcome.clusters[STAGING_ARN].images[0];
+        assert_eq!(staging_image.image_name, "api:2");
+        assert_eq!(staging_image.enriched_image_name, None);
+        assert_eq!(ecr.call_count("DescribeImages"), 1);
+    }
+
+    #[test]
+    fn denied_ecr_lookup_does_not_fail_the_scan() {
+        let mock = two_cluster_mock().with_task_definition(
+            &td_arn("web:3"),
+            task_definition(vec![container("web", &format!("{}:1.19", ECR_WEB))]),
+        );
+        let ecr = MockEcrClient::default().fail_next("DescribeImages", 1, "AccessDeniedException");
+        let options = ScanOptions {
+            enrich_with_ecr_details: Some(ecr.clients()),
+            ..Default::default()
+        };
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert!(outcome.errors.is_empty());
+        let prod_image = &outcome.clusters[PROD_ARN].images[0];
+        assert_eq!(prod_image.enriched_image_name, None);
+        assert_eq!(outcome.clusters[STAGING_ARN].images.len(), 1);
+    }
+
+    #[test]
+    fn scan_without_ecr_client_leaves_images_unchanged() {
+        let outcome = scan_outcome(&two_cluster_mock(), &ScanOptions::default()).unwrap();
+        assert!(outcome
+            .clusters
+            .values()
+            .flat_map(|result| &result.images)
+            .all(|image| image.enriched_image_name.is_none()));
+    }
+
+    fn with_max_results(max_results: i64) -> ScanOptions {
+        ScanOptions {
+            cluster_options: ClusterScanOptions {
+                max_results: Some(max_results),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn max_results_pages_list_clusters_and_list_services() {
+        let clusters: Vec<String> = (0..5)
+            .map(|i| format!("arn:aws:ecs:eu-west-1:123456789012:cluster/c-{}", i))
+            .collect();
+        let mut mock = MockEcsClient::with_clusters(clusters.iter().map(String::as_str).collect())
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            );
+        let services = (0..5)
+            .map(|i| service(&format!("web-{}", i), &td_arn("web:3")))
+            .collect();
+        mock = mock.with_services(&clusters[0], services);
+        let outcome = scan_outcome(&mock, &with_max_results(2)).unwrap();
+
+        assert_eq!(outcome.clusters.len(), 5);
+        assert_eq!(mock.call_count("ListClusters"), 3);
+        assert!(mock
+            .requests("ListClusters")
+            .iter()
+            .all(|request| request["maxResults"] == 2));
+        let first_cluster_pages = mock
+            .requests("ListServices")
+            .iter()
+            .filter(|request| request["cluster"] == clusters[0].as_str())
+            .count();
+        assert_eq!(first_cluster_pages, 3);
+        assert_eq!(outcome.clusters[&clusters[0]].images.len(), 5);
+    }
+
+    #[test]
+    fn max_results_outside_1_to_100_is_rejected() {
+        for max_results in &[0, 101] {
+            let mock = two_cluster_mock();
+            let err = scan_outcome(&mock, &with_max_results(*max_results)).unwrap_err();
+            assert!(
+                matches!(err.downcast_ref::<EcsError>(), Some(EcsError::InvalidMaxResults(m)) if m == max_results)
+            );
+            assert!(mock.calls().is_empty());
+        }
+    }
+
+    #[test]
+    fn streamed_images_match_the_outcome() {
+        let mock =
+            two_cluster_mock().with_services(PROD_ARN, vec![service("worker", &td_arn("api:1"))]);
+        let streamed: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+        let outcome = block_on(stream_images_of_clusters(
+            &mock.client(),
+            &ScanOptions::default(),
+            |cluster, image| {
+                streamed.lock().unwrap().push((cluster, image.image_name));
+                async {}
+            },
+        ))
+        .unwrap();
+
+        let mut streamed = streamed.into_inner().unwrap();
+        streamed.sort();
+        let mut scanned: Vec<(String, String)> = outcome
+            .clusters
+            .iter()
+            .flat_map(|(cluster, result)| {
+                result
+                    .images
+                    .iter()
+                    .map(move |image| (cluster.clone(), image.image_name.clone()))
+            })
+            .collect();
+        scanned.sort();
+        assert_eq!(scanned.len(), 3);
+        assert_eq!(streamed, scanned);
+    }
+
+    // clusters c-0 to c-4, each running one service
+    fn five_cluster_mock() -> MockEcsClient {
+        let clusters: Vec<String> = (0..5)
+            .map(|i| format!("arn:aws:ecs:eu-west-1:123456789012:cluster/c-{}", i))
+            .collect();
+        let mut mock = MockEcsClient::with_clusters(clusters.iter().map(String::as_str).collect())
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            );
+        for cluster in &clusters {
+            mock = mock.with_services(cluster, vec![service("web", &td_arn("web:3"))]);
+        }
+        mock
+    }
+
+    #[test]
+    fn bounded_channel_holds_back_the_scan() {
+        let mock = five_cluster_mock();
+        let (scan, receiver) =
+            scan_images_channel(Arc::new(mock.client()), ScanOptions::default(), 1);
+        let consume = async {
+            let mut received = 0;
+            while receiver.recv().await.is_ok() {
+                received += 1;
+                // the image just received, one in the buffer and one waiting to be sent
+                assert!(mock.call_count("ListServices") <= received + 2);
+            }
+            received
+        };
+        let (scanned, received) = block_on(futures::future::join(scan, consume));
+
+        scanned.unwrap();
+        assert_eq!(received, 5);
+    }
+
+    #[test]
+    fn dropping_the_receiver_stops_the_scan() {
+        let mock = five_cluster_mock();
+        let (scan, receiver) =
+            scan_images_channel(Arc::new(mock.client()), ScanOptions::default(), 1);
+        let take_one = async move { receiver.recv().await.unwrap() };
+        let (scanned, first) = block_on(futures::future::join(scan, take_one));
+
+        scanned.unwrap();
+        assert_eq!(first.image_name, "nginx:1.19");
+        assert!(mock.call_count("ListServices") < 5);
+    }
+
+    #[test]
+    fn preflight_check_stops_the_scan_before_listing_clusters() {
+        let mock = two_cluster_mock();
+        let iam = MockIamClient::denying(vec!["ecs:DescribeServices"]);
+        let options = ScanOptions {
+            preflight_check: Some(PermissionsCheck {
+                iam_client: Arc::new(iam.client()),
+                principal_arn: "arn:aws:iam::123456789012:role/ecs-reader".to_owned(),
+            }),
+            ..Default::default()
+        };
+        let err = scan_outcome(&mock, &options).unwrap_err();
+
+        match err.downcast_ref::<EcsError>() {
+            Some(EcsError::InsufficientPermissions(denied)) => {
+                assert_eq!(denied, &vec!["ecs:DescribeServices"])
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn scan_runs_the_processors_on_each_cluster() {
+        let options = ScanOptions {
+            processors: vec![Arc::new(processor::LatestTagWarningProcessor)],
+            ..Default::default()
+        };
+        let mock = two_cluster_mock().with_task_definition(
+            &td_arn("web:3"),
+            task_definition(vec![container("web", "nginx:latest")]),
+        );
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert_eq!(outcome.clusters[PROD_ARN].images[0].warnings.len(), 1);
+        assert!(outcome.clusters[STAGING_ARN].images[0].warnings.is_empty());
+    }
+
+    fn draining_mock() -> MockEcsClient {
+        let draining = Service {
+            status: Some("DRAINING".to_owned()),
+            ..service("legacy", &td_arn("api:1"))
+        };
+        two_cluster_mock().with_services(PROD_ARN, vec![draining])
+    }
+
+    fn prod_service_names(options: &ClusterScanOptions) -> Vec<String> {
+        let results = scan_with_options(&draining_mock(), &ClusterFilter::default(), options);
+        results[PROD_ARN]
+            .images
+            .iter()
+            .map(|image| image.service_name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn draining_services_are_excluded_by_default() {
+        assert!(ClusterScanOptions::default().exclude_draining);
+        assert_eq!(
+            prod_service_names(&ClusterScanOptions::default()),
+            vec!["web"]
+        );
+    }
+
+    #[test]
+    fn draining_services_are_kept_when_not_excluded() {
+        let options = ClusterScanOptions {
+            exclude_draining: false,
+            ..Default::default()
+        };
+        assert_eq!(prod_service_names(&options), vec!["legacy", "web"]);
+    }
+
+    #[test]
+    fn find_draining_services_of_a_cluster() {
+        let draining =
+            block_on(find_draining_services(&draining_mock().client(), PROD_ARN)).unwrap();
+        assert_eq!(
+            draining,
+            vec!["arn:aws:ecs:eu-west-1:123456789012:service/prod/legacy"]
+        );
+    }
+
+    #[test]
+    fn failing_service_does_not_stop_the_cluster_scan() {
+        // the task definition of broken is missing, DescribeTaskDefinition fails for it
+        let mock = two_cluster_mock().with_services(
+            PROD_ARN,
+            vec![
+                service("broken", &td_arn("deleted:1")),
+                service("worker", &td_arn("api:1")),
+            ],
+        );
+        let outcome = scan_outcome(&mock, &ScanOptions::default()).unwrap();
+
+        let prod = &outcome.clusters[PROD_ARN];
+        assert_eq!(prod.status, ClusterScanStatus::Scanned);
+        let service_names: Vec<&str> = prod
+            .images
+            .iter()
+            .map(|image| image.service_name.as_str())
+            .collect();
+        assert_eq!(service_names, vec!["web", "worker"]);
+        assert_eq!(prod.service_errors.len(), 1);
+        assert_eq!(prod.service_errors[0].service_name, "broken");
+        assert_eq!(prod.service_errors[0].cluster, PROD_ARN);
+
+        assert_eq!(outcome.service_errors.len(), 1);
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.clusters[STAGING_ARN].images.len(), 1);
+    }
+
+    #[test]
+    fn deregistered_task_definition_gives_a_placeholder_image() {
+        let inactive = TaskDefinition {
+            status: Some("INACTIVE".to_owned()),
+            ..task_definition(vec![container("api", "api:1")])
+        };
+        let mock = two_cluster_mock()
+            .with_services(PROD_ARN, vec![service("legacy", &td_arn("api:0"))])
+            .with_task_definition(&td_arn("api:0"), inactive);
+        let results = scan(&mock);
+
+        let images = &results[PROD_ARN].images;
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].service_name, "legacy");
+        assert_eq!(images[0].image_name, DEREGISTERED_IMAGE_NAME);
+        assert_eq!(images[0].task_definition_name, td_arn("api:0"));
+        // the active definition still resolves to its container image
+        assert_eq!(images[1].image_name, "nginx:1.19");
+        assert!(results[PROD_ARN].service_errors.is_empty());
+        assert!(mock
+            .requests("DescribeTaskDefinition")
+            .iter()
+            .all(|request| request["include"] == json!(["TAGS"])));
+    }
+
+    #[test]
+    fn placeholders_in_two_clusters_are_not_duplicates() {
+        let inactive = TaskDefinition {
+            status: Some("INACTIVE".to_owned()),
+            ..task_definition(vec![container("api", "api:1")])
+        };
+        let mock = two_cluster_mock()
+            .with_services(PROD_ARN, vec![service("legacy", &td_arn("api:0"))])
+            .with_services(STAGING_ARN, vec![service("legacy", &td_arn("api:0"))])
+            .with_task_definition(&td_arn("api:0"), inactive);
+        let results = scan(&mock);
+
+        assert!(results[STAGING_ARN]
+            .images
+            .iter()
+            .any(|image| image.is_placeholder()));
+        assert!(find_duplicate_image_deployments(&results).is_empty());
+    }
+
+    #[test]
+    fn task_definition_status_names() {
+        assert_eq!(
+            TaskDefinitionStatus::from_status("ACTIVE"),
+            Some(TaskDefinitionStatus::Active)
+        );
+        assert_eq!(
+            TaskDefinitionStatus::from_status("INACTIVE"),
+            Some(TaskDefinitionStatus::Inactive)
+        );
+        assert_eq!(
+            TaskDefinitionStatus::from_status("DELETE_IN_PROGRESS"),
+            Some(TaskDefinitionStatus::DeleteInProgress)
+        );
+        assert_eq!(TaskDefinitionStatus::from_status("UNKNOWN"), None);
+    }
+
+    #[test]
+    fn chain_provider_picks_up_environment_credentials() {
+        std::env::set_var("AWS_ACCESS_KEY_ID", "AKIAENVIRONMENT");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "environment-secret");
+        let creds = block_on(ChainProvider::new().credentials());
+        std::env::remove_var("AWS_ACCESS_KEY_ID");
+        std::env::remove_var("AWS_SECRET_ACCESS_KEY");
+
+        let creds = creds.unwrap();
+        assert_eq!(creds.aws_access_key_id(), "AKIAENVIRONMENT");
+        assert_eq!(creds.aws_secret_access_key(), "environment-secret");
+        // credentials are only resolved on the first request
+        build_ecs_client_with_chain(
+            Arc::new(crate::client::new_client().unwrap()),
+            Region::EuWest1,
+        );
+    }
+
+    // Records the name, parent and cluster_count of every span, like an in-memory collector
+    #[derive(Default)]
+    struct SpanLog {
+        // (metadata, parent id, cluster_count), the id of a span is its index + 1
+        spans: Vec<(
+            &'static tracing::Metadata<'static>,
+            Option<u64>,
+            Option<u64>,
+        )>,
+        entered: Vec<u64>,
+    }
+
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<std::sync::Mutex<SpanLog>>);
+
+    struct ClusterCount(Option<u64>);
+
+    impl tracing::field::Visit for ClusterCount {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            if field.name() == "cluster_count" {
+                self.0 = Some(value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "cluster_count" {
+                self.0 = format!("{:?}", value).parse().ok();
+            }
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut log = self.0.lock().unwrap();
+            let parent = if attrs.is_contextual() {
+                log.entered.last().copied()
+            } else {
+                attrs.parent().map(tracing::span::Id::into_u64)
+            };
+            log.spans.push((attrs.metadata(), parent, None));
+            tracing::span::Id::from_u64(log.spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut count = ClusterCount(None);
+            values.record(&mut count);
+            if count.0.is_some() {
+                self.0.lock().unwrap().spans[span.into_u64() as usize - 1].2 = count.0;
+            }
+        }
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, _event: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.push(span.into_u64());
+        }
+
+        fn exit(&self, _span: &tracing::span::Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+
+        // Span::current() relies on it to record cluster_count
+        fn current_span(&self) -> tracing_core::span::Current {
+            let log = self.0.lock().unwrap();
+            match log.entered.last() {
+                Some(id) => tracing_core::span::Current::new(
+                    tracing::span::Id::from_u64(*id),
+                    log.spans[*id as usize - 1].0,
+                ),
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    impl SpanLog {
+        // name of the outermost span above the given one, i.e. its trace
+        fn root_of(&self, mut id: u64) -> &'static str {
+            while let Some(parent) = self.spans[id as usize - 1].1 {
+                id = parent;
+            }
+            self.spans[id as usize - 1].0.name()
+        }
+    }
+
+    #[test]
+    fn cluster_scan_spans_belong_to_the_callers_trace() {
+        let recorder = SpanRecorder::default();
+        let mock = two_cluster_mock();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let job = tracing::info_span!("job");
+            block_on(
+                get_images_of_clusters(&mock.client(), &ScanOptions::default()).instrument(job),
+            )
+            .unwrap();
+        });
+
+        let log = recorder.0.lock().unwrap();
+        let ids = |name: &str| -> Vec<u64> {
+            (1..=log.spans.len() as u64)
+                .filter(|id| log.spans[*id as usize - 1].0.name() == name)
+                .collect()
+        };
+        let cluster_scans = ids("scan_cluster");
+        assert_eq!(cluster_scans.len(), 2);
+        assert!(cluster_scans.iter().all(|id| log.root_of(*id) == "job"));
+        let scan = ids("get_images_of_clusters");
+        assert_eq!(scan.len(), 1);
+        assert_eq!(log.spans[scan[0] as usize - 1].2, Some(2));
+    }
+
+    fn volume(name: &str) -> Volume {
+        Volume {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn mounted(source_volume: &str, container_path: &str, read_only: bool) -> MountPoint {
+        MountPoint {
+            source_volume: Some(source_volume.to_owned()),
+            container_path: Some(container_path.to_owned()),
+            read_only: Some(read_only),
+        }
+    }
+
+    #[test]
+    fn host_path_and_efs_volumes_are_captured() {
+        let web = ContainerDefinition {
+            mount_points: Some(vec![
+                mounted("docker", "/var/run/docker.sock", true),
+                mounted("shared", "/data", false),
+            ]),
+            ..container("web", "nginx:1.19")
+        };
+        let td = TaskDefinition {
+            volumes: Some(vec![
+                Volume {
+                    host: Some(HostVolumeProperties {
+                        source_path: Some("/var/run/docker.sock".to_owned()),
+                    }),
+                    ..volume("docker")
+                },
+                Volume {
+                    efs_volume_configuration: Some(EFSVolumeConfiguration {
+                        file_system_id: "fs-12345678".to_owned(),
+                        root_directory: Some("/shared".to_owned()),
+                        ..Default::default()
+                    }),
+                    ..volume("shared")
+                },
+            ]),
+            ..task_definition(vec![web])
+        };
+        let results = scan(&two_cluster_mock().with_task_definition(&td_arn("web:3"), td));
+
+        let prod = &results[PROD_ARN];
+        assert_eq!(
+            prod.images[0].volume_mounts,
+            vec![
+                VolumeMount {
+                    source_volume: "docker".to_owned(),
+                    container_path: "/var/run/docker.sock".to_owned(),
+                    read_only: true,
+                },
+                VolumeMount {
+                    source_volume: "shared".to_owned(),
+                    container_path: "/data".to_owned(),
+                    read_only: false
+                },
+            ]
+        );
+        assert_eq!(
+            prod.services[0]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .volumes,
+            vec![
+                TaskVolume {
+                    name: "docker".to_owned(),
+                    host_path: Some("/var/run/docker.sock".to_owned()),
+                    efs_volume_configuration: None,
+                },
+                TaskVolume {
+                    name: "shared".to_owned(),
+                    host_path: None,
+                    efs_volume_configuration: Some(EfsConfig {
+                        file_system_id: "fs-12345678".to_owned(),
+                        root_directory: Some("/shared".to_owned()),
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn task_without_volumes_has_no_mounts() {
+        let results = scan(&two_cluster_mock());
+
+        let staging = &results[STAGING_ARN];
+        assert!(staging.images[0].volume_mounts.is_empty());
+        assert!(staging.services[0]
+            .task_definition_metadata
+            .as_ref()
+            .unwrap()
+            .volumes
+            .is_empty());
+    }
+
+    #[test]
+    fn external_instances_are_the_ssm_managed_ones() {
+        let instance_arn = |id: &str| {
+            format!(
+                "arn:aws:ecs:eu-west-1:123456789012:container-instance/prod/{}",
+                id
+            )
+        };
+        let mock = two_cluster_mock()
+            .with_response(
+                "ListContainerInstances",
+                json!({ "containerInstanceArns": [instance_arn("a"), instance_arn("b"), instance_arn("c")] }),
+            )
+            .with_response(
+                "DescribeContainerInstances",
+                json!({ "containerInstances": [
+                    {
+                        "ec2InstanceId": "mi-0123456789abcdef0",
+                        "agentConnected": true,
+                        "versionInfo": { "agentVersion": "1.51.0" },
+                        "attributes": [{ "name": "ecs.os-type", "value": "linux" }]
+                    },
+                    { "ec2InstanceId": "i-0123456789abcdef0", "agentConnected": true },
+                    { "ec2InstanceId": "mi-0fedcba9876543210", "agentConnected": false }
+                ] }),
+            );
+
+        let instances = block_on(get_external_instances(&mock.client(), "prod")).unwrap();
+
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].instance_id, "mi-0123456789abcdef0");
+        assert_eq!(instances[0].agent_version, "1.51.0");
+        assert!(instances[0].connected_status);
+        assert_eq!(instances[0].platform_type, "linux");
+        assert_eq!(instances[1].instance_id, "mi-0fedcba9876543210");
+        assert!(!instances[1].connected_status);
+        assert_eq!(instances[1].platform_type, "");
+        assert_eq!(
+            mock.requests("DescribeContainerInstances")[0]["cluster"],
+            "prod"
+        );
+    }
+
+    #[test]
+    fn spawned_scan_matches_the_borrowing_scan() {
+        let mock = two_cluster_mock();
+        let options = ScanOptions::default();
+        let borrowed = scan_outcome(&mock, &options).unwrap();
+
+        let runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build();
+        let spawned = runtime
+            .unwrap()
+            .block_on(get_images_of_clusters_spawned(
+                Arc::new(mock.client()),
+                Arc::new(options),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            scanned_images(&spawned.clusters),
+            scanned_images(&borrowed.clusters)
+        );
+        assert_eq!(
+            spawned.clusters[PROD_ARN].images[0].image_name,
+            "nginx:1.19"
+        );
+        assert_eq!(mock.call_count("DescribeServices"), 4);
+    }
+
+    #[test]
+    fn service_arn_is_kept_verbatim_in_both_formats() {
+        let old_format = "arn:aws:ecs:eu-west-1:123456789012:service/web";
+        let new_format = "arn:aws:ecs:eu-west-1:210987654321:service/staging/api";
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN, STAGING_ARN])
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("api:1"),
+                task_definition(vec![container("api", "api:2")]),
+            )
+            .with_services(
+                PROD_ARN,
+                vec![Service {
+                    service_arn: Some(old_format.to_owned()),
+                    ..service("web", &td_arn("web:3"))
+                }],
+            )
+            .with_services(
+                STAGING_ARN,
+                vec![Service {
+                    service_arn: Some(new_format.to_owned()),
+                    ..service("api", &td_arn("api:1"))
+                }],
+            );
+        let results = scan(&mock);
+
+        let web = &results[PROD_ARN].images[0];
+        assert_eq!(web.service_arn, old_format);
+        assert_eq!(service_account_id(web).as_deref(), Some("123456789012"));
+        let api = &results[STAGING_ARN].images[0];
+        assert_eq!(api.service_arn, new_format);
+        assert_eq!(service_account_id(api).as_deref(), Some("210987654321"));
+    }
+
+    #[test]
+    fn fleet_summary_counts_scanned_clusters_only() {
+        let mock = two_cluster_mock().fail_cluster(STAGING_ARN, "ThrottlingException");
+        let outcome = scan_outcome(&mock, &on_throttle(ThrottleStrategy::SkipCluster)).unwrap();
+
+        let summary = FleetSummary::from_outcome(&outcome);
+        assert_eq!(summary.cluster_count, 2);
+        assert_eq!(summary.scanned_cluster_count, 1);
+        assert_eq!(summary.service_count, 1);
+        assert_eq!(summary.image_count, 1);
+        assert_eq!(summary.error_count, 1);
+    }
+
+    #[test]
+    fn retried_throttles_are_counted_per_cluster_and_operation() {
+        let mock = two_cluster_mock().fail_next("ListServices", 1, "ThrottlingException");
+        let options = on_throttle(ThrottleStrategy::WaitAndRetry(Duration::from_millis(10)));
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert_eq!(outcome.total_retries, 1);
+        let retried = high_retry_clusters(&outcome, 0);
+        assert_eq!(retried.len(), 1);
+        let retried = &outcome.clusters[retried[0]];
+        assert_eq!(retried.retry_counts.get("list_services"), Some(&1));
+        assert_eq!(retried.retry_counts.len(), 1);
+        // the throttled attempt is accounted for next to list, describe services and describe task definition
+        assert_eq!(retried.api_calls_made, 4);
+        assert!(high_retry_clusters(&outcome, 1).is_empty());
+    }
+
+    #[test]
+    fn unthrottled_scan_has_no_retries() {
+        let outcome = scan_outcome(&two_cluster_mock(), &ScanOptions::default()).unwrap();
+
+        assert_eq!(outcome.total_retries, 0);
+        assert!(outcome
+            .clusters
+            .values()
+            .all(|result| result.retry_counts.is_empty()));
+        assert!(outcome
+            .clusters
+            .values()
+            .all(|result| result.api_calls_made == 3));
+    }
+
+    #[test]
+    fn task_definition_status_is_kept_on_images() {
+        let inactive = TaskDefinition {
+            status: Some("INACTIVE".to_owned()),
+            ..task_definition(vec![container("api", "api:1")])
+        };
+        let mock = two_cluster_mock()
+            .with_services(PROD_ARN, vec![service("legacy", &td_arn("api:0"))])
+            .with_task_definition(&td_arn("api:0"), inactive);
+        let results = scan(&mock);
+
+        let images = &results[PROD_ARN].images;
+        assert_eq!(images[0].image_name, DEREGISTERED_IMAGE_NAME);
+        assert_eq!(images[0].task_definition_status, "INACTIVE");
+        assert_eq!(images[1].task_definition_status, "ACTIVE");
+        let inactive: Vec<&str> = find_images_with_inactive_task_definition(images)
+            .iter()
+            .map(|i| i.service_name.as_str())
+            .collect();
+        assert_eq!(inactive, vec!["legacy"]);
+    }
+
+    fn endpoint(region: Region, endpoint_variant: EndpointVariant) -> Result<Region> {
+        EcsClientBuilder::new(region)
+            .endpoint_variant(endpoint_variant)
+            .endpoint_region()
+    }
+
+    fn custom_region(name: &str, endpoint: &str) -> Region {
+        Region::Custom {
+            name: name.to_owned(),
+            endpoint: endpoint.to_owned(),
+        }
+    }
+
+    #[test]
+    fn standard_endpoint_keeps_the_region() {
+        assert_eq!(
+            endpoint(Region::EuWest1, EndpointVariant::Standard).unwrap(),
+            Region::EuWest1
+        );
+        assert_eq!(
+            EcsClientBuilder::new(Region::EuWest1).endpoint_variant,
+            EndpointVariant::Standard
+        );
+    }
+
+    #[test]
+    fn dual_stack_endpoints() {
+        assert_eq!(
+            endpoint(Region::UsEast1, EndpointVariant::DualStack).unwrap(),
+            custom_region("us-east-1", "https://ecs.us-east-1.api.aws")
+        );
+        assert_eq!(
+            endpoint(Region::CnNorth1, EndpointVariant::DualStack).unwrap(),
+            custom_region(
+                "cn-north-1",
+                "https://ecs.cn-north-1.api.amazonwebservices.com.cn"
+            )
+        );
+    }
+
+    #[test]
+    fn fips_endpoints() {
+        assert_eq!(
+            endpoint(Region::UsGovWest1, EndpointVariant::Fips).unwrap(),
+            custom_region(
+                "us-gov-west-1",
+                "https://ecs-fips.us-gov-west-1.amazonaws.com"
+            )
+        );
+        let error = endpoint(Region::EuWest1, EndpointVariant::Fips).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<EcsError>(),
+            Some(EcsError::UnsupportedEndpoint(_))
+        ));
+    }
+
+    #[test]
+    fn network_mode_of_the_task_definition() {
+        let awsvpc = TaskDefinition {
+            network_mode: Some("awsvpc".to_owned()),
+            ..task_definition(vec![container("web", "nginx:1.19")])
+        };
+        let results = scan(&two_cluster_mock().with_task_definition(&td_arn("web:3"), awsvpc));
+
+        let web = &results[PROD_ARN].services;
+        assert_eq!(
+            web[0].task_definition_metadata,
+            Some(TaskDefinitionMetadata {
+                network_mode: Some("awsvpc".to_owned()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(find_services_in_network_mode(web, "awsvpc").len(), 1);
+        assert!(find_services_in_network_mode(web, "host").is_empty());
+        // unset in the task definition, which means bridge on Linux
+        let api = &results[STAGING_ARN].services;
+        assert_eq!(
+            api[0].task_definition_metadata,
+            Some(TaskDefinitionMetadata::default())
+        );
+    }
+
+    fn deploying(running_count: i64, pending_count: i64) -> MockEcsClient {
+        let mut web = service("web", &td_arn("web:3"));
+        web.desired_count = Some(2);
+        web.running_count = Some(running_count);
+        web.pending_count = Some(pending_count);
+        web.deployments = Some(vec![Deployment {
+            status: Some("PRIMARY".to_owned()),
+            ..Default::default()
+        }]);
+        MockEcsClient::with_clusters(vec![PROD_ARN]).with_services(PROD_ARN, vec![web])
+    }
+
+    #[test]
+    fn steady_service_is_returned_after_one_poll() {
+        let mock = deploying(2, 0);
+        let service = block_on(wait_for_steady_state(
+            &mock.client(),
+            PROD_ARN,
+            "web",
+            Duration::from_secs(5),
+            Duration::from_millis(10),
+        ))
+        .unwrap();
+
+        assert_eq!(service.service_name, "web");
+        assert_eq!(mock.call_count("DescribeServices"), 1);
+        assert_eq!(mock.call_count("DescribeTaskDefinition"), 0);
+    }
+
+    #[test]
+    fn unsteady_service_times_out_with_the_last_counts() {
+        let mock = deploying(1, 1);
+        let error = block_on(wait_for_steady_state(
+            &mock.client(),
+            PROD_ARN,
+            "web",
+            Duration::from_millis(50),
+            Duration::from_millis(20),
+        ))
+        .unwrap_err();
+
+        match error.downcast_ref::<EcsError>() {
+            Some(EcsError::DeploymentTimeout {
+                service,
+                desired_count,
+                running_count,
+                pending_count,
+            }) => {
+                assert_eq!(service, "web");
+                assert_eq!((*desired_count, *running_count, *pending_count), (2, 1, 1));
+            }
+            other => panic!("expected DeploymentTimeout, got {:?}", other),
+        }
+        assert!(mock.call_count("DescribeServices") >= 2);
+    }
+
+    #[test]
+    fn waiting_for_a_missing_service_fails() {
+        let mock = deploying(2, 0);
+        let error = block_on(wait_for_steady_state(
+            &mock.client(),
+            PROD_ARN,
+            "api",
+            Duration::from_secs(5),
+            Duration::from_millis(10),
+        ))
+        .unwrap_err();
+
+        assert!(error.to_string().contains("api not found"));
+    }
+
+    #[test]
+    fn service_tags_are_requested_and_kept() {
+        let mut web = service("web", &td_arn("web:3"));
+        web.tags = Some(vec![Tag {
+            key: Some("Environment".to_owned()),
+            value: Some("production".to_owned()),
+        }]);
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN]).with_services(PROD_ARN, vec![web]);
+        let services = block_on(get_services_of_a_cluster(&mock.client(), "prod")).unwrap();
+
+        let tags = tags_to_map(services[0].tags.as_deref());
+        assert_eq!(
+            tags.get("Environment").map(String::as_str),
+            Some("production")
+        );
+        assert!(mock
+            .requests("DescribeServices")
+            .iter()
+            .all(|request| request["include"] == serde_json::json!(["TAGS"])));
+    }
+
+    #[test]
+    fn task_definition_tags_reach_the_scanned_images() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(
+                PROD_ARN,
+                vec![
+                    service("web", &td_arn("web:3")),
+                    service("api", &td_arn("api:1")),
+                ],
+            )
+            .with_tagged_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+                vec![Tag {
+                    key: Some("git.sha".to_owned()),
+                    value: Some("4f2c1e9".to_owned()),
+                }],
+            )
+            .with_task_definition(
+                &td_arn("api:1"),
+                task_definition(vec![container("api", "api:2")]),
+            );
+        let outcome = scan_outcome(&mock, &ScanOptions::default()).unwrap();
+        let images = &outcome.clusters[PROD_ARN].images;
+
+        let web = images
+            .iter()
+            .find(|image| image.service_name == "web")
+            .unwrap();
+        assert_eq!(
+            web.task_definition_tags.get("git.sha").map(String::as_str),
+            Some("4f2c1e9")
+        );
+        let api = images
+            .iter()
+            .find(|image| image.service_name == "api")
+            .unwrap();
+        assert!(api.task_definition_tags.is_empty());
+        let tagged: Vec<&str> =
+            crate::image::find_images_with_task_definition_tag(images, "git.sha", Some("4f2c1e9"))
+                .iter()
+                .map(|image| image.service_name.as_str())
+                .collect();
+        assert_eq!(tagged, vec!["web"]);
+        assert_eq!(mock.call_count("DescribeTaskDefinition"), 2);
+    }
+
+    #[test]
+    fn task_definition_tags_serialize_in_key_order() {
+        let tag = |key: &str, value: &str| Tag {
+            key: Some(key.to_owned()),
+            value: Some(value.to_owned()),
+        };
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![service("web", &td_arn("web:3"))])
+            .with_tagged_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+                vec![
+                    tag("team", "web"),
+                    tag("pipeline", "1234"),
+                    tag("git.sha", "4f2c1e9"),
+                ],
+            );
+        let results = scan(&mock);
+
+        let tags = &results[PROD_ARN].images[0].task_definition_tags;
+        assert_eq!(
+            serde_json::to_string(tags).unwrap(),
+            r#"{"git.sha":"4f2c1e9","pipeline":"1234","team":"web"}"#
+        );
+    }
+
+    #[test]
+    fn task_definitions_using_an_image_are_the_latest_revisions() {
+        let mock = MockEcsClient::default()
+            .with_task_definition(
+                &td_arn("web:2"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.20")]),
+            )
+            .with_task_definition(
+                &td_arn("proxy:7"),
+                task_definition(vec![
+                    container("app", "app:1"),
+                    container("proxy", "nginx:1.19"),
+                ]),
+            )
+            .with_task_definition(
+                &td_arn("api:1"),
+                task_definition(vec![container("api", "nginx:1.19-alpine")]),
+            )
+            .with_response(
+                "ListTaskDefinitionFamilies",
+                serde_json::json!({ "families": ["web", "proxy", "api"] }),
+            );
+        let arns = block_on(find_task_definitions_using_image(
+            &mock.client(),
+            "nginx:1.19",
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(arns, vec![td_arn("proxy:7")]);
+        assert_eq!(mock.call_count("DescribeTaskDefinition"), 3);
+        let request = &mock.requests("ListTaskDefinitionFamilies")[0];
+        assert!(request["familyPrefix"].is_null());
+        assert_eq!(request["status"], "ACTIVE");
+    }
+
+    #[test]
+    fn running_tasks_keep_their_own_capacity_provider_and_launch_type() {
+        let task_arn = |id: &str| format!("arn:aws:ecs:eu-west-1:123456789012:task/prod/{}", id);
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_response(
+                "ListTasks",
+                serde_json::json!({ "taskArns": [task_arn("a1"), task_arn("b2")] }),
+            )
+            .with_response(
+                "DescribeTasks",
+                serde_json::json!({ "tasks": [
+                    {
+                        "taskArn": task_arn("a1"),
+                        "taskDefinitionArn": td_arn("web:3"),
+                        "group": "service:web",
+                        "capacityProviderName": "FARGATE_SPOT",
+                        "launchType": "FARGATE"
+                    },
+                    {
+                        "taskArn": task_arn("b2"),
+                        "taskDefinitionArn": td_arn("migrate:1"),
+                        "group": "family:migrate",
+                        "startedBy": "deploy-pipeline",
+                        "launchType": "EC2"
+                    }
+                ] }),
+            );
+        let tasks = block_on(get_running_tasks(&mock.client(), "prod")).unwrap();
+
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(
+            tasks[0].task_capacity_provider.as_deref(),
+            Some("FARGATE_SPOT")
+        );
+        assert_eq!(tasks[0].task_launch_type.as_deref(), Some("FARGATE"));
+        assert_eq!(tasks[1].task_capacity_provider, None);
+        assert_eq!(tasks[1].group.as_deref(), Some("family:migrate"));
+        assert_eq!(tasks[1].started_by.as_deref(), Some("deploy-pipeline"));
+        assert_eq!(mock.requests("ListTasks")[0]["desiredStatus"], "RUNNING");
+        assert_eq!(
+            mock.requests("DescribeTasks")[0]["tasks"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn zero_buffer_size_still_delivers_every_image() {
+        let mock = five_cluster_mock();
+        let (scan, receiver) =
+            scan_images_channel(Arc::new(mock.client()), ScanOptions::default(), 0);
+        let consume = async {
+            let mut received = 0;
+            while receiver.recv().await.is_ok() {
+                received += 1;
+            }
+            received
+        };
+        let (scanned, received) = block_on(futures::future::join(scan, consume));
+
+        scanned.unwrap();
+        assert_eq!(received, 5);
+    }
+
+    #[test]
+    fn wait_and_retry_throttled_twice_fails_only_that_cluster() {
+        let mock = two_cluster_mock().fail_cluster(STAGING_ARN, "ThrottlingException");
+        let options = on_throttle(ThrottleStrategy::WaitAndRetry(Duration::from_millis(10)));
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert!(matches!(
+            outcome.clusters[STAGING_ARN].status,
+            ClusterScanStatus::Error(_)
+        ));
+        assert_eq!(
+            outcome.clusters[PROD_ARN].status,
+            ClusterScanStatus::Scanned
+        );
+        assert_eq!(outcome.clusters[PROD_ARN].images.len(), 1);
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(outcome.errors[0].cluster, STAGING_ARN);
+    }
+
+    #[test]
+    fn concurrent_throttled_calls_count_as_one_retry() {
+        let services = (1..=3)
+            .map(|i| service(&format!("web-{}", i), &td_arn("web:3")))
+            .collect();
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, services)
+            .with_task_definition(
+                &td_arn("web:3"),
+                task_definition(vec![container("web", "nginx:1.19")]),
+            )
+            .fail_next("DescribeTaskDefinition", 3, "ThrottlingException");
+        let options = on_throttle(ThrottleStrategy::WaitAndRetry(Duration::from_millis(10)));
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert_eq!(outcome.clusters[PROD_ARN].images.len(), 3);
+        assert_eq!(
+            outcome.clusters[PROD_ARN]
+                .retry_counts
+                .get("describe_task_definition"),
+            Some(&1)
+        );
+        assert_eq!(outcome.total_retries, 1);
+    }
+
+    #[test]
+    fn cluster_throttled_on_its_retry_keeps_its_retry_count() {
+        let mock = two_cluster_mock().fail_cluster(STAGING_ARN, "ThrottlingException");
+        let options = on_throttle(ThrottleStrategy::WaitAndRetry(Duration::from_millis(10)));
+        let outcome = scan_outcome(&mock, &options).unwrap();
+
+        assert_eq!(
+            outcome.clusters[STAGING_ARN]
+                .retry_counts
+                .values()
+                .sum::<u32>(),
+            1
+        );
+        assert_eq!(outcome.total_retries, 1);
+    }
+
+    #[test]
+    fn service_with_an_old_deployment_left_is_not_steady() {
+        let mut web = service("web", &td_arn("web:4"));
+        web.desired_count = Some(2);
+        web.running_count = Some(2);
+        web.pending_count = Some(0);
+        web.deployments = Some(vec![
+            Deployment {
+                status: Some("PRIMARY".to_owned()),
+                ..Default::default()
+            },
+            Deployment {
+                status: Some("ACTIVE".to_owned()),
+                ..Default::default()
+            },
+        ]);
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN]).with_services(PROD_ARN, vec![web]);
+        let error = block_on(wait_for_steady_state(
+            &mock.client(),
+            PROD_ARN,
+            "web",
+            Duration::from_millis(50),
+            Duration::from_millis(20),
+        ))
+        .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<EcsError>(),
+            Some(EcsError::DeploymentTimeout { .. })
+        ));
+    }
+
+    // mixed_launch_type_mock plus a FARGATE_SPOT and an auto scaling group provider service
+    fn mixed_provider_mock() -> MockEcsClient {
+        let on_provider = |name: &str, capacity_provider: &str| Service {
+            capacity_provider_strategy: Some(vec![rusoto_ecs::CapacityProviderStrategyItem {
+                capacity_provider: capacity_provider.to_owned(),
+                weight: Some(1),
+                base: None,
+            }]),
+            ..service(name, &td_arn("web:3"))
+        };
+        mixed_launch_type_mock().with_services(
+            PROD_ARN,
+            vec![
+                on_provider("spot", "FARGATE_SPOT"),
+                on_provider("asg", "prod-asg"),
+            ],
+        )
+    }
+
+    fn service_names(services: &[&ServiceImage]) -> Vec<String> {
+        services.iter().map(|s| s.service_name.clone()).collect()
+    }
+
+    #[test]
+    fn capacity_provider_services_count_for_their_launch_type() {
+        let results = scan(&mixed_provider_mock());
+
+        let services = &results[PROD_ARN].services;
+        assert_eq!(
+            service_names(&find_services_by_launch_type(services, LaunchType::Fargate)),
+            vec!["api", "spot"]
+        );
+        assert_eq!(
+            service_names(&find_services_by_launch_type(services, LaunchType::Ec2)),
+            vec!["asg", "web"]
+        );
+    }
+
+    #[test]
+    fn launch_type_option_keeps_capacity_provider_services() {
+        let mock = mixed_provider_mock();
+        let options = ClusterScanOptions {
+            launch_type: Some(LaunchType::Fargate),
+            ..Default::default()
+        };
+        let results = scan_with_options(&mock, &ClusterFilter::default(), &options);
+
+        let prod = &results[PROD_ARN];
+        let scanned: Vec<&ServiceImage> = prod.services.iter().collect();
+        assert_eq!(service_names(&scanned), vec!["api", "spot"]);
+        assert_eq!(prod.images.len(), 2);
+        // ECS would leave out the spot service if the launch type went to ListServices
+        assert!(mock
+            .requests("ListServices")
+            .iter()
+            .all(|request| request["launchType"].is_null()));
+    }
 }
@@ -0,0 +1,75 @@
+use rusoto_core::RusotoError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum EcsError {
+    InvalidCredentials(String),
+    InvalidFilterPattern(String),
+    ApiThrottled(String),
+    InvalidMaxResults(i64),
+    InsufficientPermissions(Vec<String>),
+    UnsupportedEndpoint(String),
+    // last counts seen before giving up on the deployment
+    DeploymentTimeout {
+        service: String,
+        desired_count: i64,
+        running_count: i64,
+        pending_count: i64,
+    },
+}
+
+impl fmt::Display for EcsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EcsError::InvalidCredentials(reason) => write!(f, "Invalid credentials: {}", reason),
+            EcsError::InvalidFilterPattern(pattern) => {
+                write!(f, "Invalid cluster filter pattern: {}", pattern)
+            }
+            EcsError::ApiThrottled(message) => write!(f, "AWS API call throttled: {}", message),
+            EcsError::InvalidMaxResults(max_results) => {
+                write!(
+                    f,
+                    "max_results must be between 1 and 100, got {}",
+                    max_results
+                )
+            }
+            EcsError::InsufficientPermissions(denied) => {
+                write!(f, "Missing permissions: {}", denied.join(", "))
+            }
+            EcsError::UnsupportedEndpoint(endpoint) => {
+                write!(f, "Unsupported ECS endpoint: {}", endpoint)
+            }
+            EcsError::DeploymentTimeout {
+                service,
+                desired_count,
+                running_count,
+                pending_count,
+            } => write!(
+                f,
+                "Service {} did not reach a steady state: {} desired, {} running, {} pending",
+                service, desired_count, running_count, pending_count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EcsError {}
+
+// ECS does not model throttling errors, they come back as unknown 400 responses
+pub fn from_rusoto<E: std::error::Error + Send + Sync + 'static>(
+    err: RusotoError<E>,
+) -> anyhow::Error {
+    match err {
+        RusotoError::Unknown(ref res) if res.body_as_str().contains("ThrottlingException") => {
+            EcsError::ApiThrottled(res.body_as_str().to_owned()).into()
+        }
+        err => err.into(),
+    }
+}
+
+pub fn is_throttled(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<EcsError>(),
+        Some(EcsError::ApiThrottled(_))
+    )
+}
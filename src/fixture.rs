@@ -0,0 +1,272 @@
+use crate::client::HttpClient;
+use crate::credentials::Credentials;
+use crate::ecs::{self, ScanOptions};
+
+use anyhow::Context;
+use anyhow::Result;
+use hyper::{HeaderMap, StatusCode};
+use rusoto_core::region::Region;
+use rusoto_core::request::HttpResponse;
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, DispatchSignedRequest, HttpDispatchError};
+use rusoto_credential::StaticProvider;
+use rusoto_ecs::EcsClient;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// One ECS API exchange, keyed by the x-amz-target operation and the JSON request body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub operation: String,
+    pub request: String,
+    pub status: u16,
+    pub response: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ScanFixture {
+    pub calls: Vec<RecordedCall>,
+}
+
+impl ScanFixture {
+    pub fn load(path: &Path) -> Result<ScanFixture> {
+        let f = File::open(path).with_context(|| format!("could not read {:?}", path))?;
+        serde_json::from_reader(f).with_context(|| format!("Invalid json in {:?}", path))
+    }
+
+    pub fn persist(&self, path: &Path) -> Result<()> {
+        let f = File::create(path)?;
+        serde_json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+}
+
+fn request_key(request: &SignedRequest) -> (String, String) {
+    let operation = request
+        .headers
+        .get("x-amz-target")
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+        .unwrap_or_default();
+    let body = match request.payload {
+        Some(SignedRequestPayload::Buffer(ref bytes)) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+        _ => String::new(),
+    };
+    (operation, body)
+}
+
+struct RecordingDispatcher {
+    inner: Arc<HttpClient>,
+    calls: Arc<Mutex<Vec<RecordedCall>>>,
+}
+
+impl DispatchSignedRequest for RecordingDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> rusoto_core::request::DispatchSignedRequestFuture {
+        let (operation, body) = request_key(&request);
+        let response_fut = self.inner.dispatch(request, timeout);
+        let calls = self.calls.clone();
+        Box::pin(async move {
+            let mut response = response_fut.await?;
+            let buffered = response.buffer().await?;
+            calls.lock().unwrap().push(RecordedCall {
+                operation,
+                request: body,
+                status: buffered.status.as_u16(),
+                response: buffered.body_as_str().to_owned(),
+            });
+            Ok(HttpResponse {
+                status: buffered.status,
+                headers: buffered.headers,
+                body: ByteStream::from(buffered.body.to_vec()),
+            })
+        })
+    }
+}
+
+// each recorded exchange answers one request, matching requests get them in recorded order
+struct ReplayDispatcher {
+    calls: Mutex<Vec<RecordedCall>>,
+}
+
+impl DispatchSignedRequest for ReplayDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> rusoto_core::request::DispatchSignedRequestFuture {
+        let (operation, body) = request_key(&request);
+        let recorded = {
+            let mut calls = self.calls.lock().unwrap();
+            calls
+                .iter()
+                .position(|call| call.operation == operation && call.request == body)
+                .map(|index| calls.remove(index))
+        };
+        Box::pin(async move {
+            let call = recorded.ok_or_else(|| {
+                HttpDispatchError::new(format!("No recorded response for {} {}", operation, body))
+            })?;
+            Ok(HttpResponse {
+                status: StatusCode::from_u16(call.status)
+                    .map_err(|e| HttpDispatchError::new(e.to_string()))?,
+                headers: HeaderMap::default(),
+                body: ByteStream::from(call.response.into_bytes()),
+            })
+        })
+    }
+}
+
+// Runs a full scan and records every ECS request/response pair it made
+pub async fn capture_scan_fixture(
+    client: Arc<HttpClient>,
+    creds: Credentials,
+    options: &ScanOptions,
+) -> Result<ScanFixture> {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let dispatcher = RecordingDispatcher {
+        inner: client,
+        calls: calls.clone(),
+    };
+    let cred_provider = StaticProvider::new(
+        creds.aws_access_key,
+        creds.aws_secret_key,
+        creds.aws_sts_token,
+        None,
+    );
+    let ecs_client = EcsClient::new_with(dispatcher, cred_provider, Region::EuWest1);
+    ecs::get_images_of_clusters(&ecs_client, options).await?;
+
+    let calls = calls.lock().unwrap().clone();
+    Ok(ScanFixture { calls })
+}
+
+// Builds an ECS client answering from the fixture instead of AWS, every recorded
+// exchange is replayed once
+pub fn replay_from_fixture(fixture: &ScanFixture) -> EcsClient {
+    let dispatcher = ReplayDispatcher {
+        calls: Mutex::new(fixture.calls.clone()),
+    };
+    let cred_provider = StaticProvider::new_minimal("replay".to_owned(), "replay".to_owned());
+    EcsClient::new_with(dispatcher, cred_provider, Region::EuWest1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, container, service, task_definition, MockEcsClient};
+    use rusoto_ecs::{Ecs, ListClustersRequest};
+
+    const PROD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+    const TD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3";
+
+    // the exchanges of the scans run against the mock, the way capture_scan_fixture records them
+    fn fixture_of(mock: &MockEcsClient) -> ScanFixture {
+        let calls = mock
+            .calls()
+            .into_iter()
+            .map(|call| RecordedCall {
+                operation: call.target,
+                request: call.body,
+                status: call.status,
+                response: call.response,
+            })
+            .collect();
+        ScanFixture { calls }
+    }
+
+    fn images_json(outcome: &ecs::ScanOutcome) -> String {
+        let images: Vec<_> = outcome
+            .clusters
+            .values()
+            .map(|result| &result.images)
+            .collect();
+        serde_json::to_string(&images).unwrap()
+    }
+
+    #[test]
+    fn replaying_a_fixture_gives_the_recorded_images() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(
+                PROD_ARN,
+                vec![service("web", TD_ARN), service("admin", TD_ARN)],
+            )
+            .with_task_definition(
+                TD_ARN,
+                task_definition(vec![container("web", "nginx:1.19")]),
+            );
+        let options = ScanOptions::default();
+        let scanned = block_on(ecs::get_images_of_clusters(&mock.client(), &options)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("scan-fixture-{}.json", std::process::id()));
+        fixture_of(&mock).persist(&path).unwrap();
+        let fixture = ScanFixture::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let replayed = block_on(ecs::get_images_of_clusters(
+            &replay_from_fixture(&fixture),
+            &options,
+        ))
+        .unwrap();
+
+        assert_eq!(replayed.clusters.len(), 1);
+        assert_eq!(replayed.clusters[PROD_ARN].images.len(), 2);
+        assert_eq!(images_json(&replayed), images_json(&scanned));
+    }
+
+    #[test]
+    fn replays_the_recorded_response() {
+        let fixture = ScanFixture {
+            calls: vec![RecordedCall {
+                operation: "AmazonEC2ContainerServiceV20141113.ListClusters".to_owned(),
+                request: "{}".to_owned(),
+                status: 200,
+                response: format!(r#"{{"clusterArns":["{}"]}}"#, PROD_ARN),
+            }],
+        };
+        let client = replay_from_fixture(&fixture);
+        let response = block_on(client.list_clusters(ListClustersRequest::default())).unwrap();
+        assert_eq!(response.cluster_arns, Some(vec![PROD_ARN.to_owned()]));
+    }
+
+    #[test]
+    fn repeated_requests_get_the_recorded_responses_in_order() {
+        let list_clusters = |status: u16, response: &str| RecordedCall {
+            operation: "AmazonEC2ContainerServiceV20141113.ListClusters".to_owned(),
+            request: "{}".to_owned(),
+            status,
+            response: response.to_owned(),
+        };
+        let fixture = ScanFixture {
+            calls: vec![
+                list_clusters(
+                    400,
+                    r#"{"__type":"ThrottlingException","message":"Rate exceeded"}"#,
+                ),
+                list_clusters(200, &format!(r#"{{"clusterArns":["{}"]}}"#, PROD_ARN)),
+            ],
+        };
+        let client = replay_from_fixture(&fixture);
+
+        let throttled = block_on(client.list_clusters(ListClustersRequest::default())).unwrap_err();
+        assert!(throttled.to_string().contains("Rate exceeded"));
+        let response = block_on(client.list_clusters(ListClustersRequest::default())).unwrap();
+        assert_eq!(response.cluster_arns, Some(vec![PROD_ARN.to_owned()]));
+        let exhausted = block_on(client.list_clusters(ListClustersRequest::default())).unwrap_err();
+        assert!(exhausted.to_string().contains("No recorded response"));
+    }
+
+    #[test]
+    fn fails_on_an_unrecorded_request() {
+        let client = replay_from_fixture(&ScanFixture::default());
+        let error = block_on(client.list_clusters(ListClustersRequest::default())).unwrap_err();
+        assert!(error.to_string().contains("No recorded response"));
+    }
+}
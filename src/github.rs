@@ -0,0 +1,137 @@
+use crate::image::{Image, ImageDiff};
+
+use anyhow::Result;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+pub fn detect_github_actions() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+fn outputs(images: &[Image], diff: &ImageDiff) -> Vec<(&'static str, String)> {
+    vec![
+        ("image_count", images.len().to_string()),
+        ("added_count", diff.added.len().to_string()),
+        ("removed_count", diff.removed.len().to_string()),
+        ("changed_count", diff.changed.len().to_string()),
+    ]
+}
+
+// workflow commands, see https://docs.github.com/actions/reference/workflow-commands-for-github-actions
+pub fn write_annotations<W: Write>(out: &mut W, images: &[Image], diff: &ImageDiff) -> Result<()> {
+    for (name, value) in outputs(images, diff) {
+        // deprecated by GitHub, kept for runners without $GITHUB_OUTPUT
+        writeln!(out, "::set-output name={}::{}", name, value)?;
+    }
+    for image in images.iter().filter(|image| image.uses_latest_tag()) {
+        writeln!(
+            out,
+            "::warning::{} uses the latest tag ({})",
+            image.display_name(),
+            image.image_name
+        )?;
+    }
+    for image in images
+        .iter()
+        .filter(|image| !image.critical_cves.is_empty())
+    {
+        writeln!(
+            out,
+            "::error::{} has critical CVEs: {}",
+            image.image_name,
+            image.critical_cves.join(", ")
+        )?;
+    }
+    if !diff.is_empty() {
+        writeln!(
+            out,
+            "::notice::{} images added, {} removed, {} changed",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        )?;
+    }
+    Ok(())
+}
+
+// name=value lines for the $GITHUB_OUTPUT environment file
+pub fn write_output_file<W: Write>(out: &mut W, images: &[Image], diff: &ImageDiff) -> Result<()> {
+    for (name, value) in outputs(images, diff) {
+        writeln!(out, "{}={}", name, value)?;
+    }
+    Ok(())
+}
+
+pub fn write_github_actions_output(images: &[Image], diff: &ImageDiff) -> Result<()> {
+    write_annotations(&mut io::stdout(), images, diff)?;
+    if let Ok(path) = env::var("GITHUB_OUTPUT") {
+        let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+        write_output_file(&mut f, images, diff)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(image_name: &str) -> Image {
+        Image {
+            service_name: "web".to_owned(),
+            task_definition_name: "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3"
+                .to_owned(),
+            image_name: image_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn annotations(images: &[Image], diff: &ImageDiff) -> String {
+        let mut out = Vec::new();
+        write_annotations(&mut out, images, diff).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn outputs_without_changes() {
+        let images = vec![image("nginx:1.19")];
+        assert_eq!(
+            annotations(&images, &ImageDiff::default()),
+            "::set-output name=image_count::1\n\
+             ::set-output name=added_count::0\n\
+             ::set-output name=removed_count::0\n\
+             ::set-output name=changed_count::0\n"
+        );
+    }
+
+    #[test]
+    fn warnings_errors_and_diff_notice() {
+        let mut vulnerable = image("api:2");
+        vulnerable.critical_cves = vec!["CVE-2020-0001".to_owned(), "CVE-2020-0002".to_owned()];
+        let images = vec![image("nginx:latest"), vulnerable.clone()];
+        let diff = ImageDiff {
+            added: vec![vulnerable],
+            ..Default::default()
+        };
+        assert_eq!(
+            annotations(&images, &diff),
+            "::set-output name=image_count::2\n\
+             ::set-output name=added_count::1\n\
+             ::set-output name=removed_count::0\n\
+             ::set-output name=changed_count::0\n\
+             ::warning::web @ web:3 uses the latest tag (nginx:latest)\n\
+             ::error::api:2 has critical CVEs: CVE-2020-0001, CVE-2020-0002\n\
+             ::notice::1 images added, 0 removed, 0 changed\n"
+        );
+    }
+
+    #[test]
+    fn output_file_lines() {
+        let mut out = Vec::new();
+        write_output_file(&mut out, &[image("nginx:1.19")], &ImageDiff::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "image_count=1\nadded_count=0\nremoved_count=0\nchanged_count=0\n"
+        );
+    }
+}
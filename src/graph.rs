@@ -0,0 +1,191 @@
+use crate::ecs;
+use crate::error;
+
+use anyhow::Result;
+use futures::future::join_all;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Direction;
+use rusoto_ecs::EcsClient;
+use rusoto_servicediscovery::{GetServiceRequest, ServiceDiscovery, ServiceDiscoveryClient};
+use std::collections::BTreeMap;
+
+// nodes are ECS service ARNs, edges link services registered in the same Cloud Map namespace
+pub type ServiceGraph = DiGraph<String, ()>;
+
+// arn:aws:servicediscovery:eu-west-1:123456789012:service/srv-xxxx -> srv-xxxx
+fn registry_service_id(registry_arn: &str) -> &str {
+    registry_arn.rsplit('/').next().unwrap_or(registry_arn)
+}
+
+async fn get_namespace_id(
+    service_discovery_client: &ServiceDiscoveryClient,
+    registry_arn: &str,
+) -> Result<Option<String>> {
+    let get_service_req = GetServiceRequest {
+        id: registry_service_id(registry_arn).to_owned(),
+    };
+    let get_service_res = service_discovery_client
+        .get_service(get_service_req)
+        .await
+        .map_err(error::from_rusoto)?;
+    Ok(get_service_res
+        .service
+        .and_then(|service| service.namespace_id))
+}
+
+pub async fn build_service_graph(
+    ecs_client: &EcsClient,
+    service_discovery_client: &ServiceDiscoveryClient,
+    clusters: &[String],
+) -> Result<ServiceGraph> {
+    let get_services_futures = clusters
+        .iter()
+        .map(|cluster| ecs::get_services_of_a_cluster(ecs_client, cluster));
+    let services = join_all(get_services_futures)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten();
+
+    let mut graph = ServiceGraph::new();
+    let mut registries: Vec<(NodeIndex, String)> = Vec::new();
+    for service in services {
+        let service_arn = match service.service_arn {
+            Some(service_arn) => service_arn,
+            None => continue,
+        };
+        let node = graph.add_node(service_arn);
+        for registry in service.service_registries.unwrap_or_default() {
+            registries.extend(registry.registry_arn.map(|arn| (node, arn)));
+        }
+    }
+
+    // several services may register in the same Cloud Map service, look each up once
+    let mut registry_arns: Vec<&str> = registries.iter().map(|(_, arn)| arn.as_str()).collect();
+    registry_arns.sort_unstable();
+    registry_arns.dedup();
+    let get_namespaces_futures = registry_arns.iter().map(|registry_arn| async move {
+        let namespace_id = get_namespace_id(service_discovery_client, registry_arn).await?;
+        Ok((registry_arn.to_string(), namespace_id))
+    });
+    let namespaces: BTreeMap<String, Option<String>> = join_all(get_namespaces_futures)
+        .await
+        .into_iter()
+        .collect::<Result<_>>()?;
+
+    let mut nodes_by_namespace: BTreeMap<&str, Vec<NodeIndex>> = BTreeMap::new();
+    for (node, registry_arn) in registries.iter() {
+        if let Some(Some(namespace_id)) = namespaces.get(registry_arn) {
+            let nodes = nodes_by_namespace.entry(namespace_id).or_default();
+            if !nodes.contains(node) {
+                nodes.push(*node);
+            }
+        }
+    }
+
+    // any service of a namespace may call any other, so link them both ways
+    for nodes in nodes_by_namespace.values() {
+        for from in nodes.iter() {
+            for to in nodes.iter().filter(|to| *to != from) {
+                graph.update_edge(*from, *to, ());
+            }
+        }
+    }
+    Ok(graph)
+}
+
+pub fn find_isolated_services(graph: &ServiceGraph) -> Vec<String> {
+    graph
+        .node_indices()
+        .filter(|node| {
+            graph
+                .neighbors_directed(*node, Direction::Outgoing)
+                .chain(graph.neighbors_directed(*node, Direction::Incoming))
+                .next()
+                .is_none()
+        })
+        .map(|node| graph[node].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, service, MockEcsClient, MockServiceDiscoveryClient};
+    use rusoto_ecs::{Service, ServiceRegistry};
+
+    const PROD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+    const TD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3";
+
+    fn registered(name: &str, registry_service_id: &str) -> Service {
+        Service {
+            service_registries: Some(vec![ServiceRegistry {
+                registry_arn: Some(format!(
+                    "arn:aws:servicediscovery:eu-west-1:123456789012:service/{}",
+                    registry_service_id
+                )),
+                ..Default::default()
+            }]),
+            ..service(name, TD_ARN)
+        }
+    }
+
+    fn service_arn(name: &str) -> String {
+        format!("arn:aws:ecs:eu-west-1:123456789012:service/prod/{}", name)
+    }
+
+    #[test]
+    fn services_sharing_a_namespace_are_linked() {
+        let ecs = MockEcsClient::with_clusters(vec![PROD_ARN]).with_services(
+            PROD_ARN,
+            vec![
+                registered("web", "srv-web"),
+                registered("api", "srv-api"),
+                service("batch", TD_ARN),
+            ],
+        );
+        let service_discovery = MockServiceDiscoveryClient::default()
+            .with_service("srv-web", "ns-internal")
+            .with_service("srv-api", "ns-internal");
+        let graph = block_on(build_service_graph(
+            &ecs.client(),
+            &service_discovery.client(),
+            &[PROD_ARN.to_owned()],
+        ))
+        .unwrap();
+
+        let node = |name: &str| {
+            graph
+                .node_indices()
+                .find(|node| graph[*node] == service_arn(name))
+                .unwrap()
+        };
+        assert_eq!(graph.node_count(), 3);
+        assert!(graph.contains_edge(node("web"), node("api")));
+        assert!(graph.contains_edge(node("api"), node("web")));
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(find_isolated_services(&graph), vec![service_arn("batch")]);
+    }
+
+    #[test]
+    fn services_in_different_namespaces_are_isolated() {
+        let ecs = MockEcsClient::with_clusters(vec![PROD_ARN]).with_services(
+            PROD_ARN,
+            vec![registered("web", "srv-web"), registered("api", "srv-api")],
+        );
+        let service_discovery = MockServiceDiscoveryClient::default()
+            .with_service("srv-web", "ns-public")
+            .with_service("srv-api", "ns-internal");
+        let graph = block_on(build_service_graph(
+            &ecs.client(),
+            &service_discovery.client(),
+            &[PROD_ARN.to_owned()],
+        ))
+        .unwrap();
+
+        assert_eq!(graph.edge_count(), 0);
+        assert_eq!(find_isolated_services(&graph).len(), 2);
+        assert_eq!(service_discovery.call_count("GetService"), 2);
+    }
+}
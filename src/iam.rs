@@ -0,0 +1,269 @@
+use crate::error;
+use crate::image::{self, ServiceImage};
+
+use anyhow::Result;
+use rusoto_iam::{Iam, IamClient, SimulatePrincipalPolicyRequest};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// actions issued by a scan, ecr:DescribeImages only when enriching with ECR details
+const REQUIRED_ECS_ACTIONS: [&str; 4] = [
+    "ecs:ListClusters",
+    "ecs:ListServices",
+    "ecs:DescribeServices",
+    "ecs:DescribeTaskDefinition",
+];
+const REQUIRED_ECR_ACTIONS: [&str; 1] = ["ecr:DescribeImages"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PermissionsReport {
+    pub allowed: Vec<String>,
+    pub denied: Vec<String>,
+}
+
+// IAM user or role the scan credentials belong to, e.g. arn:aws:iam::123456789012:role/ecs-reader
+#[derive(Clone)]
+pub struct PermissionsCheck {
+    pub iam_client: Arc<IamClient>,
+    pub principal_arn: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PermissionsSimulationResult {
+    pub allowed: Vec<String>,
+    pub denied: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServicePermissions {
+    pub service_name: String,
+    pub task_role_arn: String,
+    pub permissions: PermissionsSimulationResult,
+}
+
+// (allowed, denied) action names, no resource_arns simulates against any resource
+async fn simulate_principal_policy(
+    iam_client: &IamClient,
+    principal_arn: &str,
+    action_names: Vec<String>,
+    resource_arns: Option<Vec<String>>,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut allowed = Vec::new();
+    let mut denied = Vec::new();
+    let mut marker: Option<String> = None;
+    loop {
+        let simulate_req = SimulatePrincipalPolicyRequest {
+            policy_source_arn: principal_arn.to_owned(),
+            action_names: action_names.clone(),
+            resource_arns: resource_arns.clone(),
+            marker,
+            ..Default::default()
+        };
+        let simulate_res = iam_client
+            .simulate_principal_policy(simulate_req)
+            .await
+            .map_err(error::from_rusoto)?;
+        for evaluation in simulate_res.evaluation_results.unwrap_or_default() {
+            if evaluation.eval_decision == "allowed" {
+                allowed.push(evaluation.eval_action_name);
+            } else {
+                denied.push(evaluation.eval_action_name);
+            }
+        }
+        if simulate_res.is_truncated != Some(true) {
+            break;
+        }
+        marker = simulate_res.marker;
+    }
+    Ok((allowed, denied))
+}
+
+pub async fn check_required_permissions(
+    iam_client: &IamClient,
+    principal_arn: &str,
+    include_ecr: bool,
+) -> Result<PermissionsReport> {
+    let mut action_names: Vec<String> =
+        REQUIRED_ECS_ACTIONS.iter().map(|a| a.to_string()).collect();
+    if include_ecr {
+        action_names.extend(REQUIRED_ECR_ACTIONS.iter().map(|a| a.to_string()));
+    }
+
+    let (allowed, denied) =
+        simulate_principal_policy(iam_client, principal_arn, action_names, None).await?;
+    Ok(PermissionsReport { allowed, denied })
+}
+
+// actions the task role may run on resource, e.g. s3:GetObject on arn:aws:s3:::bucket/*
+pub async fn simulate_task_role_permissions(
+    iam_client: &IamClient,
+    task_role_arn: &str,
+    actions: &[&str],
+    resource: &str,
+) -> Result<PermissionsSimulationResult> {
+    let action_names = actions.iter().map(|a| a.to_string()).collect();
+    let (allowed, denied) = simulate_principal_policy(
+        iam_client,
+        task_role_arn,
+        action_names,
+        Some(vec![resource.to_owned()]),
+    )
+    .await?;
+    Ok(PermissionsSimulationResult { allowed, denied })
+}
+
+// one simulation per service with a task role, services without one are skipped
+pub async fn simulate_service_task_roles(
+    iam_client: &IamClient,
+    services: &[ServiceImage],
+    actions: &[&str],
+    resource: &str,
+) -> Result<Vec<ServicePermissions>> {
+    let mut report = Vec::new();
+    for (service_name, task_role_arn) in image::get_task_roles(services) {
+        let permissions =
+            simulate_task_role_permissions(iam_client, task_role_arn, actions, resource).await?;
+        report.push(ServicePermissions {
+            service_name: service_name.to_owned(),
+            task_role_arn: task_role_arn.to_owned(),
+            permissions,
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, MockIamClient};
+    use rusoto_ecs::{Service, TaskDefinition};
+
+    const PRINCIPAL_ARN: &str = "arn:aws:iam::123456789012:role/ecs-reader";
+
+    #[test]
+    fn missing_describe_services_is_denied() {
+        let iam = MockIamClient::denying(vec!["ecs:DescribeServices"]);
+        let report = block_on(check_required_permissions(
+            &iam.client(),
+            PRINCIPAL_ARN,
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(report.denied, vec!["ecs:DescribeServices"]);
+        assert_eq!(
+            report.allowed,
+            vec![
+                "ecs:ListClusters",
+                "ecs:ListServices",
+                "ecs:DescribeTaskDefinition"
+            ]
+        );
+    }
+
+    #[test]
+    fn ecr_action_is_only_checked_when_enriching() {
+        let iam = MockIamClient::default();
+        block_on(check_required_permissions(
+            &iam.client(),
+            PRINCIPAL_ARN,
+            false,
+        ))
+        .unwrap();
+        assert!(!iam
+            .requested_actions()
+            .contains(&"ecr:DescribeImages".to_owned()));
+
+        let report = block_on(check_required_permissions(
+            &iam.client(),
+            PRINCIPAL_ARN,
+            true,
+        ))
+        .unwrap();
+        assert!(report.denied.is_empty());
+        assert!(report.allowed.contains(&"ecr:DescribeImages".to_owned()));
+    }
+
+    const BUCKET_OBJECTS: &str = "arn:aws:s3:::assets/*";
+
+    fn with_task_role(service_name: &str, task_role_arn: Option<&str>) -> ServiceImage {
+        let td = TaskDefinition {
+            task_role_arn: task_role_arn.map(str::to_owned),
+            ..Default::default()
+        };
+        ServiceImage::from_service(
+            &Service {
+                service_name: Some(service_name.to_owned()),
+                ..Default::default()
+            },
+            Some(&td),
+        )
+    }
+
+    #[test]
+    fn task_role_simulation_on_one_resource() {
+        let iam = MockIamClient::denying(vec!["s3:PutObject"]);
+        let task_role = "arn:aws:iam::123456789012:role/web-task";
+        let result = block_on(simulate_task_role_permissions(
+            &iam.client(),
+            task_role,
+            &["s3:GetObject", "s3:PutObject"],
+            BUCKET_OBJECTS,
+        ))
+        .unwrap();
+
+        assert_eq!(result.allowed, vec!["s3:GetObject"]);
+        assert_eq!(result.denied, vec!["s3:PutObject"]);
+        let request = &iam.requests()[0];
+        assert_eq!(request["PolicySourceArn"], task_role);
+        assert_eq!(request["ResourceArns.member.1"], BUCKET_OBJECTS);
+    }
+
+    #[test]
+    fn services_without_task_role_are_skipped() {
+        let iam = MockIamClient::default();
+        let services = vec![
+            with_task_role("web", Some("arn:aws:iam::123456789012:role/web-task")),
+            with_task_role("worker", None),
+            with_task_role("api", Some("arn:aws:iam::123456789012:role/api-task")),
+        ];
+        let report = block_on(simulate_service_task_roles(
+            &iam.client(),
+            &services,
+            &["s3:GetObject"],
+            BUCKET_OBJECTS,
+        ))
+        .unwrap();
+
+        let simulated: Vec<(&str, &str)> = report
+            .iter()
+            .map(|s| (s.service_name.as_str(), s.task_role_arn.as_str()))
+            .collect();
+        assert_eq!(
+            simulated,
+            vec![
+                ("web", "arn:aws:iam::123456789012:role/web-task"),
+                ("api", "arn:aws:iam::123456789012:role/api-task")
+            ]
+        );
+        assert!(report
+            .iter()
+            .all(|s| s.permissions.allowed == vec!["s3:GetObject"]));
+        assert_eq!(iam.requests().len(), 2);
+    }
+
+    #[test]
+    fn required_permissions_are_checked_against_any_resource() {
+        let iam = MockIamClient::default();
+        block_on(check_required_permissions(
+            &iam.client(),
+            PRINCIPAL_ARN,
+            false,
+        ))
+        .unwrap();
+
+        assert!(iam.requests()[0]
+            .keys()
+            .all(|key| !key.starts_with("ResourceArns")));
+    }
+}
@@ -0,0 +1,3505 @@
+use crate::arn;
+use crate::ecs::{self, LaunchType};
+use crate::secrets::{self, SecretRef};
+use chrono::{DateTime, TimeZone, Utc};
+use rusoto_ecs::{
+    CapacityProviderStrategyItem, ContainerDefinition, EnvironmentFile, HealthCheck, MountPoint,
+    ProxyConfiguration, Service, TaskDefinition, Volume,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEREGISTERED_IMAGE_NAME: &str = "<deregistered>";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Image {
+    pub cluster_arn: String,
+    // short cluster name, for display
+    pub cluster_name: String,
+    pub image_name: String,
+    pub task_definition_name: String,
+    pub service_name: String,
+    // verbatim, either arn:aws:ecs:{region}:{account}:service/{name} (old format)
+    // or arn:aws:ecs:{region}:{account}:service/{cluster}/{name}
+    #[serde(default)]
+    pub service_arn: String,
+    pub container_name: String,
+    // repository@digest for ECR images, set when the scan enriches with ECR details
+    pub enriched_image_name: Option<String>,
+    // container level cpu units and memory (MiB)
+    #[serde(default)]
+    pub cpu_limit: Option<i64>,
+    #[serde(default)]
+    pub memory_limit: Option<i64>,
+    #[serde(default)]
+    pub memory_reservation: Option<i64>,
+    // filled by image processors, e.g. LatestTagWarningProcessor
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    // critical CVE ids reported by a scanning processor
+    #[serde(default)]
+    pub critical_cves: Vec<String>,
+    // docker labels of the container, e.g. git.commit or build.number, sorted for stable output
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub linux_parameters: Option<ContainerLinuxConfig>,
+    #[serde(default)]
+    pub health_check: Option<ContainerHealthCheck>,
+    #[serde(default)]
+    pub volume_mounts: Vec<VolumeMount>,
+    #[serde(default)]
+    pub working_directory: Option<String>,
+    // ACTIVE, INACTIVE once deregistered
+    #[serde(default)]
+    pub task_definition_status: String,
+    // seconds before the container is killed after SIGTERM
+    #[serde(default)]
+    pub stop_timeout: Option<i64>,
+    // seconds the container waits for its dependencies before giving up
+    #[serde(default)]
+    pub start_timeout: Option<i64>,
+    #[serde(default)]
+    pub container_dependencies: Vec<ContainerDependency>,
+    #[serde(default)]
+    pub gpu_count: Option<i64>,
+    #[serde(default)]
+    pub inference_accelerators: Vec<InferenceAcceleratorAssignment>,
+    #[serde(default)]
+    pub system_controls: Vec<SystemControl>,
+    #[serde(default)]
+    pub ulimits: Vec<Ulimit>,
+    // log driver is awsfirelens, i.e. logs go through the FireLens router of the task
+    #[serde(default)]
+    pub uses_firelens_log_driver: bool,
+    // fluentbit or fluentd when the container is the FireLens router itself
+    #[serde(default)]
+    pub firelens_router_type: Option<String>,
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    // environment variables holding a secret ARN, see secrets::get_secret_references
+    #[serde(default)]
+    pub environment_secrets: Vec<SecretRef>,
+    // entries added to /etc/hosts of the container
+    #[serde(default)]
+    pub extra_hosts: Vec<HostEntry>,
+    // resolvers and search domains replacing the VPC defaults
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    #[serde(default)]
+    pub dns_search_domains: Vec<String>,
+    // legacy bridge networking links, name[:alias]
+    #[serde(default)]
+    pub links: Vec<String>,
+    // containers of the same task whose volumes are all mounted into this one
+    #[serde(default)]
+    pub volumes_from: Vec<VolumeFrom>,
+    // Secrets Manager ARN holding the login of a private, non-ECR registry
+    #[serde(default)]
+    pub repository_credentials_arn: Option<String>,
+    #[serde(default)]
+    pub container_hostname: Option<String>,
+    #[serde(default)]
+    pub disable_networking: Option<bool>,
+    // docker run -i / -t, usually a debugging leftover
+    #[serde(default)]
+    pub interactive: Option<bool>,
+    #[serde(default)]
+    pub pseudo_terminal: Option<bool>,
+    // linuxParameters.maxSwap in MiB, 0 disables swap, None leaves the instance default
+    #[serde(default)]
+    pub memory_swap: Option<i64>,
+    // 0 to 100, linuxParameters.swappiness
+    #[serde(default)]
+    pub memory_swappiness: Option<i64>,
+    // /dev/shm size in MiB, linuxParameters.sharedMemorySize
+    #[serde(default)]
+    pub shm_size: Option<i64>,
+    #[serde(default)]
+    pub devices: Vec<DeviceMapping>,
+    // build metadata such as git.sha or pipeline run id, from DescribeTaskDefinition TAGS
+    #[serde(default)]
+    pub task_definition_tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub environment_files: Vec<EnvFileRef>,
+}
+
+// .env file loaded into the container, type_ is s3
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvFileRef {
+    pub s3_arn: String,
+    pub type_: String,
+}
+
+impl From<&EnvironmentFile> for EnvFileRef {
+    fn from(environment_file: &EnvironmentFile) -> Self {
+        EnvFileRef {
+            s3_arn: environment_file.value.clone(),
+            type_: environment_file.type_.clone(),
+        }
+    }
+}
+
+// host device exposed to the container, permissions are read, write and mknod
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceMapping {
+    pub host_path: String,
+    pub container_path: Option<String>,
+    pub permissions: Vec<String>,
+}
+
+impl From<&rusoto_ecs::Device> for DeviceMapping {
+    fn from(device: &rusoto_ecs::Device) -> Self {
+        DeviceMapping {
+            host_path: device.host_path.clone(),
+            container_path: device.container_path.clone(),
+            permissions: device.permissions.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeFrom {
+    pub source_container: String,
+    pub read_only: bool,
+}
+
+impl From<&rusoto_ecs::VolumeFrom> for VolumeFrom {
+    fn from(volume_from: &rusoto_ecs::VolumeFrom) -> Self {
+        VolumeFrom {
+            source_container: volume_from.source_container.clone().unwrap_or_default(),
+            read_only: volume_from.read_only.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostEntry {
+    pub hostname: String,
+    pub ip_address: String,
+}
+
+impl From<&rusoto_ecs::HostEntry> for HostEntry {
+    fn from(host_entry: &rusoto_ecs::HostEntry) -> Self {
+        HostEntry {
+            hostname: host_entry.hostname.clone(),
+            ip_address: host_entry.ip_address.clone(),
+        }
+    }
+}
+
+// resource limit of the container, name is e.g. nofile, nproc or stack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ulimit {
+    pub name: String,
+    pub soft_limit: i64,
+    pub hard_limit: i64,
+}
+
+impl From<&rusoto_ecs::Ulimit> for Ulimit {
+    fn from(ulimit: &rusoto_ecs::Ulimit) -> Self {
+        Ulimit {
+            name: ulimit.name.clone(),
+            soft_limit: ulimit.soft_limit,
+            hard_limit: ulimit.hard_limit,
+        }
+    }
+}
+
+// sysctl override of the container, e.g. net.core.somaxconn = 1024
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemControl {
+    pub namespace: String,
+    pub value: String,
+}
+
+impl From<&rusoto_ecs::SystemControl> for SystemControl {
+    fn from(system_control: &rusoto_ecs::SystemControl) -> Self {
+        SystemControl {
+            namespace: system_control.namespace.clone().unwrap_or_default(),
+            value: system_control.value.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Elastic Inference accelerator of the task assigned to the container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InferenceAcceleratorAssignment {
+    pub inference_accelerator_name: String,
+    // e.g. eia2.medium, empty when the task does not declare the accelerator
+    pub device_type: String,
+}
+
+fn gpu_count(container_definition: &ContainerDefinition) -> Option<i64> {
+    container_definition
+        .resource_requirements
+        .iter()
+        .flatten()
+        .filter(|requirement| requirement.type_ == "GPU")
+        .filter_map(|requirement| requirement.value.parse::<i64>().ok())
+        .fold(None, |total, count| Some(total.unwrap_or(0) + count))
+}
+
+fn inference_accelerators(
+    task_definition: &TaskDefinition,
+    container_definition: &ContainerDefinition,
+) -> Vec<InferenceAcceleratorAssignment> {
+    container_definition
+        .resource_requirements
+        .iter()
+        .flatten()
+        .filter(|requirement| requirement.type_ == "InferenceAccelerator")
+        .map(|requirement| InferenceAcceleratorAssignment {
+            inference_accelerator_name: requirement.value.clone(),
+            device_type: task_definition
+                .inference_accelerators
+                .iter()
+                .flatten()
+                .find(|accelerator| accelerator.device_name == requirement.value)
+                .map(|accelerator| accelerator.device_type.clone())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+// DependsOn entry, condition is START, COMPLETE, SUCCESS or HEALTHY
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerDependency {
+    pub container_name: String,
+    pub condition: String,
+    // start_timeout of the dependent container, which applies to each of its dependencies
+    pub timeout: Option<i64>,
+}
+
+// used by the container agent when a container does not set stop_timeout
+pub const DEFAULT_STOP_TIMEOUT: i64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeMount {
+    pub source_volume: String,
+    pub container_path: String,
+    pub read_only: bool,
+}
+
+impl From<&MountPoint> for VolumeMount {
+    fn from(mount_point: &MountPoint) -> Self {
+        VolumeMount {
+            source_volume: mount_point.source_volume.clone().unwrap_or_default(),
+            container_path: mount_point.container_path.clone().unwrap_or_default(),
+            read_only: mount_point.read_only.unwrap_or(false),
+        }
+    }
+}
+
+// interval, timeout and start_period in seconds, unset values get the ECS defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerHealthCheck {
+    pub command: Vec<String>,
+    pub interval: i64,
+    pub timeout: i64,
+    pub retries: i64,
+    pub start_period: Option<i64>,
+}
+
+impl From<&HealthCheck> for ContainerHealthCheck {
+    fn from(health_check: &HealthCheck) -> Self {
+        ContainerHealthCheck {
+            command: health_check.command.clone(),
+            interval: health_check.interval.unwrap_or(30),
+            timeout: health_check.timeout.unwrap_or(5),
+            retries: health_check.retries.unwrap_or(3),
+            start_period: health_check.start_period,
+        }
+    }
+}
+
+// security relevant container settings, None on placeholder images
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContainerLinuxConfig {
+    pub privileged: bool,
+    pub add_capabilities: Vec<String>,
+    pub drop_capabilities: Vec<String>,
+    pub read_only_root_filesystem: bool,
+    pub user: Option<String>,
+}
+
+impl ContainerLinuxConfig {
+    pub fn from_container_definition(container_definition: &ContainerDefinition) -> Self {
+        let capabilities = container_definition
+            .linux_parameters
+            .as_ref()
+            .and_then(|linux_parameters| linux_parameters.capabilities.clone())
+            .unwrap_or_default();
+        ContainerLinuxConfig {
+            privileged: container_definition.privileged.unwrap_or(false),
+            add_capabilities: capabilities.add.unwrap_or_default(),
+            drop_capabilities: capabilities.drop.unwrap_or_default(),
+            read_only_root_filesystem: container_definition
+                .readonly_root_filesystem
+                .unwrap_or(false),
+            user: container_definition.user.clone(),
+        }
+    }
+}
+
+// Service and task definition level attributes, one per scanned service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceImage {
+    pub service_name: String,
+    pub cluster_arn: String,
+    pub task_definition_name: String,
+    pub scheduling_strategy: Option<String>,
+    pub launch_type: Option<String>,
+    #[serde(default)]
+    pub launch_type_source: Option<LaunchTypeSource>,
+    // None when the task definition could not be described
+    #[serde(default)]
+    pub task_definition_metadata: Option<TaskDefinitionMetadata>,
+    #[serde(default)]
+    pub service_created_at: Option<DateTime<Utc>>,
+    // DescribeServices has no service level update time, this is the latest deployment update
+    #[serde(default)]
+    pub service_updated_at: Option<DateTime<Utc>>,
+    // TASK_DEFINITION or SERVICE, ECS may also report NONE
+    #[serde(default)]
+    pub propagate_tags: Option<String>,
+    // empty for services using a launch type
+    #[serde(default)]
+    pub capacity_provider_strategy: Vec<CapacityProviderEntry>,
+    // e.g. Environment=production, Team=platform
+    #[serde(default)]
+    pub service_tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub desired_count: i64,
+}
+
+// "1024" cpu units or "1 vCPU"
+fn parse_vcpu(cpu: &str) -> Option<f64> {
+    let cpu = cpu.trim();
+    match cpu.to_lowercase().strip_suffix("vcpu") {
+        Some(vcpu) => vcpu.trim().parse().ok(),
+        None => cpu.parse::<f64>().ok().map(|units| units / 1024.0),
+    }
+}
+
+// "2048" MiB or "2 GB"
+fn parse_memory_gb(memory: &str) -> Option<f64> {
+    let memory = memory.trim();
+    match memory.to_lowercase().strip_suffix("gb") {
+        Some(gb) => gb.trim().parse().ok(),
+        None => memory.parse::<f64>().ok().map(|mib| mib / 1024.0),
+    }
+}
+
+// Fargate on-demand cost of the desired tasks, None without task level cpu and memory
+pub fn estimate_hourly_cost_usd(
+    service: &ServiceImage,
+    fargate_price_per_vcpu_hour: f64,
+    fargate_price_per_gb_hour: f64,
+) -> Option<f64> {
+    let metadata = service.task_definition_metadata.as_ref()?;
+    let vcpu = parse_vcpu(metadata.cpu.as_deref()?)?;
+    let memory_gb = parse_memory_gb(metadata.memory.as_deref()?)?;
+    let task_cost = vcpu * fargate_price_per_vcpu_hour + memory_gb * fargate_price_per_gb_hour;
+    Some(task_cost * service.desired_count as f64)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InferenceAccelerator {
+    pub device_name: String,
+    // e.g. eia2.medium
+    pub device_type: String,
+}
+
+impl From<&rusoto_ecs::InferenceAccelerator> for InferenceAccelerator {
+    fn from(accelerator: &rusoto_ecs::InferenceAccelerator) -> Self {
+        InferenceAccelerator {
+            device_name: accelerator.device_name.clone(),
+            device_type: accelerator.device_type.clone(),
+        }
+    }
+}
+
+// base tasks run on the provider first, the rest is split by weight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapacityProviderEntry {
+    pub capacity_provider: String,
+    pub weight: i64,
+    pub base: i64,
+}
+
+impl From<&CapacityProviderStrategyItem> for CapacityProviderEntry {
+    fn from(item: &CapacityProviderStrategyItem) -> Self {
+        CapacityProviderEntry {
+            capacity_provider: item.capacity_provider.clone(),
+            weight: item.weight.unwrap_or(0),
+            base: item.base.unwrap_or(0),
+        }
+    }
+}
+
+// weight share of FARGATE_SPOT in the strategy, 0.0 without a strategy
+pub fn fargate_spot_ratio(service: &ServiceImage) -> f64 {
+    let total: i64 = service
+        .capacity_provider_strategy
+        .iter()
+        .map(|entry| entry.weight)
+        .sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let spot: i64 = service
+        .capacity_provider_strategy
+        .iter()
+        .filter(|entry| entry.capacity_provider == "FARGATE_SPOT")
+        .map(|entry| entry.weight)
+        .sum();
+    spot as f64 / total as f64
+}
+
+// App Mesh proxy set on the task definition, type_ is APPMESH
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub type_: String,
+    pub container_name: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl From<&ProxyConfiguration> for ProxyConfig {
+    fn from(proxy_configuration: &ProxyConfiguration) -> Self {
+        ProxyConfig {
+            // APPMESH is the only proxy type ECS supports
+            type_: proxy_configuration
+                .type_
+                .clone()
+                .unwrap_or_else(|| "APPMESH".to_owned()),
+            container_name: proxy_configuration.container_name.clone(),
+            properties: proxy_configuration
+                .properties
+                .iter()
+                .flatten()
+                .filter_map(|property| Some((property.name.clone()?, property.value.clone()?)))
+                .collect(),
+        }
+    }
+}
+
+// ECS timestamps are fractional epoch seconds
+fn epoch_to_datetime(epoch: f64) -> Option<DateTime<Utc>> {
+    let secs = epoch.trunc() as i64;
+    let nanos = (epoch.fract() * 1_000_000_000.0) as u32;
+    Utc.timestamp_opt(secs, nanos).single()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskDefinitionMetadata {
+    // bridge, host, awsvpc or none, unset means bridge on Linux
+    pub network_mode: Option<String>,
+    #[serde(default)]
+    pub volumes: Vec<TaskVolume>,
+    #[serde(default)]
+    pub proxy_configuration: Option<ProxyConfig>,
+    #[serde(default)]
+    pub ipc_mode: Option<String>,
+    #[serde(default)]
+    pub pid_mode: Option<String>,
+    // IAM role assumed by the containers of the task
+    #[serde(default)]
+    pub task_role_arn: Option<String>,
+    // Elastic Inference accelerators declared by the task definition
+    #[serde(default)]
+    pub inference_accelerators: Vec<InferenceAccelerator>,
+    // container instance attributes the task needs, e.g. ecs.capability.task-eni
+    #[serde(default)]
+    pub requires_attributes: Vec<String>,
+    // memberOf cluster query language expressions
+    #[serde(default)]
+    pub placement_constraints: Vec<String>,
+    // cpu units or "0.5 vCPU", memory in MiB or "1 GB", required on Fargate
+    #[serde(default)]
+    pub cpu: Option<String>,
+    #[serde(default)]
+    pub memory: Option<String>,
+}
+
+impl From<&TaskDefinition> for TaskDefinitionMetadata {
+    fn from(task_definition: &TaskDefinition) -> Self {
+        TaskDefinitionMetadata {
+            network_mode: task_definition.network_mode.clone(),
+            volumes: task_definition
+                .volumes
+                .iter()
+                .flatten()
+                .map(TaskVolume::from)
+                .collect(),
+            proxy_configuration: task_definition
+                .proxy_configuration
+                .as_ref()
+                .map(ProxyConfig::from),
+            ipc_mode: task_definition.ipc_mode.clone(),
+            pid_mode: task_definition.pid_mode.clone(),
+            task_role_arn: task_definition.task_role_arn.clone(),
+            inference_accelerators: task_definition
+                .inference_accelerators
+                .iter()
+                .flatten()
+                .map(InferenceAccelerator::from)
+                .collect(),
+            requires_attributes: task_definition
+                .requires_attributes
+                .iter()
+                .flatten()
+                .map(|attribute| attribute.name.clone())
+                .collect(),
+            placement_constraints: task_definition
+                .placement_constraints
+                .iter()
+                .flatten()
+                .filter_map(|constraint| constraint.expression.clone())
+                .collect(),
+            cpu: task_definition.cpu.clone(),
+            memory: task_definition.memory.clone(),
+        }
+    }
+}
+
+// where the service tasks run, also resolved for services using a capacity provider strategy
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LaunchTypeSource {
+    Fargate,
+    Ec2,
+    External,
+}
+
+impl LaunchTypeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LaunchTypeSource::Fargate => "FARGATE",
+            LaunchTypeSource::Ec2 => "EC2",
+            LaunchTypeSource::External => "EXTERNAL",
+        }
+    }
+
+    pub fn of(service: &Service) -> Option<Self> {
+        match service.launch_type.as_deref() {
+            Some("FARGATE") => return Some(LaunchTypeSource::Fargate),
+            Some("EC2") => return Some(LaunchTypeSource::Ec2),
+            Some("EXTERNAL") => return Some(LaunchTypeSource::External),
+            _ => {}
+        }
+        // FARGATE and FARGATE_SPOT are the only managed providers, others are EC2 auto scaling groups
+        let strategy = service.capacity_provider_strategy.as_ref()?;
+        let provider = strategy.first()?;
+        if provider.capacity_provider.starts_with("FARGATE") {
+            Some(LaunchTypeSource::Fargate)
+        } else {
+            Some(LaunchTypeSource::Ec2)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskVolume {
+    pub name: String,
+    pub host_path: Option<String>,
+    pub efs_volume_configuration: Option<EfsConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EfsConfig {
+    pub file_system_id: String,
+    pub root_directory: Option<String>,
+}
+
+impl From<&Volume> for TaskVolume {
+    fn from(volume: &Volume) -> Self {
+        TaskVolume {
+            name: volume.name.clone().unwrap_or_default(),
+            host_path: volume
+                .host
+                .as_ref()
+                .and_then(|host| host.source_path.clone()),
+            efs_volume_configuration: volume.efs_volume_configuration.as_ref().map(|efs| {
+                EfsConfig {
+                    file_system_id: efs.file_system_id.clone(),
+                    root_directory: efs.root_directory.clone(),
+                }
+            }),
+        }
+    }
+}
+
+impl ServiceImage {
+    // task level fields stay empty when the task definition could not be described
+    pub fn from_service(service: &Service, task_definition: Option<&TaskDefinition>) -> Self {
+        ServiceImage {
+            service_name: service.service_name.clone().unwrap_or_default(),
+            cluster_arn: service.cluster_arn.clone().unwrap_or_default(),
+            task_definition_name: service.task_definition.clone().unwrap_or_default(),
+            scheduling_strategy: service.scheduling_strategy.clone(),
+            launch_type: service.launch_type.clone(),
+            launch_type_source: LaunchTypeSource::of(service),
+            task_definition_metadata: task_definition.map(TaskDefinitionMetadata::from),
+            service_created_at: service.created_at.and_then(epoch_to_datetime),
+            service_updated_at: service
+                .deployments
+                .iter()
+                .flatten()
+                .filter_map(|deployment| deployment.updated_at)
+                .fold(None, |latest: Option<f64>, updated_at| {
+                    Some(latest.map_or(updated_at, |latest| latest.max(updated_at)))
+                })
+                .and_then(epoch_to_datetime),
+            propagate_tags: service.propagate_tags.clone(),
+            capacity_provider_strategy: service
+                .capacity_provider_strategy
+                .iter()
+                .flatten()
+                .map(CapacityProviderEntry::from)
+                .collect(),
+            service_tags: ecs::tags_to_map(service.tags.as_deref()),
+            desired_count: service.desired_count.unwrap_or(0),
+        }
+    }
+
+    pub fn is_daemon(&self) -> bool {
+        self.scheduling_strategy.as_deref() == Some("DAEMON")
+    }
+}
+
+// returns (replica, daemon) services, services without a strategy default to REPLICA
+pub fn partition_by_scheduling_strategy(
+    images: &[ServiceImage],
+) -> (Vec<&ServiceImage>, Vec<&ServiceImage>) {
+    images.iter().partition(|service| !service.is_daemon())
+}
+
+// services on a capacity provider strategy have no launch type, theirs is derived from the
+// strategy, see LaunchTypeSource::of
+pub fn find_services_by_launch_type(images: &[ServiceImage], lt: LaunchType) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| match service.launch_type.as_deref() {
+            Some(launch_type) => launch_type == lt.as_str(),
+            None => service
+                .launch_type_source
+                .is_some_and(|source| source.as_str() == lt.as_str()),
+        })
+        .collect()
+}
+
+// services not updated within threshold, falling back on the creation time,
+// services without any timestamp are left out
+pub fn find_long_unchanged_services(
+    images: &[ServiceImage],
+    threshold: Duration,
+) -> Vec<&ServiceImage> {
+    let threshold =
+        chrono::Duration::from_std(threshold).unwrap_or_else(|_| chrono::Duration::max_value());
+    let now = Utc::now();
+    images
+        .iter()
+        .filter(|service| {
+            service
+                .service_updated_at
+                .or(service.service_created_at)
+                .is_some_and(|changed_at| now.signed_duration_since(changed_at) > threshold)
+        })
+        .collect()
+}
+
+// service name and task role ARN of the services running with a task role
+pub fn get_task_roles(images: &[ServiceImage]) -> Vec<(&str, &str)> {
+    images
+        .iter()
+        .filter_map(|service| {
+            service
+                .task_definition_metadata
+                .as_ref()?
+                .task_role_arn
+                .as_deref()
+                .map(|task_role_arn| (service.service_name.as_str(), task_role_arn))
+        })
+        .collect()
+}
+
+// "host" shares the namespace with the instance, "task" between the containers of a task
+fn is_shared_namespace(mode: Option<&str>) -> bool {
+    matches!(mode, Some("host") | Some("task"))
+}
+
+// None when the task definition could not be described
+fn task_definition_metadata(service: &ServiceImage) -> Option<&TaskDefinitionMetadata> {
+    service.task_definition_metadata.as_ref()
+}
+
+pub fn find_services_with_shared_pid_namespace(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            is_shared_namespace(
+                task_definition_metadata(service).and_then(|metadata| metadata.pid_mode.as_deref()),
+            )
+        })
+        .collect()
+}
+
+// unlike "task", "host" lets the containers see every process of the instance
+pub fn find_services_with_host_pid_mode(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service).and_then(|metadata| metadata.pid_mode.as_deref())
+                == Some("host")
+        })
+        .collect()
+}
+
+pub fn find_services_with_shared_ipc(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            is_shared_namespace(
+                task_definition_metadata(service).and_then(|metadata| metadata.ipc_mode.as_deref()),
+            )
+        })
+        .collect()
+}
+
+// any value of the tag when value is None
+pub fn find_services_with_tag<'a>(
+    images: &'a [ServiceImage],
+    key: &str,
+    value: Option<&str>,
+) -> Vec<&'a ServiceImage> {
+    images
+        .iter()
+        .filter(|service| match (service.service_tags.get(key), value) {
+            (Some(tag_value), Some(value)) => tag_value == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+        .collect()
+}
+
+pub fn find_services_requiring_attribute<'a>(
+    images: &'a [ServiceImage],
+    attribute: &str,
+) -> Vec<&'a ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service).is_some_and(|metadata| {
+                metadata
+                    .requires_attributes
+                    .iter()
+                    .any(|name| name == attribute)
+            })
+        })
+        .collect()
+}
+
+pub fn find_services_with_az_placement_constraints(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service).is_some_and(|metadata| {
+                metadata
+                    .placement_constraints
+                    .iter()
+                    .any(|expression| expression.contains("attribute:ecs.availability-zone"))
+            })
+        })
+        .collect()
+}
+
+pub fn find_services_using_elastic_inference(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service)
+                .is_some_and(|metadata| !metadata.inference_accelerators.is_empty())
+        })
+        .collect()
+}
+
+pub fn find_services_with_app_mesh(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service)
+                .and_then(|metadata| metadata.proxy_configuration.as_ref())
+                .is_some_and(|proxy| proxy.type_ == "APPMESH")
+        })
+        .collect()
+}
+
+// tasks of these services do not carry the service or task definition cost allocation tags
+pub fn find_services_without_tag_propagation(images: &[ServiceImage]) -> Vec<&ServiceImage> {
+    images
+        .iter()
+        .filter(|service| matches!(service.propagate_tags.as_deref(), None | Some("NONE")))
+        .collect()
+}
+
+pub fn find_services_in_network_mode<'a>(
+    images: &'a [ServiceImage],
+    mode: &str,
+) -> Vec<&'a ServiceImage> {
+    images
+        .iter()
+        .filter(|service| {
+            task_definition_metadata(service).and_then(|metadata| metadata.network_mode.as_deref())
+                == Some(mode)
+        })
+        .collect()
+}
+
+impl Image {
+    pub fn from_task_definition(
+        task_definition: &TaskDefinition,
+        cluster_arn: String,
+        service_name: String,
+    ) -> Option<Self> {
+        let container_definition = task_definition.container_definitions.as_ref()?.last()?;
+        Self::from_container_definition(
+            task_definition,
+            container_definition,
+            cluster_arn,
+            service_name,
+        )
+    }
+
+    // one image per container definition, sidecars such as log routers included
+    pub fn containers_of_task_definition(
+        task_definition: &TaskDefinition,
+        cluster_arn: &str,
+        service_name: &str,
+    ) -> Vec<Self> {
+        task_definition
+            .container_definitions
+            .iter()
+            .flatten()
+            .filter_map(|container_definition| {
+                Self::from_container_definition(
+                    task_definition,
+                    container_definition,
+                    cluster_arn.to_owned(),
+                    service_name.to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    fn from_container_definition(
+        task_definition: &TaskDefinition,
+        container_definition: &ContainerDefinition,
+        cluster_arn: String,
+        service_name: String,
+    ) -> Option<Self> {
+        let td_arn = task_definition.task_definition_arn.clone()?;
+        container_definition.image.clone().map(|i| Image {
+            cluster_name: arn::cluster_short_name(&cluster_arn).to_owned(),
+            cluster_arn,
+            image_name: i,
+            task_definition_name: td_arn,
+            service_name,
+            service_arn: String::new(),
+            container_name: container_definition.name.clone().unwrap_or_default(),
+            enriched_image_name: None,
+            cpu_limit: container_definition.cpu,
+            memory_limit: container_definition.memory,
+            memory_reservation: container_definition.memory_reservation,
+            warnings: Vec::new(),
+            critical_cves: Vec::new(),
+            labels: container_definition
+                .docker_labels
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            linux_parameters: Some(ContainerLinuxConfig::from_container_definition(
+                container_definition,
+            )),
+            health_check: container_definition
+                .health_check
+                .as_ref()
+                .map(ContainerHealthCheck::from),
+            volume_mounts: container_definition
+                .mount_points
+                .as_ref()
+                .map(|mount_points| mount_points.iter().map(VolumeMount::from).collect())
+                .unwrap_or_default(),
+            working_directory: container_definition.working_directory.clone(),
+            task_definition_status: task_definition.status.clone().unwrap_or_default(),
+            stop_timeout: container_definition.stop_timeout,
+            start_timeout: container_definition.start_timeout,
+            container_dependencies: container_definition
+                .depends_on
+                .iter()
+                .flatten()
+                .map(|dependency| ContainerDependency {
+                    container_name: dependency.container_name.clone(),
+                    condition: dependency.condition.clone(),
+                    timeout: container_definition.start_timeout,
+                })
+                .collect(),
+            gpu_count: gpu_count(container_definition),
+            inference_accelerators: inference_accelerators(task_definition, container_definition),
+            system_controls: container_definition
+                .system_controls
+                .iter()
+                .flatten()
+                .map(SystemControl::from)
+                .collect(),
+            ulimits: container_definition
+                .ulimits
+                .iter()
+                .flatten()
+                .map(Ulimit::from)
+                .collect(),
+            uses_firelens_log_driver: container_definition
+                .log_configuration
+                .as_ref()
+                .is_some_and(|log_configuration| log_configuration.log_driver == "awsfirelens"),
+            firelens_router_type: container_definition
+                .firelens_configuration
+                .as_ref()
+                .map(|firelens_configuration| firelens_configuration.type_.clone()),
+            secrets: container_definition
+                .secrets
+                .iter()
+                .flatten()
+                .map(SecretRef::from)
+                .collect(),
+            environment_secrets: secrets::get_secret_references(
+                &secrets::get_container_environment_variables(container_definition),
+            ),
+            extra_hosts: container_definition
+                .extra_hosts
+                .iter()
+                .flatten()
+                .map(HostEntry::from)
+                .collect(),
+            dns_servers: container_definition.dns_servers.clone().unwrap_or_default(),
+            dns_search_domains: container_definition
+                .dns_search_domains
+                .clone()
+                .unwrap_or_default(),
+            links: container_definition.links.clone().unwrap_or_default(),
+            volumes_from: container_definition
+                .volumes_from
+                .iter()
+                .flatten()
+                .map(VolumeFrom::from)
+                .collect(),
+            repository_credentials_arn: container_definition
+                .repository_credentials
+                .as_ref()
+                .map(|credentials| credentials.credentials_parameter.clone()),
+            container_hostname: container_definition.hostname.clone(),
+            disable_networking: container_definition.disable_networking,
+            interactive: container_definition.interactive,
+            pseudo_terminal: container_definition.pseudo_terminal,
+            memory_swap: container_definition
+                .linux_parameters
+                .as_ref()
+                .and_then(|linux_parameters| linux_parameters.max_swap),
+            memory_swappiness: container_definition
+                .linux_parameters
+                .as_ref()
+                .and_then(|linux_parameters| linux_parameters.swappiness),
+            shm_size: container_definition
+                .linux_parameters
+                .as_ref()
+                .and_then(|linux_parameters| linux_parameters.shared_memory_size),
+            devices: container_definition
+                .linux_parameters
+                .iter()
+                .flat_map(|linux_parameters| linux_parameters.devices.iter().flatten())
+                .map(DeviceMapping::from)
+                .collect(),
+            // set by the scan from the DescribeTaskDefinition response
+            task_definition_tags: BTreeMap::new(),
+            environment_files: container_definition
+                .environment_files
+                .iter()
+                .flatten()
+                .map(EnvFileRef::from)
+                .collect(),
+        })
+    }
+
+    // placeholder for services still referencing an inactive task definition
+    pub fn deregistered(
+        task_definition: &TaskDefinition,
+        cluster_arn: String,
+        service_name: String,
+    ) -> Self {
+        Image {
+            cluster_name: arn::cluster_short_name(&cluster_arn).to_owned(),
+            cluster_arn,
+            image_name: DEREGISTERED_IMAGE_NAME.to_owned(),
+            task_definition_name: task_definition
+                .task_definition_arn
+                .clone()
+                .unwrap_or_default(),
+            service_name,
+            task_definition_status: task_definition.status.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    // placeholders of deregistered task definitions carry no container, only the service
+    // and task definition they were found for
+    pub fn is_placeholder(&self) -> bool {
+        self.image_name == DEREGISTERED_IMAGE_NAME
+    }
+
+    // untagged images resolve to latest at pull time
+    pub fn uses_latest_tag(&self) -> bool {
+        if self.is_placeholder() {
+            return false;
+        }
+        let reference = parse_image_reference(&self.image_name);
+        reference.digest.is_none() && reference.tag.unwrap_or("latest") == "latest"
+    }
+
+    // column names matching the [String; 4] conversions
+    pub fn columns() -> [&'static str; 4] {
+        ["cluster", "service", "task_definition", "image"]
+    }
+
+    pub fn sort_key(&self) -> (&str, &str, &str) {
+        (&self.service_name, &self.container_name, &self.image_name)
+    }
+
+    pub fn display_name(&self) -> String {
+        format!(
+            "{} @ {}",
+            self.service_name,
+            arn::task_definition_short_name(&self.task_definition_name)
+        )
+    }
+}
+
+// repository[:tag][@digest], the repository may carry a registry host with a port
+#[derive(Debug, PartialEq)]
+pub struct ImageReference<'a> {
+    pub repository: &'a str,
+    pub tag: Option<&'a str>,
+    pub digest: Option<&'a str>,
+}
+
+pub fn parse_image_reference(image_name: &str) -> ImageReference<'_> {
+    let (name, digest) = match image_name.find('@') {
+        Some(idx) => (&image_name[..idx], Some(&image_name[idx + 1..])),
+        None => (image_name, None),
+    };
+    let last_segment_start = name.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+    match name[last_segment_start..].rfind(':') {
+        Some(idx) => ImageReference {
+            repository: &name[..last_segment_start + idx],
+            tag: Some(&name[last_segment_start + idx + 1..]),
+            digest,
+        },
+        None => ImageReference {
+            repository: name,
+            tag: None,
+            digest,
+        },
+    }
+}
+
+// images pinned by digest only, e.g. repo@sha256:abc
+pub fn find_digest_only_images(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            let reference = parse_image_reference(&image.image_name);
+            reference.tag.is_none()
+                && reference
+                    .digest
+                    .is_some_and(|digest| digest.starts_with("sha256:"))
+        })
+        .collect()
+}
+
+// bare image names, resolved to latest at pull time
+pub fn find_untagged_digest_images(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            let reference = parse_image_reference(&image.image_name);
+            !image.is_placeholder() && reference.tag.is_none() && reference.digest.is_none()
+        })
+        .collect()
+}
+
+pub fn find_images_with_inactive_task_definition(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.task_definition_status == "INACTIVE")
+        .collect()
+}
+
+pub fn find_images_without_health_check(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.is_placeholder() && image.health_check.is_none())
+        .collect()
+}
+
+pub fn find_containers_with_short_stop_timeout(images: &[Image], min_seconds: i64) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            !image.is_placeholder()
+                && image.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT) < min_seconds
+        })
+        .collect()
+}
+
+// dependencies usually start sidecars, pass every container, e.g. ClusterScanResult::containers
+pub fn find_containers_with_tight_start_timeout(images: &[Image], threshold: i64) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .start_timeout
+                .is_some_and(|timeout| timeout < threshold)
+        })
+        .collect()
+}
+
+pub fn find_gpu_containers(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.gpu_count.is_some_and(|count| count > 0))
+        .collect()
+}
+
+pub fn find_containers_with_sysctl<'a>(
+    images: &'a [Image],
+    namespace_prefix: &str,
+) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .system_controls
+                .iter()
+                .any(|system_control| system_control.namespace.starts_with(namespace_prefix))
+        })
+        .collect()
+}
+
+pub fn find_containers_with_ulimit<'a>(images: &'a [Image], name: &str) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|image| image.ulimits.iter().any(|ulimit| ulimit.name == name))
+        .collect()
+}
+
+// containers whose soft nofile limit is at least the threshold
+pub fn find_containers_with_high_nofile(images: &[Image], threshold: i64) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .ulimits
+                .iter()
+                .any(|ulimit| ulimit.name == "nofile" && ulimit.soft_limit >= threshold)
+        })
+        .collect()
+}
+
+pub fn find_containers_routing_to_firelens(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.uses_firelens_log_driver && image.firelens_router_type.is_none())
+        .collect()
+}
+
+// the router is a sidecar, pass every container, e.g. ClusterScanResult::containers
+pub fn find_firelens_router_containers(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.firelens_router_type.is_some())
+        .collect()
+}
+
+pub fn find_containers_with_extra_hosts(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.extra_hosts.is_empty())
+        .collect()
+}
+
+pub fn find_containers_with_custom_dns(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.dns_servers.is_empty())
+        .collect()
+}
+
+pub fn find_containers_using_links(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.links.is_empty())
+        .collect()
+}
+
+// the sources are sibling containers, pass them all, e.g. ClusterScanResult::containers
+pub fn find_containers_mounting_volumes_from_others(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.volumes_from.is_empty())
+        .collect()
+}
+
+pub fn find_containers_with_private_registry_auth(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.repository_credentials_arn.is_some())
+        .collect()
+}
+
+pub fn find_containers_with_disabled_networking(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.disable_networking == Some(true))
+        .collect()
+}
+
+pub fn find_interactive_containers(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.interactive == Some(true) || image.pseudo_terminal == Some(true))
+        .collect()
+}
+
+pub fn find_containers_with_swap_enabled(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| image.memory_swap.is_some_and(|max_swap| max_swap > 0))
+        .collect()
+}
+
+pub fn find_containers_with_large_shared_memory(
+    images: &[Image],
+    threshold_mib: i64,
+) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .shm_size
+                .is_some_and(|shm_size| shm_size > threshold_mib)
+        })
+        .collect()
+}
+
+pub fn find_containers_with_host_devices(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| !image.devices.is_empty())
+        .collect()
+}
+
+// any value of the tag when value is None
+pub fn find_images_with_task_definition_tag<'a>(
+    images: &'a [Image],
+    key: &str,
+    value: Option<&str>,
+) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|image| match (image.task_definition_tags.get(key), value) {
+            (Some(tag_value), Some(value)) => tag_value == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+        .collect()
+}
+
+pub fn find_containers_with_s3_env_files(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .environment_files
+                .iter()
+                .any(|file| file.type_ == "s3")
+        })
+        .collect()
+}
+
+pub fn find_containers_with_working_directory<'a>(
+    images: &'a [Image],
+    path: &str,
+) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|image| image.working_directory.as_deref() == Some(path))
+        .collect()
+}
+
+pub fn find_privileged_containers(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .linux_parameters
+                .as_ref()
+                .is_some_and(|linux| linux.privileged)
+        })
+        .collect()
+}
+
+// capabilities are matched with or without the CAP_ prefix, e.g. SYS_ADMIN or CAP_SYS_ADMIN
+pub fn find_containers_with_capability<'a>(images: &'a [Image], cap: &str) -> Vec<&'a Image> {
+    let cap = cap.trim_start_matches("CAP_");
+    images
+        .iter()
+        .filter(|image| {
+            image.linux_parameters.as_ref().is_some_and(|linux| {
+                linux
+                    .add_capabilities
+                    .iter()
+                    .any(|added| added.trim_start_matches("CAP_") == cap)
+            })
+        })
+        .collect()
+}
+
+pub fn service_account_id(image: &Image) -> Option<String> {
+    arn::account_id(&image.service_arn).map(str::to_owned)
+}
+
+// images carrying the label key, with the given value if any
+pub fn find_images_with_label<'a>(
+    images: &'a [Image],
+    key: &str,
+    value: Option<&str>,
+) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|image| match (image.labels.get(key), value) {
+            (Some(label_value), Some(value)) => label_value == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+        .collect()
+}
+
+// MiB reserved by the containers that set a soft memory limit
+pub fn sum_reserved_memory(images: &[Image]) -> i64 {
+    images
+        .iter()
+        .filter_map(|image| image.memory_reservation)
+        .sum()
+}
+
+// changes between two scans, images are matched on (cluster, service, container)
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImageDiff {
+    pub added: Vec<Image>,
+    pub removed: Vec<Image>,
+    // (before, after) pairs whose image_name changed
+    pub changed: Vec<(Image, Image)>,
+}
+
+impl ImageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.added.len() + self.removed.len() + self.changed.len()
+    }
+}
+
+pub fn diff_images(before: &[Image], after: &[Image]) -> ImageDiff {
+    fn key(image: &Image) -> (&str, &str, &str) {
+        (
+            &image.cluster_arn,
+            &image.service_name,
+            &image.container_name,
+        )
+    }
+    let before_by_key: HashMap<_, &Image> = before.iter().map(|i| (key(i), i)).collect();
+    let after_by_key: HashMap<_, &Image> = after.iter().map(|i| (key(i), i)).collect();
+
+    let mut diff = ImageDiff::default();
+    for image in after {
+        match before_by_key.get(&key(image)) {
+            None => diff.added.push(image.clone()),
+            Some(previous) if previous.image_name != image.image_name => {
+                diff.changed.push(((*previous).clone(), image.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    diff.removed = before
+        .iter()
+        .filter(|image| !after_by_key.contains_key(&key(image)))
+        .cloned()
+        .collect();
+    diff
+}
+
+// keeps the first image of each (image_name, task_definition_name) pair, in order
+pub fn deduplicate_images(images: Vec<Image>) -> Vec<Image> {
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    images
+        .into_iter()
+        .filter(|image| seen.insert((image.image_name.clone(), image.task_definition_name.clone())))
+        .collect()
+}
+
+// placeholders share one name but stand for different services, they are all kept
+pub fn deduplicate_by_image_name_only(images: Vec<Image>) -> Vec<Image> {
+    let mut seen: HashSet<String> = HashSet::new();
+    images
+        .into_iter()
+        .filter(|image| image.is_placeholder() || seen.insert(image.image_name.clone()))
+        .collect()
+}
+
+impl From<&Image> for [String; 4] {
+    fn from(image: &Image) -> Self {
+        [
+            image.cluster_arn.clone(),
+            image.service_name.clone(),
+            image.task_definition_name.clone(),
+            image.image_name.clone(),
+        ]
+    }
+}
+
+impl TryFrom<[String; 4]> for Image {
+    type Error = anyhow::Error;
+
+    fn try_from(row: [String; 4]) -> Result<Self, Self::Error> {
+        if let Some(idx) = row.iter().position(|value| value.is_empty()) {
+            Err(anyhow!("Empty {} column", Image::columns()[idx]))?;
+        }
+        let [cluster_arn, service_name, task_definition_name, image_name] = row;
+        Ok(Image {
+            cluster_name: arn::cluster_short_name(&cluster_arn).to_owned(),
+            cluster_arn,
+            service_name,
+            task_definition_name,
+            image_name,
+            ..Default::default()
+        })
+    }
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (service: {}, task: {})",
+            self.image_name, self.service_name, self.task_definition_name
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageDisplayStyle {
+    Compact,
+    Detailed,
+    Json,
+}
+
+fn image_to_json(image: &Image) -> serde_json::Value {
+    // only fails for maps with non string keys, Image has none
+    serde_json::to_value(image).expect("Image serializes to JSON")
+}
+
+// one "field: value" line per populated field, the identifying fields first
+fn format_detailed(image: &Image) -> String {
+    const LEADING_FIELDS: [&str; 4] = [
+        "image_name",
+        "container_name",
+        "service_name",
+        "task_definition_name",
+    ];
+    let fields = match image_to_json(image) {
+        serde_json::Value::Object(fields) => fields,
+        _ => unreachable!("Image serializes to a JSON object"),
+    };
+    let is_populated = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Array(values) => !values.is_empty(),
+        serde_json::Value::Object(fields) => !fields.is_empty(),
+        _ => true,
+    };
+    let leading = LEADING_FIELDS
+        .iter()
+        .filter_map(|name| fields.get(*name).map(|value| (*name, value)));
+    let rest = fields
+        .iter()
+        .filter(|(name, _)| !LEADING_FIELDS.contains(&name.as_str()))
+        .map(|(name, value)| (name.as_str(), value));
+    leading
+        .chain(rest)
+        .filter(|(_, value)| is_populated(value))
+        .map(|(name, value)| match value {
+            serde_json::Value::String(s) => format!("{}: {}", name.replace('_', " "), s),
+            value => format!("{}: {}", name.replace('_', " "), value),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn format_image(image: &Image, style: ImageDisplayStyle) -> String {
+    match style {
+        ImageDisplayStyle::Compact => image.to_string(),
+        ImageDisplayStyle::Detailed => format_detailed(image),
+        ImageDisplayStyle::Json => {
+            let mut value = image_to_json(image);
+            // keep both the full arn and the short name for downstream tools
+            value["task_definition_short_name"] =
+                arn::task_definition_short_name(&image.task_definition_name).into();
+            value.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{container, task_definition};
+    use rusoto_ecs::CapacityProviderStrategyItem;
+    use rusoto_ecs::ContainerDefinition;
+    use rusoto_ecs::ContainerDependency as DependsOn;
+    use rusoto_ecs::Deployment;
+    use rusoto_ecs::KeyValuePair;
+    use rusoto_ecs::RepositoryCredentials;
+    use rusoto_ecs::{FirelensConfiguration, LogConfiguration};
+    use rusoto_ecs::{InferenceAccelerator, ResourceRequirement};
+    use rusoto_ecs::{KernelCapabilities, LinuxParameters};
+
+    fn web_image() -> Image {
+        Image {
+            image_name: "nginx:1.19".to_owned(),
+            task_definition_name: "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3"
+                .to_owned(),
+            service_name: "web".to_owned(),
+            container_name: "nginx".to_owned(),
+            cluster_arn: "arn:aws:ecs:eu-west-1:123456789012:cluster/prod".to_owned(),
+            cluster_name: "prod".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compact_style_fits_on_one_line() {
+        let compact = format_image(&web_image(), ImageDisplayStyle::Compact);
+        assert_eq!(
+            compact,
+            "nginx:1.19 (service: web, task: arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3)"
+        );
+        assert!(!compact.contains('\n'));
+        assert_eq!(compact, web_image().to_string());
+    }
+
+    #[test]
+    fn detailed_style_has_one_line_per_populated_field() {
+        assert_eq!(
+            format_image(&web_image(), ImageDisplayStyle::Detailed),
+            "image name: nginx:1.19\n\
+             container name: nginx\n\
+             service name: web\n\
+             task definition name: arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3\n\
+             cluster arn: arn:aws:ecs:eu-west-1:123456789012:cluster/prod\n\
+             cluster name: prod\n\
+             uses firelens log driver: false"
+        );
+    }
+
+    #[test]
+    fn detailed_style_shows_nested_values_as_compact_json() {
+        let image = Image {
+            volume_mounts: vec![VolumeMount {
+                source_volume: "shared".to_owned(),
+                container_path: "/data".to_owned(),
+                read_only: true,
+            }],
+            ..web_image()
+        };
+        let detailed = format_image(&image, ImageDisplayStyle::Detailed);
+
+        assert!(detailed.starts_with("image name: nginx:1.19\ncontainer name: nginx\n"));
+        assert!(detailed.contains("\nvolume mounts: [{\"container_path\":\"/data\",\"read_only\":true,\"source_volume\":\"shared\"}]"));
+        assert!(!detailed.contains("enriched image name"));
+    }
+
+    #[test]
+    fn json_style_round_trips() {
+        let json = format_image(&web_image(), ImageDisplayStyle::Json);
+        let parsed: Image = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.image_name, "nginx:1.19");
+        assert_eq!(parsed.service_name, "web");
+        assert_eq!(
+            parsed.task_definition_name,
+            web_image().task_definition_name
+        );
+    }
+
+    #[test]
+    fn display_name_of_arn_with_path() {
+        assert_eq!(web_image().display_name(), "web @ web:3");
+    }
+
+    #[test]
+    fn display_name_of_name_without_path() {
+        let mut image = web_image();
+        image.task_definition_name = "web:3".to_owned();
+        assert_eq!(image.display_name(), "web @ web:3");
+        image.task_definition_name = "web".to_owned();
+        assert_eq!(image.display_name(), "web @ web");
+    }
+
+    #[test]
+    fn json_style_carries_the_task_definition_short_name() {
+        let json = format_image(&web_image(), ImageDisplayStyle::Json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["task_definition_short_name"], "web:3");
+        assert_eq!(
+            value["task_definition_name"],
+            web_image().task_definition_name
+        );
+    }
+
+    #[test]
+    fn row_conversions_are_inverse() {
+        let row: [String; 4] = (&web_image()).into();
+        assert_eq!(
+            row,
+            [
+                "arn:aws:ecs:eu-west-1:123456789012:cluster/prod".to_owned(),
+                "web".to_owned(),
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3".to_owned(),
+                "nginx:1.19".to_owned(),
+            ]
+        );
+
+        let image = Image::try_from(row.clone()).unwrap();
+        assert_eq!(<[String; 4]>::from(&image), row);
+    }
+
+    #[test]
+    fn row_with_empty_service_is_rejected() {
+        let mut row: [String; 4] = (&web_image()).into();
+        row[1] = String::new();
+        let err = Image::try_from(row).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Empty {} column", Image::columns()[1])
+        );
+    }
+
+    #[test]
+    fn columns_follow_the_row_order() {
+        assert_eq!(
+            Image::columns(),
+            ["cluster", "service", "task_definition", "image"]
+        );
+    }
+
+    const ECR_REPOSITORY: &str = "123456789012.dkr.ecr.eu-west-1.amazonaws.com/web";
+
+    fn images_named(image_names: &[String]) -> Vec<Image> {
+        image_names
+            .iter()
+            .map(|image_name| Image {
+                image_name: image_name.clone(),
+                ..web_image()
+            })
+            .collect()
+    }
+
+    fn names<'a>(images: &[&'a Image]) -> Vec<&'a str> {
+        images
+            .iter()
+            .map(|image| image.image_name.as_str())
+            .collect()
+    }
+
+    // the four kinds of references, for a docker hub and an ECR repository
+    fn references(repository: &str) -> Vec<String> {
+        vec![
+            format!("{}@sha256:abc", repository),
+            format!("{}:1.19", repository),
+            format!("{}:1.19@sha256:abc", repository),
+            repository.to_owned(),
+        ]
+    }
+
+    #[test]
+    fn parse_image_reference_with_tag_and_digest() {
+        assert_eq!(
+            parse_image_reference("nginx"),
+            ImageReference {
+                repository: "nginx",
+                tag: None,
+                digest: None
+            }
+        );
+        assert_eq!(
+            parse_image_reference("nginx:1.19@sha256:abc"),
+            ImageReference {
+                repository: "nginx",
+                tag: Some("1.19"),
+                digest: Some("sha256:abc")
+            }
+        );
+        assert_eq!(
+            parse_image_reference("nginx@sha256:abc"),
+            ImageReference {
+                repository: "nginx",
+                tag: None,
+                digest: Some("sha256:abc")
+            }
+        );
+    }
+
+    #[test]
+    fn parse_image_reference_with_registry_port() {
+        assert_eq!(
+            parse_image_reference("registry.local:5000/team/app"),
+            ImageReference {
+                repository: "registry.local:5000/team/app",
+                tag: None,
+                digest: None
+            }
+        );
+        assert_eq!(
+            parse_image_reference("registry.local:5000/team/app:v2"),
+            ImageReference {
+                repository: "registry.local:5000/team/app",
+                tag: Some("v2"),
+                digest: None
+            }
+        );
+    }
+
+    #[test]
+    fn digest_only_images() {
+        for repository in &["nginx", ECR_REPOSITORY] {
+            let images = images_named(&references(repository));
+            assert_eq!(
+                names(&find_digest_only_images(&images)),
+                vec![format!("{}@sha256:abc", repository)]
+            );
+        }
+    }
+
+    #[test]
+    fn untagged_images_without_digest() {
+        for repository in &["nginx", ECR_REPOSITORY] {
+            let images = images_named(&references(repository));
+            assert_eq!(
+                names(&find_untagged_digest_images(&images)),
+                vec![*repository]
+            );
+        }
+    }
+
+    fn deployed(image_name: &str, task_definition_name: &str, service_name: &str) -> Image {
+        Image {
+            image_name: image_name.to_owned(),
+            task_definition_name: task_definition_name.to_owned(),
+            service_name: service_name.to_owned(),
+            ..web_image()
+        }
+    }
+
+    #[test]
+    fn ten_duplicates_collapse_to_one() {
+        let images: Vec<Image> = (0..10)
+            .map(|i| deployed("nginx:1.19", "web:3", &format!("web-{}", i)))
+            .collect();
+
+        let deduplicated = deduplicate_images(images.clone());
+        assert_eq!(deduplicated.len(), 1);
+        assert_eq!(deduplicated[0].service_name, "web-0");
+        assert_eq!(deduplicate_by_image_name_only(images).len(), 1);
+    }
+
+    #[test]
+    fn same_image_in_different_task_definitions() {
+        let images = vec![
+            deployed("nginx:1.19", "web:3", "web"),
+            deployed("envoy:1.16", "web:3", "web"),
+            deployed("nginx:1.19", "admin:1", "admin"),
+            deployed("nginx:1.19", "web:3", "web-canary"),
+        ];
+
+        let by_pair: Vec<(String, String)> = deduplicate_images(images.clone())
+            .into_iter()
+            .map(|image| (image.image_name, image.service_name))
+            .collect();
+        assert_eq!(
+            by_pair,
+            vec![
+                ("nginx:1.19".to_owned(), "web".to_owned()),
+                ("envoy:1.16".to_owned(), "web".to_owned()),
+                ("nginx:1.19".to_owned(), "admin".to_owned()),
+            ]
+        );
+
+        let by_name: Vec<String> = deduplicate_by_image_name_only(images)
+            .into_iter()
+            .map(|image| image.image_name)
+            .collect();
+        assert_eq!(by_name, vec!["nginx:1.19", "envoy:1.16"]);
+    }
+
+    fn image_of(cd: ContainerDefinition) -> Image {
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3".to_owned(),
+            ),
+            ..task_definition(vec![cd])
+        };
+        Image::from_task_definition(
+            &td,
+            "arn:aws:ecs:eu-west-1:123456789012:cluster/prod".to_owned(),
+            "web".to_owned(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn container_limits_are_copied() {
+        let limited = ContainerDefinition {
+            cpu: Some(256),
+            memory: Some(512),
+            memory_reservation: Some(128),
+            ..container("web", "nginx:1.19")
+        };
+        let image = image_of(limited);
+        assert_eq!(image.cpu_limit, Some(256));
+        assert_eq!(image.memory_limit, Some(512));
+        assert_eq!(image.memory_reservation, Some(128));
+    }
+
+    #[test]
+    fn containers_without_limits_serialize_null() {
+        let image = image_of(container("web", "nginx:1.19"));
+        assert_eq!(
+            (
+                image.cpu_limit,
+                image.memory_limit,
+                image.memory_reservation
+            ),
+            (None, None, None)
+        );
+
+        let value: serde_json::Value = serde_json::to_value(&image).unwrap();
+        assert!(value["memory_reservation"].is_null());
+        assert!(value
+            .as_object()
+            .unwrap()
+            .contains_key("memory_reservation"));
+    }
+
+    #[test]
+    fn sum_reserved_memory_ignores_unset_reservations() {
+        let reserving = |memory_reservation| Image {
+            memory_reservation,
+            ..web_image()
+        };
+        let images = vec![reserving(Some(128)), reserving(None), reserving(Some(256))];
+        assert_eq!(sum_reserved_memory(&images), 384);
+        assert_eq!(sum_reserved_memory(&[]), 0);
+    }
+
+    fn labelled(service_name: &str, labels: &[(&str, &str)]) -> Image {
+        Image {
+            service_name: service_name.to_owned(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+                .collect(),
+            ..web_image()
+        }
+    }
+
+    fn service_names<'a>(images: &[&'a Image]) -> Vec<&'a str> {
+        images
+            .iter()
+            .map(|image| image.service_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn find_images_by_label_key_and_value() {
+        let images = vec![
+            labelled("web", &[("git.commit", "abc123"), ("team", "web")]),
+            labelled("api", &[("git.commit", "def456")]),
+            labelled("batch", &[]),
+        ];
+
+        assert_eq!(
+            service_names(&find_images_with_label(&images, "git.commit", None)),
+            vec!["web", "api"]
+        );
+        assert_eq!(
+            service_names(&find_images_with_label(
+                &images,
+                "git.commit",
+                Some("def456")
+            )),
+            vec!["api"]
+        );
+        assert!(find_images_with_label(&images, "git.commit", Some("000000")).is_empty());
+        assert!(find_images_with_label(&images, "build.number", None).is_empty());
+    }
+
+    #[test]
+    fn docker_labels_are_copied_and_default_to_empty() {
+        let mut docker_labels = HashMap::new();
+        docker_labels.insert("build.number".to_owned(), "42".to_owned());
+        let image = image_of(ContainerDefinition {
+            docker_labels: Some(docker_labels),
+            ..container("web", "nginx:1.19")
+        });
+        assert_eq!(
+            image.labels.get("build.number").map(String::as_str),
+            Some("42")
+        );
+
+        let image = image_of(container("web", "nginx:1.19"));
+        assert!(image.labels.is_empty());
+        let value: serde_json::Value = serde_json::to_value(&image).unwrap();
+        assert_eq!(value["labels"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn labels_serialize_in_key_order() {
+        let image = labelled(
+            "web",
+            &[("team", "web"), ("build.number", "42"), ("git.sha", "abc")],
+        );
+        let json = serde_json::to_string(&image.labels).unwrap();
+        assert_eq!(
+            json,
+            r#"{"build.number":"42","git.sha":"abc","team":"web"}"#
+        );
+    }
+
+    fn with_capabilities(add: &[&str]) -> ContainerDefinition {
+        ContainerDefinition {
+            linux_parameters: Some(LinuxParameters {
+                capabilities: Some(KernelCapabilities {
+                    add: Some(add.iter().map(|cap| (*cap).to_owned()).collect()),
+                    drop: Some(vec!["NET_RAW".to_owned()]),
+                }),
+                ..Default::default()
+            }),
+            ..container("web", "nginx:1.19")
+        }
+    }
+
+    #[test]
+    fn linux_settings_are_copied() {
+        let cd = ContainerDefinition {
+            readonly_root_filesystem: Some(true),
+            user: Some("nginx".to_owned()),
+            ..with_capabilities(&["SYS_ADMIN"])
+        };
+        let linux = image_of(cd).linux_parameters.unwrap();
+        assert!(!linux.privileged);
+        assert_eq!(linux.add_capabilities, vec!["SYS_ADMIN"]);
+        assert_eq!(linux.drop_capabilities, vec!["NET_RAW"]);
+        assert!(linux.read_only_root_filesystem);
+        assert_eq!(linux.user.as_deref(), Some("nginx"));
+    }
+
+    #[test]
+    fn privileged_containers_are_found() {
+        let privileged = ContainerDefinition {
+            privileged: Some(true),
+            ..container("web", "nginx:1.19")
+        };
+        let images = vec![image_of(privileged), image_of(container("api", "api:2"))];
+
+        let found = find_privileged_containers(&images);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].container_name, "web");
+    }
+
+    #[test]
+    fn sys_admin_capability_with_or_without_prefix() {
+        let images = vec![
+            image_of(with_capabilities(&["CAP_SYS_ADMIN"])),
+            image_of(with_capabilities(&["NET_ADMIN"])),
+            image_of(container("web", "nginx:1.19")),
+        ];
+
+        assert_eq!(
+            find_containers_with_capability(&images, "CAP_SYS_ADMIN").len(),
+            1
+        );
+        assert_eq!(
+            find_containers_with_capability(&images, "SYS_ADMIN").len(),
+            1
+        );
+        assert_eq!(
+            find_containers_with_capability(&images, "SYS_PTRACE").len(),
+            0
+        );
+    }
+
+    fn health_checked(health_check: HealthCheck) -> ContainerDefinition {
+        ContainerDefinition {
+            health_check: Some(health_check),
+            ..container("web", "nginx:1.19")
+        }
+    }
+
+    #[test]
+    fn explicit_health_check_is_copied() {
+        let health_check = HealthCheck {
+            command: vec![
+                "CMD-SHELL".to_owned(),
+                "curl -f http://localhost/ || exit 1".to_owned(),
+            ],
+            interval: Some(10),
+            timeout: Some(2),
+            retries: Some(5),
+            start_period: Some(60),
+        };
+        let image = image_of(health_checked(health_check.clone()));
+
+        assert_eq!(
+            image.health_check,
+            Some(ContainerHealthCheck {
+                command: health_check.command,
+                interval: 10,
+                timeout: 2,
+                retries: 5,
+                start_period: Some(60),
+            })
+        );
+    }
+
+    #[test]
+    fn unset_health_check_values_get_the_ecs_defaults() {
+        let health_check = HealthCheck {
+            command: vec!["CMD".to_owned(), "true".to_owned()],
+            ..Default::default()
+        };
+        let converted = image_of(health_checked(health_check)).health_check.unwrap();
+        assert_eq!(
+            (converted.interval, converted.timeout, converted.retries),
+            (30, 5, 3)
+        );
+        assert_eq!(converted.start_period, None);
+    }
+
+    #[test]
+    fn images_without_health_check_are_found() {
+        let checked = health_checked(HealthCheck {
+            command: vec!["CMD".to_owned(), "true".to_owned()],
+            ..Default::default()
+        });
+        let images = vec![image_of(checked), image_of(container("api", "api:2"))];
+
+        assert!(images[1].health_check.is_none());
+        let missing = find_images_without_health_check(&images);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].container_name, "api");
+    }
+
+    fn placed(service_name: &str, container_name: &str, image_name: &str) -> Image {
+        Image {
+            cluster_arn: "arn:aws:ecs:eu-west-1:123456789012:cluster/prod".to_owned(),
+            service_name: service_name.to_owned(),
+            container_name: container_name.to_owned(),
+            image_name: image_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn uses_latest_tag() {
+        assert!(placed("web", "app", "nginx").uses_latest_tag());
+        assert!(placed("web", "app", "nginx:latest").uses_latest_tag());
+        assert!(!placed("web", "app", "nginx:1.19").uses_latest_tag());
+        assert!(!placed("web", "app", "nginx@sha256:abc").uses_latest_tag());
+        assert!(!placed("web", "app", DEREGISTERED_IMAGE_NAME).uses_latest_tag());
+    }
+
+    #[test]
+    fn placeholders_are_not_reported_as_images() {
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/api:0".to_owned(),
+            ),
+            status: Some("INACTIVE".to_owned()),
+            ..Default::default()
+        };
+        let cluster_arn = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+        let placeholders = vec![
+            Image::deregistered(&td, cluster_arn.to_owned(), "legacy".to_owned()),
+            Image::deregistered(&td, cluster_arn.to_owned(), "admin".to_owned()),
+        ];
+
+        assert!(placeholders.iter().all(Image::is_placeholder));
+        assert!(!placed("web", "app", "nginx").is_placeholder());
+        assert!(find_untagged_digest_images(&placeholders).is_empty());
+        assert!(find_images_without_health_check(&placeholders).is_empty());
+        assert!(find_containers_with_short_stop_timeout(&placeholders, 60).is_empty());
+        let kept = deduplicate_by_image_name_only(placeholders);
+        assert_eq!(
+            service_names(&kept.iter().collect::<Vec<_>>()),
+            vec!["legacy", "admin"]
+        );
+    }
+
+    #[test]
+    fn diff_images_added_removed_and_changed() {
+        let before = vec![
+            placed("web", "app", "nginx:1.18"),
+            placed("web", "sidecar", "envoy:1.14"),
+            placed("worker", "app", "worker:1"),
+        ];
+        let after = vec![
+            placed("web", "app", "nginx:1.19"),
+            placed("web", "sidecar", "envoy:1.14"),
+            placed("api", "app", "api:1"),
+        ];
+        let diff = diff_images(&before, &after);
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].service_name, "api");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].service_name, "worker");
+        assert_eq!(diff.changed.len(), 1);
+        let (previous, current) = &diff.changed[0];
+        assert_eq!(previous.image_name, "nginx:1.18");
+        assert_eq!(current.image_name, "nginx:1.19");
+        assert_eq!(diff.change_count(), 3);
+    }
+
+    #[test]
+    fn diff_of_identical_scans_is_empty() {
+        let images = vec![placed("web", "app", "nginx:1.19")];
+        let diff = diff_images(&images, &images);
+        assert!(diff.is_empty());
+        assert_eq!(diff.change_count(), 0);
+    }
+
+    #[test]
+    fn launch_type_source_from_launch_type_or_capacity_provider() {
+        let launched = |launch_type: &str| Service {
+            launch_type: Some(launch_type.to_owned()),
+            ..Default::default()
+        };
+        let provided = |capacity_provider: &str| Service {
+            capacity_provider_strategy: Some(vec![CapacityProviderStrategyItem {
+                capacity_provider: capacity_provider.to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            LaunchTypeSource::of(&launched("FARGATE")),
+            Some(LaunchTypeSource::Fargate)
+        );
+        assert_eq!(
+            LaunchTypeSource::of(&launched("EC2")),
+            Some(LaunchTypeSource::Ec2)
+        );
+        assert_eq!(
+            LaunchTypeSource::of(&launched("EXTERNAL")),
+            Some(LaunchTypeSource::External)
+        );
+        assert_eq!(
+            LaunchTypeSource::of(&provided("FARGATE_SPOT")),
+            Some(LaunchTypeSource::Fargate)
+        );
+        assert_eq!(
+            LaunchTypeSource::of(&provided("asg-provider")),
+            Some(LaunchTypeSource::Ec2)
+        );
+        assert_eq!(LaunchTypeSource::of(&Service::default()), None);
+    }
+
+    #[test]
+    fn containers_with_working_directory() {
+        let in_dir = |name: &str, working_directory: Option<&str>| Image {
+            service_name: name.to_owned(),
+            ..image_of(ContainerDefinition {
+                working_directory: working_directory.map(str::to_owned),
+                ..container("app", "app:1")
+            })
+        };
+        let images = vec![
+            in_dir("root", Some("/")),
+            in_dir("app", Some("/srv/app")),
+            in_dir("unset", None),
+        ];
+
+        assert_eq!(images[1].working_directory.as_deref(), Some("/srv/app"));
+        assert_eq!(images[2].working_directory, None);
+        let found: Vec<&str> = find_containers_with_working_directory(&images, "/")
+            .iter()
+            .map(|i| i.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["root"]);
+        assert!(find_containers_with_working_directory(&images, "/tmp").is_empty());
+    }
+
+    fn service_changed(created_at: Option<f64>, updated_at: &[f64]) -> ServiceImage {
+        let service = Service {
+            service_name: Some("web".to_owned()),
+            created_at,
+            deployments: Some(
+                updated_at
+                    .iter()
+                    .map(|updated_at| Deployment {
+                        updated_at: Some(*updated_at),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        ServiceImage::from_service(&service, None)
+    }
+
+    #[test]
+    fn service_times_from_creation_and_latest_deployment() {
+        let service = service_changed(Some(1_600_000_000.5), &[1_600_000_100.0, 1_600_000_200.0]);
+
+        assert_eq!(
+            service.service_created_at,
+            Some(Utc.timestamp(1_600_000_000, 500_000_000))
+        );
+        assert_eq!(
+            service.service_updated_at,
+            Some(Utc.timestamp(1_600_000_200, 0))
+        );
+        let unset = service_changed(None, &[]);
+        assert_eq!(unset.service_created_at, None);
+        assert_eq!(unset.service_updated_at, None);
+    }
+
+    #[test]
+    fn long_unchanged_services_fall_back_on_creation_time() {
+        let now = Utc::now().timestamp() as f64;
+        let day = 24.0 * 3600.0;
+        let services = vec![
+            service_changed(Some(now - 100.0 * day), &[now - 60.0 * day]),
+            service_changed(Some(now - 100.0 * day), &[now - day]),
+            service_changed(Some(now - 90.0 * day), &[]),
+            service_changed(None, &[]),
+        ];
+
+        let unchanged =
+            find_long_unchanged_services(&services, Duration::from_secs(30 * 24 * 3600));
+        let created: Vec<Option<DateTime<Utc>>> =
+            unchanged.iter().map(|s| s.service_created_at).collect();
+        assert_eq!(
+            created,
+            vec![
+                services[0].service_created_at,
+                services[2].service_created_at
+            ]
+        );
+    }
+
+    #[test]
+    fn services_without_tag_propagation() {
+        let propagating = |name: &str, propagate_tags: Option<&str>| {
+            let service = Service {
+                service_name: Some(name.to_owned()),
+                propagate_tags: propagate_tags.map(str::to_owned),
+                ..Default::default()
+            };
+            ServiceImage::from_service(&service, None)
+        };
+        let services = vec![
+            propagating("service", Some("SERVICE")),
+            propagating("task-definition", Some("TASK_DEFINITION")),
+            propagating("none", Some("NONE")),
+            propagating("unset", None),
+        ];
+
+        assert_eq!(services[0].propagate_tags.as_deref(), Some("SERVICE"));
+        let found: Vec<&str> = find_services_without_tag_propagation(&services)
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["none", "unset"]);
+    }
+
+    #[test]
+    fn short_stop_timeouts_count_unset_as_the_agent_default() {
+        let stopping = |name: &str, stop_timeout: Option<i64>| Image {
+            service_name: name.to_owned(),
+            ..image_of(ContainerDefinition {
+                stop_timeout,
+                ..container("app", "app:1")
+            })
+        };
+        let images = vec![
+            stopping("quick", Some(2)),
+            stopping("unset", None),
+            stopping("patient", Some(120)),
+        ];
+
+        assert_eq!(images[2].stop_timeout, Some(120));
+        let short = |min_seconds| -> Vec<&str> {
+            find_containers_with_short_stop_timeout(&images, min_seconds)
+                .iter()
+                .map(|i| i.service_name.as_str())
+                .collect()
+        };
+        assert_eq!(short(DEFAULT_STOP_TIMEOUT), vec!["quick"]);
+        assert_eq!(short(30), vec!["quick", "unset"]);
+    }
+
+    #[test]
+    fn dependencies_carry_the_start_timeout() {
+        let app = ContainerDefinition {
+            start_timeout: Some(30),
+            depends_on: Some(vec![
+                DependsOn {
+                    container_name: "envoy".to_owned(),
+                    condition: "HEALTHY".to_owned(),
+                },
+                DependsOn {
+                    container_name: "migrate".to_owned(),
+                    condition: "SUCCESS".to_owned(),
+                },
+            ]),
+            ..container("app", "app:1")
+        };
+        let image = image_of(app);
+
+        assert_eq!(image.start_timeout, Some(30));
+        let conditions: Vec<(&str, &str, Option<i64>)> = image
+            .container_dependencies
+            .iter()
+            .map(|d| (d.container_name.as_str(), d.condition.as_str(), d.timeout))
+            .collect();
+        assert_eq!(
+            conditions,
+            vec![
+                ("envoy", "HEALTHY", Some(30)),
+                ("migrate", "SUCCESS", Some(30))
+            ]
+        );
+        assert!(image_of(container("app", "app:1"))
+            .container_dependencies
+            .is_empty());
+    }
+
+    #[test]
+    fn tight_start_timeouts_skip_unset_ones() {
+        let starting = |name: &str, start_timeout: Option<i64>| Image {
+            service_name: name.to_owned(),
+            ..image_of(ContainerDefinition {
+                start_timeout,
+                ..container("app", "app:1")
+            })
+        };
+        let images = vec![
+            starting("tight", Some(5)),
+            starting("unset", None),
+            starting("relaxed", Some(120)),
+        ];
+
+        let tight: Vec<&str> = find_containers_with_tight_start_timeout(&images, 30)
+            .iter()
+            .map(|i| i.service_name.as_str())
+            .collect();
+        assert_eq!(tight, vec!["tight"]);
+    }
+
+    fn containers_of(cds: Vec<ContainerDefinition>) -> Vec<Image> {
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3".to_owned(),
+            ),
+            ..task_definition(cds)
+        };
+        Image::containers_of_task_definition(
+            &td,
+            "arn:aws:ecs:eu-west-1:123456789012:cluster/prod",
+            "web",
+        )
+    }
+
+    #[test]
+    fn tight_start_timeout_of_a_container_before_the_last_one() {
+        let app = ContainerDefinition {
+            start_timeout: Some(5),
+            depends_on: Some(vec![DependsOn {
+                container_name: "envoy".to_owned(),
+                condition: "HEALTHY".to_owned(),
+            }]),
+            ..container("app", "app:1")
+        };
+        // the image of the service would only be envoy, the last container
+        let containers = containers_of(vec![app, container("envoy", "envoy:1.16")]);
+        let tight: Vec<&str> = find_containers_with_tight_start_timeout(&containers, 30)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(tight, vec!["app"]);
+    }
+
+    fn requirement(type_: &str, value: &str) -> ResourceRequirement {
+        ResourceRequirement {
+            type_: type_.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    fn requiring(requirements: Vec<ResourceRequirement>) -> ContainerDefinition {
+        ContainerDefinition {
+            resource_requirements: Some(requirements),
+            ..container("model", "model:1")
+        }
+    }
+
+    #[test]
+    fn gpu_requirements_are_summed() {
+        let images = vec![
+            image_of(requiring(vec![
+                requirement("GPU", "2"),
+                requirement("GPU", "1"),
+            ])),
+            image_of(requiring(vec![requirement("GPU", "0")])),
+            image_of(container("web", "nginx:1.19")),
+        ];
+
+        let counts: Vec<Option<i64>> = images.iter().map(|image| image.gpu_count).collect();
+        assert_eq!(counts, vec![Some(3), Some(0), None]);
+        let gpu: Vec<Option<i64>> = find_gpu_containers(&images)
+            .iter()
+            .map(|image| image.gpu_count)
+            .collect();
+        assert_eq!(gpu, vec![Some(3)]);
+    }
+
+    #[test]
+    fn inference_accelerators_get_the_task_device_type() {
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/model:1".to_owned(),
+            ),
+            inference_accelerators: Some(vec![InferenceAccelerator {
+                device_name: "device_1".to_owned(),
+                device_type: "eia2.medium".to_owned(),
+            }]),
+            ..task_definition(vec![requiring(vec![
+                requirement("InferenceAccelerator", "device_1"),
+                requirement("InferenceAccelerator", "device_2"),
+            ])])
+        };
+        let image =
+            Image::from_task_definition(&td, "prod".to_owned(), "model".to_owned()).unwrap();
+
+        assert_eq!(
+            image.inference_accelerators,
+            vec![
+                InferenceAcceleratorAssignment {
+                    inference_accelerator_name: "device_1".to_owned(),
+                    device_type: "eia2.medium".to_owned(),
+                },
+                InferenceAcceleratorAssignment {
+                    inference_accelerator_name: "device_2".to_owned(),
+                    device_type: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn proxy_properties_serialize_in_key_order() {
+        let property = |name: &str, value: &str| KeyValuePair {
+            name: Some(name.to_owned()),
+            value: Some(value.to_owned()),
+        };
+        let proxy = ProxyConfig::from(&ProxyConfiguration {
+            type_: Some("APPMESH".to_owned()),
+            container_name: "envoy".to_owned(),
+            properties: Some(vec![
+                property("ProxyIngressPort", "15000"),
+                property("AppPorts", "8080"),
+                property("IgnoredUID", "1337"),
+            ]),
+        });
+        assert_eq!(
+            serde_json::to_string(&proxy.properties).unwrap(),
+            r#"{"AppPorts":"8080","IgnoredUID":"1337","ProxyIngressPort":"15000"}"#
+        );
+    }
+
+    #[test]
+    fn app_mesh_proxy_configuration() {
+        let meshed = TaskDefinition {
+            proxy_configuration: Some(ProxyConfiguration {
+                type_: None,
+                container_name: "envoy".to_owned(),
+                properties: Some(vec![
+                    KeyValuePair {
+                        name: Some("ProxyIngressPort".to_owned()),
+                        value: Some("15000".to_owned()),
+                    },
+                    KeyValuePair {
+                        name: Some("IgnoredUID".to_owned()),
+                        value: None,
+                    },
+                ]),
+            }),
+            ..task_definition(vec![container("web", "nginx:1.19")])
+        };
+        let plain = task_definition(vec![container("api", "api:1")]);
+        let service_with = |name: &str, td: &TaskDefinition| {
+            ServiceImage::from_service(
+                &Service {
+                    service_name: Some(name.to_owned()),
+                    ..Default::default()
+                },
+                Some(td),
+            )
+        };
+        let services = vec![service_with("web", &meshed), service_with("api", &plain)];
+
+        let proxy = services[0]
+            .task_definition_metadata
+            .as_ref()
+            .unwrap()
+            .proxy_configuration
+            .as_ref()
+            .unwrap();
+        assert_eq!(proxy.type_, "APPMESH");
+        assert_eq!(proxy.container_name, "envoy");
+        assert_eq!(proxy.properties.len(), 1);
+        assert_eq!(proxy.properties["ProxyIngressPort"], "15000");
+        assert_eq!(
+            services[1]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .proxy_configuration,
+            None
+        );
+        let meshed: Vec<&str> = find_services_with_app_mesh(&services)
+            .iter()
+            .map(|s| s.service_name.as_str())
+            .collect();
+        assert_eq!(meshed, vec!["web"]);
+    }
+
+    #[test]
+    fn host_and_task_namespaces_are_shared() {
+        let in_modes = |name: &str, mode: Option<&str>| {
+            let td = TaskDefinition {
+                ipc_mode: mode.map(str::to_owned),
+                pid_mode: mode.map(str::to_owned),
+                ..task_definition(vec![container("app", "app:1")])
+            };
+            ServiceImage::from_service(
+                &Service {
+                    service_name: Some(name.to_owned()),
+                    ..Default::default()
+                },
+                Some(&td),
+            )
+        };
+        let services = vec![
+            in_modes("host", Some("host")),
+            in_modes("task", Some("task")),
+            in_modes("none", Some("none")),
+            in_modes("unset", None),
+        ];
+
+        assert_eq!(
+            services[0]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .ipc_mode
+                .as_deref(),
+            Some("host")
+        );
+        assert_eq!(
+            services[3]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .pid_mode,
+            None
+        );
+        let names = |found: Vec<&ServiceImage>| -> Vec<String> {
+            found.iter().map(|s| s.service_name.clone()).collect()
+        };
+        assert_eq!(
+            names(find_services_with_shared_pid_namespace(&services)),
+            vec!["host", "task"]
+        );
+        assert_eq!(
+            names(find_services_with_shared_ipc(&services)),
+            vec!["host", "task"]
+        );
+        assert_eq!(
+            names(find_services_with_host_pid_mode(&services)),
+            vec!["host"]
+        );
+    }
+
+    #[test]
+    fn containers_with_sysctl_under_a_namespace() {
+        let tuned = |name: &str, sysctls: &[(&str, &str)]| Image {
+            service_name: name.to_owned(),
+            ..image_of(ContainerDefinition {
+                system_controls: Some(
+                    sysctls
+                        .iter()
+                        .map(|(namespace, value)| rusoto_ecs::SystemControl {
+                            namespace: Some((*namespace).to_owned()),
+                            value: Some((*value).to_owned()),
+                        })
+                        .collect(),
+                ),
+                ..container("app", "app:1")
+            })
+        };
+        let images = vec![
+            tuned("net", &[("net.core.somaxconn", "1024")]),
+            tuned("ipc", &[("kernel.shmmax", "68719476736")]),
+            tuned("none", &[]),
+        ];
+
+        assert_eq!(
+            images[0].system_controls,
+            vec![SystemControl {
+                namespace: "net.core.somaxconn".to_owned(),
+                value: "1024".to_owned()
+            }]
+        );
+        let found: Vec<&str> = find_containers_with_sysctl(&images, "net.")
+            .iter()
+            .map(|i| i.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["net"]);
+        assert!(find_containers_with_sysctl(&images, "vm.").is_empty());
+    }
+
+    fn limited(name: &str, ulimits: &[(&str, i64, i64)]) -> Image {
+        Image {
+            service_name: name.to_owned(),
+            ..image_of(ContainerDefinition {
+                ulimits: Some(
+                    ulimits
+                        .iter()
+                        .map(|(name, soft_limit, hard_limit)| rusoto_ecs::Ulimit {
+                            name: (*name).to_owned(),
+                            soft_limit: *soft_limit,
+                            hard_limit: *hard_limit,
+                        })
+                        .collect(),
+                ),
+                ..container("app", "app:1")
+            })
+        }
+    }
+
+    #[test]
+    fn containers_with_a_ulimit() {
+        let images = vec![
+            limited("nproc", &[("nproc", 512, 1024)]),
+            limited("none", &[]),
+        ];
+
+        assert_eq!(
+            images[0].ulimits,
+            vec![Ulimit {
+                name: "nproc".to_owned(),
+                soft_limit: 512,
+                hard_limit: 1024
+            }]
+        );
+        assert_eq!(find_containers_with_ulimit(&images, "nproc").len(), 1);
+        assert!(find_containers_with_ulimit(&images, "nofile").is_empty());
+    }
+
+    #[test]
+    fn high_nofile_compares_the_soft_limit_inclusively() {
+        let images = vec![
+            limited("at", &[("nofile", 65536, 65536)]),
+            limited("hard-only", &[("nofile", 1024, 65536)]),
+            limited("other", &[("nproc", 65536, 65536)]),
+        ];
+
+        let found: Vec<&str> = find_containers_with_high_nofile(&images, 65536)
+            .iter()
+            .map(|i| i.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["at"]);
+    }
+
+    #[test]
+    fn firelens_routers_and_senders() {
+        let sender = ContainerDefinition {
+            log_configuration: Some(LogConfiguration {
+                log_driver: "awsfirelens".to_owned(),
+                ..Default::default()
+            }),
+            ..container("app", "app:1")
+        };
+        let router = ContainerDefinition {
+            firelens_configuration: Some(FirelensConfiguration {
+                type_: "fluentbit".to_owned(),
+                options: None,
+            }),
+            ..container("log_router", "amazon/aws-for-fluent-bit:2.10.0")
+        };
+        let awslogs = ContainerDefinition {
+            log_configuration: Some(LogConfiguration {
+                log_driver: "awslogs".to_owned(),
+                ..Default::default()
+            }),
+            ..container("web", "nginx:1.19")
+        };
+        let images = vec![image_of(sender), image_of(router), image_of(awslogs)];
+
+        assert!(images[0].uses_firelens_log_driver);
+        assert_eq!(images[1].firelens_router_type.as_deref(), Some("fluentbit"));
+        assert!(!images[2].uses_firelens_log_driver);
+        let containers = |found: Vec<&Image>| -> Vec<String> {
+            found.iter().map(|i| i.container_name.clone()).collect()
+        };
+        assert_eq!(
+            containers(find_containers_routing_to_firelens(&images)),
+            vec!["app"]
+        );
+        assert_eq!(
+            containers(find_firelens_router_containers(&images)),
+            vec!["log_router"]
+        );
+    }
+
+    #[test]
+    fn firelens_router_sidecar_is_found_among_the_containers() {
+        let router = ContainerDefinition {
+            firelens_configuration: Some(FirelensConfiguration {
+                type_: "fluentbit".to_owned(),
+                options: None,
+            }),
+            ..container("log_router", "amazon/aws-for-fluent-bit:2.10.0")
+        };
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3".to_owned(),
+            ),
+            ..task_definition(vec![router, container("app", "app:1")])
+        };
+        let cluster_arn = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+
+        let image = Image::from_task_definition(&td, cluster_arn.to_owned(), "web".to_owned());
+        assert!(find_firelens_router_containers(&[image.unwrap()]).is_empty());
+
+        let containers = Image::containers_of_task_definition(&td, cluster_arn, "web");
+        let routers: Vec<&str> = find_firelens_router_containers(&containers)
+            .iter()
+            .map(|image| image.container_name.as_str())
+            .collect();
+        assert_eq!(routers, vec!["log_router"]);
+        assert_eq!(containers[0].service_name, "web");
+    }
+
+    #[test]
+    fn extra_hosts_are_copied() {
+        let pinned = ContainerDefinition {
+            extra_hosts: Some(vec![rusoto_ecs::HostEntry {
+                hostname: "db.internal".to_owned(),
+                ip_address: "10.0.0.12".to_owned(),
+            }]),
+            ..container("app", "app:1")
+        };
+        let images = vec![image_of(pinned), image_of(container("web", "nginx:1.19"))];
+
+        assert_eq!(
+            images[0].extra_hosts,
+            vec![HostEntry {
+                hostname: "db.internal".to_owned(),
+                ip_address: "10.0.0.12".to_owned()
+            }]
+        );
+        assert!(images[1].extra_hosts.is_empty());
+        let found: Vec<&str> = find_containers_with_extra_hosts(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["app"]);
+    }
+
+    #[test]
+    fn custom_dns_servers_and_search_domains() {
+        let resolving = ContainerDefinition {
+            dns_servers: Some(vec!["10.0.0.2".to_owned()]),
+            dns_search_domains: Some(vec!["corp.internal".to_owned()]),
+            ..container("app", "app:1")
+        };
+        let search_only = ContainerDefinition {
+            dns_search_domains: Some(vec!["corp.internal".to_owned()]),
+            ..container("worker", "worker:1")
+        };
+        let images = vec![
+            image_of(resolving),
+            image_of(search_only),
+            image_of(container("web", "nginx:1.19")),
+        ];
+
+        assert_eq!(images[0].dns_servers, vec!["10.0.0.2"]);
+        assert_eq!(images[0].dns_search_domains, vec!["corp.internal"]);
+        assert!(images[2].dns_servers.is_empty() && images[2].dns_search_domains.is_empty());
+        let found: Vec<&str> = find_containers_with_custom_dns(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["app"]);
+    }
+
+    #[test]
+    fn containers_using_legacy_links() {
+        let linked = ContainerDefinition {
+            links: Some(vec!["db:database".to_owned()]),
+            ..container("app", "app:1")
+        };
+        let images = vec![image_of(linked), image_of(container("web", "nginx:1.19"))];
+
+        assert_eq!(images[0].links, vec!["db:database"]);
+        assert!(images[1].links.is_empty());
+        let found: Vec<&str> = find_containers_using_links(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["app"]);
+    }
+
+    #[test]
+    fn volumes_from_default_to_read_write() {
+        let mounting = ContainerDefinition {
+            volumes_from: Some(vec![
+                rusoto_ecs::VolumeFrom {
+                    source_container: Some("config".to_owned()),
+                    read_only: Some(true),
+                },
+                rusoto_ecs::VolumeFrom {
+                    source_container: Some("cache".to_owned()),
+                    read_only: None,
+                },
+            ]),
+            ..container("app", "app:1")
+        };
+        let images = vec![image_of(mounting), image_of(container("web", "nginx:1.19"))];
+
+        assert_eq!(
+            images[0].volumes_from,
+            vec![
+                VolumeFrom {
+                    source_container: "config".to_owned(),
+                    read_only: true
+                },
+                VolumeFrom {
+                    source_container: "cache".to_owned(),
+                    read_only: false
+                },
+            ]
+        );
+        let found: Vec<&str> = find_containers_mounting_volumes_from_others(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["app"]);
+    }
+
+    #[test]
+    fn volumes_from_of_a_container_before_the_last_one() {
+        let sidecar = ContainerDefinition {
+            volumes_from: Some(vec![rusoto_ecs::VolumeFrom {
+                source_container: Some("app".to_owned()),
+                read_only: Some(true),
+            }]),
+            ..container("log-shipper", "fluentd:v1.11")
+        };
+        let containers = containers_of(vec![sidecar, container("app", "app:1")]);
+
+        let found: Vec<&str> = find_containers_mounting_volumes_from_others(&containers)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["log-shipper"]);
+        assert!(containers[1].volumes_from.is_empty());
+    }
+
+    #[test]
+    fn private_registry_credentials_arn() {
+        let credentials = "arn:aws:secretsmanager:eu-west-1:123456789012:secret:dockerhub";
+        let private = ContainerDefinition {
+            repository_credentials: Some(RepositoryCredentials {
+                credentials_parameter: credentials.to_owned(),
+            }),
+            ..container("app", "docker.io/team/app:1")
+        };
+        let ecr = container("web", "123456789012.dkr.ecr.eu-west-1.amazonaws.com/web:1");
+        let images = vec![image_of(private), image_of(ecr)];
+
+        assert_eq!(
+            images[0].repository_credentials_arn.as_deref(),
+            Some(credentials)
+        );
+        assert_eq!(images[1].repository_credentials_arn, None);
+        let found: Vec<&str> = find_containers_with_private_registry_auth(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["app"]);
+    }
+
+    #[test]
+    fn only_explicitly_disabled_networking_is_reported() {
+        let networking = |name: &str, disable_networking: Option<bool>| {
+            image_of(ContainerDefinition {
+                disable_networking,
+                hostname: Some(format!("{}.local", name)),
+                ..container(name, "app:1")
+            })
+        };
+        let images = vec![
+            networking("offline", Some(true)),
+            networking("online", Some(false)),
+            networking("unset", None),
+        ];
+
+        assert_eq!(
+            images[0].container_hostname.as_deref(),
+            Some("offline.local")
+        );
+        assert_eq!(images[2].disable_networking, None);
+        let found: Vec<&str> = find_containers_with_disabled_networking(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["offline"]);
+    }
+
+    #[test]
+    fn interactive_or_tty_containers() {
+        let flagged = |name: &str, interactive: Option<bool>, pseudo_terminal: Option<bool>| {
+            image_of(ContainerDefinition {
+                interactive,
+                pseudo_terminal,
+                ..container(name, "app:1")
+            })
+        };
+        let images = vec![
+            flagged("stdin", Some(true), None),
+            flagged("tty", None, Some(true)),
+            flagged("off", Some(false), Some(false)),
+            flagged("unset", None, None),
+        ];
+
+        let found: Vec<&str> = find_interactive_containers(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["stdin", "tty"]);
+        assert_eq!(images[2].pseudo_terminal, Some(false));
+    }
+
+    #[test]
+    fn only_a_positive_max_swap_enables_swap() {
+        let swapping = |name: &str, max_swap: Option<i64>, swappiness: Option<i64>| {
+            image_of(ContainerDefinition {
+                linux_parameters: Some(LinuxParameters {
+                    max_swap,
+                    swappiness,
+                    ..Default::default()
+                }),
+                ..container(name, "app:1")
+            })
+        };
+        let images = vec![
+            swapping("enabled", Some(1024), Some(60)),
+            swapping("disabled", Some(0), None),
+            swapping("negative", Some(-1), None),
+            image_of(container("unset", "app:1")),
+        ];
+
+        assert_eq!(
+            (images[0].memory_swap, images[0].memory_swappiness),
+            (Some(1024), Some(60))
+        );
+        assert_eq!(images[3].memory_swap, None);
+        let found: Vec<&str> = find_containers_with_swap_enabled(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["enabled"]);
+    }
+
+    #[test]
+    fn large_shared_memory_is_above_the_threshold() {
+        let sharing = |name: &str, shared_memory_size: i64| {
+            image_of(ContainerDefinition {
+                linux_parameters: Some(LinuxParameters {
+                    shared_memory_size: Some(shared_memory_size),
+                    ..Default::default()
+                }),
+                ..container(name, "app:1")
+            })
+        };
+        let images = vec![
+            sharing("large", 2048),
+            sharing("at", 512),
+            image_of(container("unset", "app:1")),
+        ];
+
+        assert_eq!(images[0].shm_size, Some(2048));
+        assert_eq!(images[2].shm_size, None);
+        let found: Vec<&str> = find_containers_with_large_shared_memory(&images, 512)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["large"]);
+    }
+
+    #[test]
+    fn host_device_mappings_are_copied() {
+        let fuse = ContainerDefinition {
+            linux_parameters: Some(LinuxParameters {
+                devices: Some(vec![rusoto_ecs::Device {
+                    host_path: "/dev/fuse".to_owned(),
+                    container_path: None,
+                    permissions: Some(vec!["read".to_owned(), "write".to_owned()]),
+                }]),
+                ..Default::default()
+            }),
+            ..container("mounter", "s3fs:1")
+        };
+        let images = vec![image_of(fuse), image_of(container("web", "nginx:1.19"))];
+
+        assert_eq!(
+            images[0].devices,
+            vec![DeviceMapping {
+                host_path: "/dev/fuse".to_owned(),
+                container_path: None,
+                permissions: vec!["read".to_owned(), "write".to_owned()],
+            }]
+        );
+        assert!(images[1].devices.is_empty());
+        let found: Vec<&str> = find_containers_with_host_devices(&images)
+            .iter()
+            .map(|i| i.container_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["mounter"]);
+    }
+
+    fn with_strategy(strategy: &[(&str, Option<i64>, Option<i64>)]) -> ServiceImage {
+        let service = Service {
+            service_name: Some("web".to_owned()),
+            capacity_provider_strategy: Some(
+                strategy
+                    .iter()
+                    .map(
+                        |(capacity_provider, weight, base)| CapacityProviderStrategyItem {
+                            capacity_provider: (*capacity_provider).to_owned(),
+                            weight: *weight,
+                            base: *base,
+                        },
+                    )
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        ServiceImage::from_service(&service, None)
+    }
+
+    #[test]
+    fn full_capacity_provider_strategy_is_kept() {
+        let service = with_strategy(&[
+            ("FARGATE", Some(1), Some(2)),
+            ("FARGATE_SPOT", Some(3), None),
+        ]);
+
+        assert_eq!(
+            service.capacity_provider_strategy,
+            vec![
+                CapacityProviderEntry {
+                    capacity_provider: "FARGATE".to_owned(),
+                    weight: 1,
+                    base: 2
+                },
+                CapacityProviderEntry {
+                    capacity_provider: "FARGATE_SPOT".to_owned(),
+                    weight: 3,
+                    base: 0
+                },
+            ]
+        );
+        assert_eq!(service.launch_type_source, Some(LaunchTypeSource::Fargate));
+    }
+
+    #[test]
+    fn fargate_spot_ratio_by_weight() {
+        assert_eq!(
+            fargate_spot_ratio(&with_strategy(&[
+                ("FARGATE", Some(1), None),
+                ("FARGATE_SPOT", Some(3), None)
+            ])),
+            0.75
+        );
+        assert_eq!(
+            fargate_spot_ratio(&with_strategy(&[("FARGATE_SPOT", Some(1), None)])),
+            1.0
+        );
+        assert_eq!(
+            fargate_spot_ratio(&with_strategy(&[("FARGATE_SPOT", Some(0), Some(1))])),
+            0.0
+        );
+        assert_eq!(fargate_spot_ratio(&with_strategy(&[])), 0.0);
+    }
+
+    fn accelerated(name: &str, accelerators: Option<Vec<InferenceAccelerator>>) -> ServiceImage {
+        let td = TaskDefinition {
+            inference_accelerators: accelerators,
+            ..task_definition(vec![container(name, "model:1")])
+        };
+        ServiceImage::from_service(
+            &Service {
+                service_name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            Some(&td),
+        )
+    }
+
+    #[test]
+    fn services_using_elastic_inference() {
+        let services = vec![
+            accelerated(
+                "model",
+                Some(vec![InferenceAccelerator {
+                    device_name: "device_1".to_owned(),
+                    device_type: "eia2.medium".to_owned(),
+                }]),
+            ),
+            accelerated("web", None),
+            accelerated("api", Some(Vec::new())),
+        ];
+
+        assert_eq!(
+            services[0]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .inference_accelerators,
+            vec![super::InferenceAccelerator {
+                device_name: "device_1".to_owned(),
+                device_type: "eia2.medium".to_owned()
+            }]
+        );
+        assert!(services[1]
+            .task_definition_metadata
+            .as_ref()
+            .unwrap()
+            .inference_accelerators
+            .is_empty());
+        let found: Vec<&str> = find_services_using_elastic_inference(&services)
+            .iter()
+            .map(|service| service.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["model"]);
+    }
+
+    fn tagged(name: &str, tags: &[(&str, &str)]) -> ServiceImage {
+        let service = Service {
+            service_name: Some(name.to_owned()),
+            tags: Some(
+                tags.iter()
+                    .map(|(key, value)| rusoto_ecs::Tag {
+                        key: Some((*key).to_owned()),
+                        value: Some((*value).to_owned()),
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        ServiceImage::from_service(&service, None)
+    }
+
+    #[test]
+    fn services_with_tag_by_key_or_key_and_value() {
+        let services = vec![
+            tagged(
+                "web",
+                &[("Environment", "production"), ("Team", "platform")],
+            ),
+            tagged("api", &[("Environment", "staging")]),
+            tagged("worker", &[]),
+        ];
+
+        let names = |found: Vec<&ServiceImage>| {
+            found
+                .iter()
+                .map(|service| service.service_name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(find_services_with_tag(&services, "Environment", None)),
+            vec!["web", "api"]
+        );
+        assert_eq!(
+            names(find_services_with_tag(
+                &services,
+                "Environment",
+                Some("staging")
+            )),
+            vec!["api"]
+        );
+        assert!(find_services_with_tag(&services, "Owner", None).is_empty());
+        assert_eq!(
+            services[0].service_tags.get("Team").map(String::as_str),
+            Some("platform")
+        );
+    }
+
+    #[test]
+    fn service_tags_serialize_in_key_order() {
+        let service = tagged(
+            "web",
+            &[("Team", "platform"), ("Environment", "production")],
+        );
+        assert_eq!(
+            serde_json::to_string(&service.service_tags).unwrap(),
+            r#"{"Environment":"production","Team":"platform"}"#
+        );
+    }
+
+    fn requiring_attributes(name: &str, attributes: &[&str]) -> ServiceImage {
+        let td = TaskDefinition {
+            requires_attributes: Some(
+                attributes
+                    .iter()
+                    .map(|attribute| rusoto_ecs::Attribute {
+                        name: (*attribute).to_owned(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..task_definition(vec![container(name, "app:1")])
+        };
+        ServiceImage::from_service(
+            &Service {
+                service_name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            Some(&td),
+        )
+    }
+
+    #[test]
+    fn services_requiring_an_attribute() {
+        let services = vec![
+            requiring_attributes(
+                "web",
+                &[
+                    "ecs.capability.task-eni",
+                    "com.amazonaws.ecs.capability.docker-remote-api.1.19",
+                ],
+            ),
+            requiring_attributes(
+                "api",
+                &["com.amazonaws.ecs.capability.docker-remote-api.1.19"],
+            ),
+            ServiceImage::from_service(
+                &Service {
+                    service_name: Some("fargate".to_owned()),
+                    ..Default::default()
+                },
+                None,
+            ),
+        ];
+
+        assert!(services[2].task_definition_metadata.is_none());
+        let found: Vec<&str> =
+            find_services_requiring_attribute(&services, "ecs.capability.task-eni")
+                .iter()
+                .map(|service| service.service_name.as_str())
+                .collect();
+        assert_eq!(found, vec!["web"]);
+    }
+
+    fn constrained(name: &str, expressions: &[Option<&str>]) -> ServiceImage {
+        let td = TaskDefinition {
+            placement_constraints: Some(
+                expressions
+                    .iter()
+                    .map(|expression| rusoto_ecs::TaskDefinitionPlacementConstraint {
+                        expression: expression.map(str::to_owned),
+                        type_: Some("memberOf".to_owned()),
+                    })
+                    .collect(),
+            ),
+            ..task_definition(vec![container(name, "app:1")])
+        };
+        ServiceImage::from_service(
+            &Service {
+                service_name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            Some(&td),
+        )
+    }
+
+    #[test]
+    fn services_with_az_placement_constraints() {
+        let services = vec![
+            constrained(
+                "web",
+                &[Some(
+                    "attribute:ecs.availability-zone in [eu-west-1a, eu-west-1b]",
+                )],
+            ),
+            constrained("api", &[Some("attribute:ecs.instance-type =~ t3.*"), None]),
+            constrained("worker", &[]),
+        ];
+
+        assert_eq!(
+            services[1]
+                .task_definition_metadata
+                .as_ref()
+                .unwrap()
+                .placement_constraints,
+            vec!["attribute:ecs.instance-type =~ t3.*"]
+        );
+        let found: Vec<&str> = find_services_with_az_placement_constraints(&services)
+            .iter()
+            .map(|service| service.service_name.as_str())
+            .collect();
+        assert_eq!(found, vec!["web"]);
+    }
+
+    #[test]
+    fn containers_with_s3_env_files() {
+        let with_files = ContainerDefinition {
+            environment_files: Some(vec![EnvironmentFile {
+                type_: "s3".to_owned(),
+                value: "arn:aws:s3:::config-bucket/web.env".to_owned(),
+            }]),
+            ..container("web", "nginx:1.19")
+        };
+        let images = vec![image_of(with_files), image_of(container("api", "api:2"))];
+
+        assert_eq!(
+            images[0].environment_files,
+            vec![EnvFileRef {
+                s3_arn: "arn:aws:s3:::config-bucket/web.env".to_owned(),
+                type_: "s3".to_owned()
+            }]
+        );
+        assert!(images[1].environment_files.is_empty());
+        assert_eq!(
+            names(&find_containers_with_s3_env_files(&images)),
+            vec!["nginx:1.19"]
+        );
+    }
+
+    fn sized(cpu: Option<&str>, memory: Option<&str>, desired_count: i64) -> ServiceImage {
+        let td = TaskDefinition {
+            cpu: cpu.map(str::to_owned),
+            memory: memory.map(str::to_owned),
+            ..task_definition(vec![container("web", "nginx:1.19")])
+        };
+        let service = Service {
+            service_name: Some("web".to_owned()),
+            desired_count: Some(desired_count),
+            ..Default::default()
+        };
+        ServiceImage::from_service(&service, Some(&td))
+    }
+
+    #[test]
+    fn parse_vcpu_units_and_vcpus() {
+        assert_eq!(parse_vcpu("1024"), Some(1.0));
+        assert_eq!(parse_vcpu("256"), Some(0.25));
+        assert_eq!(parse_vcpu("0.5 vCPU"), Some(0.5));
+        assert_eq!(parse_vcpu("2 VCPU"), Some(2.0));
+        assert_eq!(parse_vcpu("half"), None);
+    }
+
+    #[test]
+    fn parse_memory_mib_and_gb() {
+        assert_eq!(parse_memory_gb("2048"), Some(2.0));
+        assert_eq!(parse_memory_gb("512"), Some(0.5));
+        assert_eq!(parse_memory_gb("4 GB"), Some(4.0));
+        assert_eq!(parse_memory_gb("1gb"), Some(1.0));
+        assert_eq!(parse_memory_gb("lots"), None);
+    }
+
+    #[test]
+    fn estimate_hourly_cost_of_desired_tasks() {
+        let cost =
+            estimate_hourly_cost_usd(&sized(Some("512"), Some("1 GB"), 2), 0.04, 0.004).unwrap();
+        assert!((cost - 0.048).abs() < 1e-9);
+        assert_eq!(
+            estimate_hourly_cost_usd(&sized(Some("512"), Some("1 GB"), 0), 0.04, 0.004),
+            Some(0.0)
+        );
+        assert_eq!(
+            estimate_hourly_cost_usd(&sized(None, Some("1 GB"), 2), 0.04, 0.004),
+            None
+        );
+        assert_eq!(
+            estimate_hourly_cost_usd(&sized(Some("512"), Some("lots"), 2), 0.04, 0.004),
+            None
+        );
+        assert_eq!(
+            estimate_hourly_cost_usd(
+                &ServiceImage::from_service(&Service::default(), None),
+                0.04,
+                0.004
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn services_without_a_described_task_definition_match_no_task_filter() {
+        let service = Service {
+            service_name: Some("web".to_owned()),
+            desired_count: Some(2),
+            ..Default::default()
+        };
+        let services = vec![ServiceImage::from_service(&service, None)];
+
+        assert_eq!(services[0].task_definition_metadata, None);
+        assert!(find_services_with_shared_pid_namespace(&services).is_empty());
+        assert!(find_services_with_shared_ipc(&services).is_empty());
+        assert!(find_services_with_app_mesh(&services).is_empty());
+        assert!(find_services_using_elastic_inference(&services).is_empty());
+        assert!(find_services_with_az_placement_constraints(&services).is_empty());
+        assert_eq!(estimate_hourly_cost_usd(&services[0], 0.04, 0.004), None);
+    }
+}
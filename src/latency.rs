@@ -0,0 +1,210 @@
+use crate::error;
+
+use rusoto_core::RusotoError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationLatency {
+    // rusoto method name, e.g. describe_services
+    pub operation_name: String,
+    // empty for account wide calls such as list_clusters
+    pub cluster: String,
+    pub call_count: u32,
+    // calls rejected with a ThrottlingException, included in call_count
+    #[serde(default)]
+    pub throttled_count: u32,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ApiLatencyReport {
+    pub operations: Vec<OperationLatency>,
+}
+
+pub fn slowest_operations(report: &ApiLatencyReport, n: usize) -> Vec<&OperationLatency> {
+    let mut operations: Vec<&OperationLatency> = report.operations.iter().collect();
+    operations.sort_by_key(|op| std::cmp::Reverse(op.max_duration));
+    operations.truncate(n);
+    operations
+}
+
+#[derive(Default)]
+struct CallStats {
+    call_count: u32,
+    throttled_count: u32,
+    // cluster scans retried because this operation was throttled
+    retry_count: u32,
+    total_duration: Duration,
+    max_duration: Duration,
+}
+
+// Times and counts the API calls made while scanning a cluster
+#[derive(Default)]
+pub struct ApiRecorder {
+    stats: Mutex<BTreeMap<&'static str, CallStats>>,
+    last_throttled: Mutex<Option<&'static str>>,
+}
+
+impl ApiRecorder {
+    pub async fn record<T, E: Error + Send + Sync + 'static>(
+        &self,
+        operation_name: &'static str,
+        call: impl Future<Output = Result<T, RusotoError<E>>>,
+    ) -> anyhow::Result<T> {
+        let started_at = Instant::now();
+        let res = call.await.map_err(error::from_rusoto);
+        let elapsed = started_at.elapsed();
+
+        let mut stats = self.stats.lock().unwrap();
+        let op_stats = stats.entry(operation_name).or_default();
+        op_stats.call_count += 1;
+        op_stats.total_duration += elapsed;
+        op_stats.max_duration = op_stats.max_duration.max(elapsed);
+        if res.as_ref().is_err_and(error::is_throttled) {
+            op_stats.throttled_count += 1;
+            *self.last_throttled.lock().unwrap() = Some(operation_name);
+        }
+        res
+    }
+
+    // operation of the most recent throttled call, the one a retry is blamed on
+    pub fn last_throttled_operation(&self) -> Option<&'static str> {
+        *self.last_throttled.lock().unwrap()
+    }
+
+    pub fn record_retry(&self, operation_name: &'static str) {
+        self.stats
+            .lock()
+            .unwrap()
+            .entry(operation_name)
+            .or_default()
+            .retry_count += 1;
+    }
+
+    // retries per operation, as recorded by record_retry
+    pub fn retry_counts(&self) -> BTreeMap<String, u32> {
+        self.stats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, op_stats)| op_stats.retry_count > 0)
+            .map(|(operation_name, op_stats)| ((*operation_name).to_owned(), op_stats.retry_count))
+            .collect()
+    }
+
+    pub fn call_count(&self) -> u32 {
+        self.stats
+            .lock()
+            .unwrap()
+            .values()
+            .map(|op_stats| op_stats.call_count)
+            .sum()
+    }
+
+    pub fn latencies(&self, cluster: &str) -> Vec<OperationLatency> {
+        self.stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(operation_name, op_stats)| OperationLatency {
+                operation_name: (*operation_name).to_owned(),
+                cluster: cluster.to_owned(),
+                call_count: op_stats.call_count,
+                throttled_count: op_stats.throttled_count,
+                total_duration: op_stats.total_duration,
+                max_duration: op_stats.max_duration,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, MockEcsClient};
+    use rusoto_ecs::{Ecs, ListClustersRequest, ListServicesError};
+
+    fn latency(operation_name: &str, max_millis: u64) -> OperationLatency {
+        OperationLatency {
+            operation_name: operation_name.to_owned(),
+            cluster: String::new(),
+            call_count: 1,
+            throttled_count: 0,
+            total_duration: Duration::from_millis(max_millis),
+            max_duration: Duration::from_millis(max_millis),
+        }
+    }
+
+    #[test]
+    fn slowest_operations_by_max_duration() {
+        let report = ApiLatencyReport {
+            operations: vec![
+                latency("list_services", 20),
+                latency("describe_services", 90),
+                latency("list_clusters", 5),
+            ],
+        };
+
+        let slowest: Vec<&str> = slowest_operations(&report, 2)
+            .iter()
+            .map(|op| op.operation_name.as_str())
+            .collect();
+        assert_eq!(slowest, vec!["describe_services", "list_services"]);
+    }
+
+    async fn succeeded() -> Result<(), RusotoError<ListServicesError>> {
+        Ok(())
+    }
+
+    #[test]
+    fn recorder_counts_calls_per_operation() {
+        let recorder = ApiRecorder::default();
+        block_on(async {
+            recorder.record("list_services", succeeded()).await.unwrap();
+            recorder.record("list_services", succeeded()).await.unwrap();
+            recorder
+                .record("describe_services", succeeded())
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(recorder.call_count(), 3);
+        let latencies = recorder.latencies("prod");
+        let counts: Vec<(&str, u32)> = latencies
+            .iter()
+            .map(|op| (op.operation_name.as_str(), op.call_count))
+            .collect();
+        assert_eq!(counts, vec![("describe_services", 1), ("list_services", 2)]);
+        assert!(latencies.iter().all(|op| op.cluster == "prod"));
+    }
+
+    #[test]
+    fn throttled_calls_are_reported_per_operation() {
+        let mock = MockEcsClient::default().fail_next("ListClusters", 1, "ThrottlingException");
+        let client = mock.client();
+        let recorder = ApiRecorder::default();
+        block_on(async {
+            let list_clusters = || client.list_clusters(ListClustersRequest::default());
+            assert!(recorder
+                .record("list_clusters", list_clusters())
+                .await
+                .is_err());
+            recorder
+                .record("list_clusters", list_clusters())
+                .await
+                .unwrap();
+        });
+
+        let latencies = recorder.latencies("");
+        assert_eq!(latencies[0].call_count, 2);
+        assert_eq!(latencies[0].throttled_count, 1);
+        assert_eq!(recorder.last_throttled_operation(), Some("list_clusters"));
+    }
+}
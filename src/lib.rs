@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate anyhow;
+
+pub mod arn;
+pub mod checkpoint;
+pub mod client;
+pub mod config;
+pub mod credentials;
+pub mod dynamodb;
+pub mod ecr;
+pub mod ecs;
+pub mod error;
+pub mod fixture;
+pub mod github;
+pub mod graph;
+pub mod iam;
+pub mod image;
+pub mod latency;
+pub mod policy;
+pub mod processor;
+pub mod secrets;
+pub mod slack;
+
+#[cfg(test)]
+mod test_helpers;
@@ -1,25 +1,16 @@
-#[macro_use]
-extern crate log;
-
-#[macro_use]
-extern crate anyhow;
-
-mod client;
-mod config;
-mod credentials;
-mod ecs;
-
 use anyhow::Result;
+use aws_list_ecs_images::config::Config;
+use aws_list_ecs_images::{arn, client, credentials, ecs};
 use clap::Clap;
-use config::Config;
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use console::style;
 use futures::future::join_all;
 use rusoto_ecs::EcsClient;
 
-use ecs::Image;
+use aws_list_ecs_images::ecs::{ClusterFilter, ClusterScanStatus, ScanOptions, ScanOutcome};
+use aws_list_ecs_images::image::Image;
+use aws_list_ecs_images::policy;
 
 #[derive(Clap)]
 #[clap(version = "0.1")]
@@ -32,36 +23,31 @@ struct CliOpts {
 
     #[clap(short = "c", long = "cluster_include")]
     cluster_includes: Vec<String>,
-}
 
-fn get_cluster_short_name(cluster: &String) -> String {
-    cluster.split("/").last().clone().unwrap().to_owned()
+    #[clap(short = "x", long = "cluster_exclude")]
+    cluster_excludes: Vec<String>,
+
+    #[clap(long = "rich")]
+    rich_display: bool,
+
+    // exit with 1 for latest tags, 2 for policy violations, 3 for scan errors
+    #[clap(long = "exit_code")]
+    exit_code: bool,
 }
 
 fn get_image_short_name(image: &Image) -> String {
     format!(
         "{}:{}",
-        image
-            .task_definition_name
-            .split("/")
-            .last()
-            .and_then(|l| l.split(":").next())
-            .unwrap()
-            .to_owned(),
-        image
-            .image_name
-            .split("/")
-            .last()
-            .clone()
-            .unwrap()
-            .to_owned()
+        arn::task_definition_family(&image.task_definition_name),
+        image.image_name.rsplit('/').next().unwrap().to_owned()
     )
 }
 
 fn print_results(
-    all_clusters_images: &Vec<HashMap<String, Vec<Image>>>,
-    roles: &Vec<String>,
+    all_clusters_images: &[ScanOutcome],
+    roles: &[String],
     config: &Config,
+    rich_display: bool,
 ) {
     for (idx, clusters_images) in all_clusters_images.iter().enumerate() {
         let role = roles.get(idx).unwrap();
@@ -70,9 +56,22 @@ fn print_results(
             None => role,
         };
         println!("{}:", style(role_short_name).cyan());
-        for (cluster, images) in clusters_images {
-            println!("  {}:", style(get_cluster_short_name(cluster)).green());
-            let mut short_images: Vec<String> = images.iter().map(get_image_short_name).collect();
+        for (cluster, scan_result) in &clusters_images.clusters {
+            if scan_result.status == ClusterScanStatus::Skipped {
+                continue;
+            }
+            println!("  {}:", style(arn::cluster_short_name(cluster)).green());
+            let mut short_images: Vec<String> = scan_result
+                .images
+                .iter()
+                .map(|image| {
+                    if rich_display {
+                        format!("{} ({})", image.display_name(), image.image_name)
+                    } else {
+                        get_image_short_name(image)
+                    }
+                })
+                .collect();
             short_images.sort();
             for image in short_images {
                 println!("    {}", image);
@@ -116,28 +115,39 @@ async fn main() -> Result<()> {
 
     let get_creds = join_all(get_creds_futures).await;
 
-    let all_creds_res: Result<Vec<credentials::Credentials>> =
-        get_creds.into_iter().map(|c| c).collect();
+    let all_creds_res: Result<Vec<credentials::Credentials>> = get_creds.into_iter().collect();
 
     let ecs_clients: Vec<EcsClient> = all_creds_res?
         .into_iter()
         .map(|creds| ecs::build_ecs_client(client.clone(), creds))
         .collect();
 
+    let scan_options = ScanOptions {
+        filter: ClusterFilter {
+            cluster_include_patterns: opts.cluster_includes.clone(),
+            cluster_exclude_patterns: opts.cluster_excludes.clone(),
+        },
+        ..Default::default()
+    };
     let get_images_of_clusters_results = join_all(
         ecs_clients
             .iter()
-            .map(|ecs_client| ecs::get_images_of_clusters(&ecs_client, &opts.cluster_includes)),
+            .map(|ecs_client| ecs::get_images_of_clusters(ecs_client, &scan_options)),
     )
     .await;
 
-    let images_of_clusters_res: Result<Vec<HashMap<String, Vec<Image>>>> =
-        get_images_of_clusters_results
-            .into_iter()
-            .map(|ic| ic)
-            .collect();
+    let images_of_clusters_res: Result<Vec<ScanOutcome>> =
+        get_images_of_clusters_results.into_iter().collect();
     let cluster_images = images_of_clusters_res?;
-    print_results(&cluster_images, &role_arns, &config);
+    print_results(&cluster_images, &role_arns, &config, opts.rich_display);
 
+    if opts.exit_code {
+        let exit_code = cluster_images
+            .iter()
+            .map(|outcome| policy::compute_exit_code(outcome, &config.security_policy))
+            .max()
+            .unwrap_or(policy::EXIT_OK);
+        std::process::exit(exit_code);
+    }
     Ok(())
 }
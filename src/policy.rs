@@ -0,0 +1,510 @@
+use crate::ecs::{self, ScanOutcome};
+use crate::image::Image;
+use crate::latency::ApiRecorder;
+use anyhow::Result;
+use rusoto_ecs::{ContainerDefinition, EcsClient, TaskDefinition};
+use serde::{Deserialize, Serialize};
+
+// read from the security_policy entry of the config, unset rules keep the strict default
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityPolicy {
+    pub allow_privileged_containers: bool,
+    pub allow_root_user: bool,
+    pub require_readonly_root_filesystem: bool,
+    pub allow_host_network: bool,
+    pub allow_host_pid: bool,
+}
+
+// capabilities a container should not add, with or without the CAP_ prefix
+#[derive(Debug, Clone)]
+pub struct DangerousCapabilitiesPolicy {
+    pub capabilities: Vec<String>,
+}
+
+impl Default for DangerousCapabilitiesPolicy {
+    fn default() -> Self {
+        DangerousCapabilitiesPolicy {
+            capabilities: [
+                "CAP_NET_ADMIN",
+                "CAP_SYS_PTRACE",
+                "CAP_SYS_MODULE",
+                "CAP_SYS_RAWIO",
+                "CAP_AUDIT_WRITE",
+            ]
+            .iter()
+            .map(|cap| cap.to_string())
+            .collect(),
+        }
+    }
+}
+
+pub fn find_containers_with_dangerous_capabilities<'a>(
+    images: &'a [Image],
+    policy: &DangerousCapabilitiesPolicy,
+) -> Vec<&'a Image> {
+    // ECS lists capabilities without the prefix, e.g. NET_ADMIN
+    let dangerous: Vec<&str> = policy
+        .capabilities
+        .iter()
+        .map(|cap| cap.trim_start_matches("CAP_"))
+        .collect();
+    images
+        .iter()
+        .filter(|image| {
+            image.linux_parameters.as_ref().is_some_and(|linux| {
+                linux
+                    .add_capabilities
+                    .iter()
+                    .any(|added| dangerous.contains(&added.trim_start_matches("CAP_")))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyViolation {
+    pub rule_name: String,
+    pub container_name: String,
+    pub description: String,
+}
+
+// containers without an explicit user run as root
+pub fn is_root_user(user: Option<&str>) -> bool {
+    match user {
+        None => true,
+        Some(user) => {
+            let name = user.split(':').next().unwrap();
+            name.is_empty() || name == "0" || name == "root"
+        }
+    }
+}
+
+// placeholder images of deregistered task definitions have no user to check
+pub fn find_containers_running_as_root(images: &[Image]) -> Vec<&Image> {
+    images
+        .iter()
+        .filter(|image| {
+            image
+                .linux_parameters
+                .as_ref()
+                .is_some_and(|linux| is_root_user(linux.user.as_deref()))
+        })
+        .collect()
+}
+
+pub async fn get_task_definition_policy_violations(
+    ecs_client: &EcsClient,
+    task_definition: &str,
+    policy: &SecurityPolicy,
+) -> Result<Vec<PolicyViolation>> {
+    let td = ecs::describe_task_definition(
+        ecs_client,
+        task_definition.to_owned(),
+        &ApiRecorder::default(),
+    )
+    .await?
+    .ok_or_else(|| anyhow!("Task definition {} not found", task_definition))?;
+    Ok(check_task_definition(&td, policy))
+}
+
+pub fn check_task_definition(td: &TaskDefinition, policy: &SecurityPolicy) -> Vec<PolicyViolation> {
+    let mut violations: Vec<PolicyViolation> = Vec::new();
+    for cd in td.container_definitions.iter().flatten() {
+        check_container_definition(td, cd, policy, &mut violations);
+    }
+    violations
+}
+
+fn check_container_definition(
+    td: &TaskDefinition,
+    cd: &ContainerDefinition,
+    policy: &SecurityPolicy,
+    violations: &mut Vec<PolicyViolation>,
+) {
+    let container_name = cd.name.clone().unwrap_or_default();
+    let mut violation = |rule_name: &str, description: &str| {
+        violations.push(PolicyViolation {
+            rule_name: rule_name.to_owned(),
+            container_name: container_name.clone(),
+            description: description.to_owned(),
+        })
+    };
+
+    if !policy.allow_privileged_containers && cd.privileged == Some(true) {
+        violation(
+            "allow_privileged_containers",
+            "container runs in privileged mode",
+        );
+    }
+    if !policy.allow_root_user && is_root_user(cd.user.as_deref()) {
+        violation("allow_root_user", "container runs as the root user");
+    }
+    if policy.require_readonly_root_filesystem && cd.readonly_root_filesystem != Some(true) {
+        violation(
+            "require_readonly_root_filesystem",
+            "container root filesystem is writable",
+        );
+    }
+    // network and pid modes are set on the task but apply to every container
+    if !policy.allow_host_network && td.network_mode.as_deref() == Some("host") {
+        violation("allow_host_network", "container uses the host network");
+    }
+    if !policy.allow_host_pid && td.pid_mode.as_deref() == Some("host") {
+        violation("allow_host_pid", "container shares the host pid namespace");
+    }
+}
+
+// same rules as check_task_definition, from what a scan keeps of each container.
+// Network and pid modes are not captured on Image and are not checked.
+pub fn check_image(image: &Image, policy: &SecurityPolicy) -> Vec<PolicyViolation> {
+    let mut violations: Vec<PolicyViolation> = Vec::new();
+    let linux = match image.linux_parameters {
+        Some(ref linux) => linux,
+        None => return violations,
+    };
+    let mut violation = |rule_name: &str, description: &str| {
+        violations.push(PolicyViolation {
+            rule_name: rule_name.to_owned(),
+            container_name: image.container_name.clone(),
+            description: description.to_owned(),
+        })
+    };
+
+    if !policy.allow_privileged_containers && linux.privileged {
+        violation(
+            "allow_privileged_containers",
+            "container runs in privileged mode",
+        );
+    }
+    if !policy.allow_root_user && is_root_user(linux.user.as_deref()) {
+        violation("allow_root_user", "container runs as the root user");
+    }
+    if policy.require_readonly_root_filesystem && !linux.read_only_root_filesystem {
+        violation(
+            "require_readonly_root_filesystem",
+            "container root filesystem is writable",
+        );
+    }
+    violations
+}
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_LATEST_TAGGED_IMAGES: i32 = 1;
+pub const EXIT_POLICY_VIOLATIONS: i32 = 2;
+pub const EXIT_SCAN_ERRORS: i32 = 3;
+
+// the most severe finding wins: scan errors, then policy violations, then latest tags
+pub fn compute_exit_code(outcome: &ScanOutcome, policy: &SecurityPolicy) -> i32 {
+    if !outcome.errors.is_empty() || !outcome.service_errors.is_empty() {
+        return EXIT_SCAN_ERRORS;
+    }
+    // every container definition counts, a root sidecar is as much a violation as the app
+    let policy_violations = outcome
+        .clusters
+        .values()
+        .flat_map(|scan_result| scan_result.task_definitions.iter())
+        .any(|td| !check_task_definition(td, policy).is_empty());
+    if policy_violations {
+        return EXIT_POLICY_VIOLATIONS;
+    }
+    let latest_tagged_images = outcome
+        .clusters
+        .values()
+        .flat_map(|scan_result| scan_result.images.iter())
+        .any(|image| image.uses_latest_tag());
+    if latest_tagged_images {
+        return EXIT_LATEST_TAGGED_IMAGES;
+    }
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::{get_images_of_clusters, ScanOptions};
+    use crate::test_helpers::service;
+    use crate::test_helpers::{block_on, container, task_definition, MockEcsClient};
+    use rusoto_ecs::{KernelCapabilities, LinuxParameters};
+
+    const TD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:task-definition/web:3";
+
+    fn violations_of(cd: ContainerDefinition, policy: &SecurityPolicy) -> Vec<PolicyViolation> {
+        let mock = MockEcsClient::default().with_task_definition(TD_ARN, task_definition(vec![cd]));
+        block_on(get_task_definition_policy_violations(
+            &mock.client(),
+            TD_ARN,
+            policy,
+        ))
+        .unwrap()
+    }
+
+    fn rule_names(violations: &[PolicyViolation]) -> Vec<&str> {
+        violations.iter().map(|v| v.rule_name.as_str()).collect()
+    }
+
+    #[test]
+    fn privileged_container_is_a_violation() {
+        let privileged = ContainerDefinition {
+            privileged: Some(true),
+            user: Some("1000".to_owned()),
+            ..container("web", "nginx:1.19")
+        };
+        let violations = violations_of(privileged.clone(), &SecurityPolicy::default());
+        assert_eq!(rule_names(&violations), vec!["allow_privileged_containers"]);
+        assert_eq!(violations[0].container_name, "web");
+
+        let policy = SecurityPolicy {
+            allow_privileged_containers: true,
+            ..Default::default()
+        };
+        assert!(violations_of(privileged, &policy).is_empty());
+    }
+
+    #[test]
+    fn non_root_container_passes_root_user_check() {
+        let non_root = ContainerDefinition {
+            user: Some("nginx".to_owned()),
+            ..container("web", "nginx:1.19")
+        };
+        assert!(violations_of(non_root, &SecurityPolicy::default()).is_empty());
+
+        let root = container("web", "nginx:1.19");
+        assert_eq!(
+            rule_names(&violations_of(root, &SecurityPolicy::default())),
+            vec!["allow_root_user"]
+        );
+    }
+
+    #[test]
+    fn root_users() {
+        assert!(is_root_user(None));
+        assert!(is_root_user(Some("")));
+        assert!(is_root_user(Some("root")));
+        assert!(is_root_user(Some("0")));
+        assert!(is_root_user(Some("0:0")));
+        assert!(is_root_user(Some("root:docker")));
+    }
+
+    #[test]
+    fn non_root_users() {
+        assert!(!is_root_user(Some("1000")));
+        assert!(!is_root_user(Some("1000:0")));
+        assert!(!is_root_user(Some("nginx")));
+    }
+
+    const PROD_ARN: &str = "arn:aws:ecs:eu-west-1:123456789012:cluster/prod";
+
+    fn exit_code_of(containers: Vec<ContainerDefinition>, policy: &SecurityPolicy) -> i32 {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![service("web", TD_ARN)])
+            .with_task_definition(TD_ARN, task_definition(containers));
+        let outcome = block_on(get_images_of_clusters(
+            &mock.client(),
+            &ScanOptions::default(),
+        ))
+        .unwrap();
+        compute_exit_code(&outcome, policy)
+    }
+
+    fn non_root(name: &str, image: &str) -> ContainerDefinition {
+        ContainerDefinition {
+            user: Some("1000".to_owned()),
+            ..container(name, image)
+        }
+    }
+
+    #[test]
+    fn clean_scan_exits_ok() {
+        assert_eq!(
+            exit_code_of(
+                vec![non_root("web", "nginx:1.19")],
+                &SecurityPolicy::default()
+            ),
+            EXIT_OK
+        );
+    }
+
+    #[test]
+    fn latest_tag_exits_one() {
+        assert_eq!(
+            exit_code_of(vec![non_root("web", "nginx")], &SecurityPolicy::default()),
+            EXIT_LATEST_TAGGED_IMAGES
+        );
+    }
+
+    #[test]
+    fn policy_violation_outranks_latest_tag() {
+        let root = container("web", "nginx:latest");
+        assert_eq!(
+            exit_code_of(vec![root.clone()], &SecurityPolicy::default()),
+            EXIT_POLICY_VIOLATIONS
+        );
+
+        let policy = SecurityPolicy {
+            allow_root_user: true,
+            ..Default::default()
+        };
+        assert_eq!(exit_code_of(vec![root], &policy), EXIT_LATEST_TAGGED_IMAGES);
+    }
+
+    #[test]
+    fn sidecars_count_towards_the_exit_code() {
+        let privileged_sidecar = ContainerDefinition {
+            privileged: Some(true),
+            ..non_root("xray", "amazon/aws-xray-daemon:3.2.0")
+        };
+        // the image of the service is the last container, the sidecar comes first
+        let containers = vec![privileged_sidecar, non_root("web", "nginx:1.19")];
+        assert_eq!(
+            exit_code_of(containers.clone(), &SecurityPolicy::default()),
+            EXIT_POLICY_VIOLATIONS
+        );
+
+        let policy = SecurityPolicy {
+            allow_privileged_containers: true,
+            ..Default::default()
+        };
+        assert_eq!(exit_code_of(containers, &policy), EXIT_OK);
+    }
+
+    #[test]
+    fn host_network_counts_towards_the_exit_code() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(PROD_ARN, vec![service("web", TD_ARN)])
+            .with_task_definition(
+                TD_ARN,
+                TaskDefinition {
+                    network_mode: Some("host".to_owned()),
+                    ..task_definition(vec![non_root("web", "nginx:1.19")])
+                },
+            );
+        let outcome = block_on(get_images_of_clusters(
+            &mock.client(),
+            &ScanOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            compute_exit_code(&outcome, &SecurityPolicy::default()),
+            EXIT_POLICY_VIOLATIONS
+        );
+    }
+
+    #[test]
+    fn policy_reads_partial_config() {
+        let policy: SecurityPolicy = serde_json::from_str(r#"{"allow_root_user": true}"#).unwrap();
+        assert!(policy.allow_root_user);
+        assert!(!policy.allow_privileged_containers);
+        assert!(!policy.require_readonly_root_filesystem);
+    }
+
+    #[test]
+    fn scan_errors_outrank_everything() {
+        let mock = MockEcsClient::with_clusters(vec![PROD_ARN])
+            .with_services(
+                PROD_ARN,
+                vec![service("web", TD_ARN), service("orphan", "orphan:1")],
+            )
+            .with_task_definition(TD_ARN, task_definition(vec![container("web", "nginx")]));
+        let outcome = block_on(get_images_of_clusters(
+            &mock.client(),
+            &ScanOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(outcome.service_errors.len(), 1);
+        assert_eq!(
+            compute_exit_code(&outcome, &SecurityPolicy::default()),
+            EXIT_SCAN_ERRORS
+        );
+    }
+
+    fn adding(container_name: &str, add: &[&str]) -> Image {
+        let cd = ContainerDefinition {
+            linux_parameters: Some(LinuxParameters {
+                capabilities: Some(KernelCapabilities {
+                    add: Some(add.iter().map(|cap| (*cap).to_owned()).collect()),
+                    drop: None,
+                }),
+                ..Default::default()
+            }),
+            ..container(container_name, "app:1")
+        };
+        let td = TaskDefinition {
+            task_definition_arn: Some(TD_ARN.to_owned()),
+            ..task_definition(vec![cd])
+        };
+        Image::from_task_definition(&td, "prod".to_owned(), "web".to_owned()).unwrap()
+    }
+
+    fn container_names<'a>(images: &[&'a Image]) -> Vec<&'a str> {
+        images
+            .iter()
+            .map(|image| image.container_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn dangerous_capabilities_with_or_without_prefix() {
+        let images = vec![
+            adding("net", &["NET_ADMIN"]),
+            adding("ptrace", &["CAP_SYS_PTRACE"]),
+            adding("chown", &["CHOWN"]),
+            adding("plain", &[]),
+        ];
+
+        let found = find_containers_with_dangerous_capabilities(
+            &images,
+            &DangerousCapabilitiesPolicy::default(),
+        );
+        assert_eq!(container_names(&found), vec!["net", "ptrace"]);
+    }
+
+    #[test]
+    fn dangerous_capability_list_is_configurable() {
+        let images = vec![adding("net", &["NET_ADMIN"]), adding("chown", &["CHOWN"])];
+        let policy = DangerousCapabilitiesPolicy {
+            capabilities: vec!["CAP_CHOWN".to_owned()],
+        };
+
+        assert_eq!(
+            container_names(&find_containers_with_dangerous_capabilities(
+                &images, &policy
+            )),
+            vec!["chown"]
+        );
+    }
+
+    fn running_as(container_name: &str, user: Option<&str>) -> Image {
+        let cd = ContainerDefinition {
+            user: user.map(str::to_owned),
+            ..container(container_name, "app:1")
+        };
+        let td = TaskDefinition {
+            task_definition_arn: Some(TD_ARN.to_owned()),
+            ..task_definition(vec![cd])
+        };
+        Image::from_task_definition(&td, "prod".to_owned(), "web".to_owned()).unwrap()
+    }
+
+    #[test]
+    fn containers_running_as_root() {
+        let td = TaskDefinition {
+            task_definition_arn: Some(TD_ARN.to_owned()),
+            ..Default::default()
+        };
+        let images = vec![
+            running_as("unset", None),
+            running_as("root", Some("root")),
+            running_as("uid", Some("0:0")),
+            running_as("app", Some("1000:1000")),
+            Image::deregistered(&td, "prod".to_owned(), "old".to_owned()),
+        ];
+
+        assert_eq!(
+            container_names(&find_containers_running_as_root(&images)),
+            vec!["unset", "root", "uid"]
+        );
+    }
+}
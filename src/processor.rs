@@ -0,0 +1,148 @@
+use crate::ecr;
+use crate::image::Image;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rusoto_ecr::EcrClient;
+use std::sync::Arc;
+
+// Post-processing step run on every image found by a scan, in ScanOptions order
+#[async_trait]
+pub trait ImageProcessor: Send + Sync {
+    async fn process(&self, image: &mut Image) -> Result<()>;
+}
+
+pub struct LatestTagWarningProcessor;
+
+#[async_trait]
+impl ImageProcessor for LatestTagWarningProcessor {
+    async fn process(&self, image: &mut Image) -> Result<()> {
+        if image.uses_latest_tag() {
+            image.warnings.push(format!(
+                "{} resolves to latest at pull time",
+                image.image_name
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub struct EcrDigestEnricher {
+    pub ecr_client: Arc<EcrClient>,
+}
+
+#[async_trait]
+impl ImageProcessor for EcrDigestEnricher {
+    async fn process(&self, image: &mut Image) -> Result<()> {
+        image.enriched_image_name =
+            ecr::resolve_digest(&self.ecr_client, &image.image_name).await?;
+        Ok(())
+    }
+}
+
+pub async fn run_processors(
+    processors: &[Arc<dyn ImageProcessor>],
+    images: &mut [Image],
+) -> Result<()> {
+    for image in images.iter_mut() {
+        for processor in processors {
+            processor.process(image).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{block_on, MockEcrClient};
+
+    // appends its name to the warnings, to observe the order processors run in
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl ImageProcessor for Named {
+        async fn process(&self, image: &mut Image) -> Result<()> {
+            image.warnings.push(self.0.to_owned());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl ImageProcessor for Failing {
+        async fn process(&self, image: &mut Image) -> Result<()> {
+            Err(anyhow!("cannot process {}", image.image_name))
+        }
+    }
+
+    fn images_named(image_names: &[&str]) -> Vec<Image> {
+        image_names
+            .iter()
+            .map(|image_name| Image {
+                image_name: (*image_name).to_owned(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn processors_run_in_order_on_every_image() {
+        let processors: Vec<Arc<dyn ImageProcessor>> =
+            vec![Arc::new(Named("first")), Arc::new(Named("second"))];
+        let mut images = images_named(&["nginx:1.19", "api:2"]);
+        block_on(run_processors(&processors, &mut images)).unwrap();
+
+        for image in &images {
+            assert_eq!(image.warnings, vec!["first", "second"]);
+        }
+    }
+
+    #[test]
+    fn processor_error_propagates_and_stops_the_pipeline() {
+        let processors: Vec<Arc<dyn ImageProcessor>> =
+            vec![Arc::new(Failing), Arc::new(Named("after"))];
+        let mut images = images_named(&["nginx:1.19"]);
+        let err = block_on(run_processors(&processors, &mut images)).unwrap_err();
+
+        assert_eq!(err.to_string(), "cannot process nginx:1.19");
+        assert!(images[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn latest_tag_warning_for_untagged_and_latest_images() {
+        let mut images = images_named(&["nginx", "nginx:latest", "nginx:1.19", "nginx@sha256:abc"]);
+        let processors: Vec<Arc<dyn ImageProcessor>> = vec![Arc::new(LatestTagWarningProcessor)];
+        block_on(run_processors(&processors, &mut images)).unwrap();
+
+        let warned: Vec<&str> = images
+            .iter()
+            .filter(|image| !image.warnings.is_empty())
+            .map(|image| image.image_name.as_str())
+            .collect();
+        assert_eq!(warned, vec!["nginx", "nginx:latest"]);
+        assert_eq!(
+            images[0].warnings,
+            vec!["nginx resolves to latest at pull time"]
+        );
+    }
+
+    #[test]
+    fn ecr_digest_enricher_fills_the_enriched_name() {
+        let ecr = MockEcrClient::default().with_image("web", "1.19", "sha256:abc");
+        let enricher = EcrDigestEnricher {
+            ecr_client: Arc::new(ecr.client()),
+        };
+        let mut image = Image {
+            image_name: "123456789012.dkr.ecr.eu-west-1.amazonaws.com/web:1.19".to_owned(),
+            ..Default::default()
+        };
+        block_on(enricher.process(&mut image)).unwrap();
+
+        assert_eq!(
+            image.enriched_image_name.as_deref(),
+            Some("123456789012.dkr.ecr.eu-west-1.amazonaws.com/web@sha256:abc")
+        );
+    }
+}
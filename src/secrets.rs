@@ -0,0 +1,146 @@
+use crate::image::Image;
+
+use rusoto_ecs::{ContainerDefinition, Secret};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// name of the variable in the container and the Secrets Manager or SSM parameter ARN it is read from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SecretRef {
+    pub name: String,
+    pub value_from: String,
+}
+
+impl From<&Secret> for SecretRef {
+    fn from(secret: &Secret) -> Self {
+        SecretRef {
+            name: secret.name.clone(),
+            value_from: secret.value_from.clone(),
+        }
+    }
+}
+
+// arn:aws:secretsmanager:{region}:{account}:secret:{name} or arn:aws:ssm:{region}:{account}:parameter/{name}
+fn is_secret_arn(value: &str) -> bool {
+    let mut parts = value.splitn(4, ':');
+    parts.next() == Some("arn")
+        && parts.next().is_some()
+        && matches!(parts.next(), Some("secretsmanager") | Some("ssm"))
+}
+
+pub fn get_container_environment_variables(
+    container_definition: &ContainerDefinition,
+) -> HashMap<String, String> {
+    container_definition
+        .environment
+        .iter()
+        .flatten()
+        .filter_map(|pair| Some((pair.name.clone()?, pair.value.clone()?)))
+        .collect()
+}
+
+// plain environment variables whose value is a secret ARN rather than the secret itself
+pub fn get_secret_references(environment: &HashMap<String, String>) -> Vec<SecretRef> {
+    let mut references: Vec<SecretRef> = environment
+        .iter()
+        .filter(|(_, value)| is_secret_arn(value))
+        .map(|(name, value)| SecretRef {
+            name: name.clone(),
+            value_from: value.clone(),
+        })
+        .collect();
+    references.sort_by(|a, b| a.name.cmp(&b.name));
+    references
+}
+
+// secrets field first, then environment references not already listed there
+pub fn all_secret_references(image: &Image) -> Vec<&SecretRef> {
+    let mut seen = HashSet::new();
+    image
+        .secrets
+        .iter()
+        .chain(image.environment_secrets.iter())
+        .filter(|secret| seen.insert(*secret))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{container, task_definition};
+    use rusoto_ecs::{KeyValuePair, TaskDefinition};
+
+    const DB_PASSWORD: &str = "arn:aws:secretsmanager:eu-west-1:123456789012:secret:db-password";
+    const API_KEY: &str = "arn:aws:ssm:eu-west-1:123456789012:parameter/api-key";
+
+    fn secret_ref(name: &str, value_from: &str) -> SecretRef {
+        SecretRef {
+            name: name.to_owned(),
+            value_from: value_from.to_owned(),
+        }
+    }
+
+    fn environment(pairs: &[(&str, &str)]) -> Vec<KeyValuePair> {
+        pairs
+            .iter()
+            .map(|(name, value)| KeyValuePair {
+                name: Some((*name).to_owned()),
+                value: Some((*value).to_owned()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn secret_arns_in_plain_environment_variables() {
+        let environment: HashMap<String, String> = [
+            ("DB_PASSWORD", DB_PASSWORD),
+            ("API_KEY", API_KEY),
+            ("BUCKET", "arn:aws:s3:::assets"),
+            ("LOG_LEVEL", "debug"),
+        ]
+        .iter()
+        .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+        .collect();
+
+        assert_eq!(
+            get_secret_references(&environment),
+            vec![
+                secret_ref("API_KEY", API_KEY),
+                secret_ref("DB_PASSWORD", DB_PASSWORD)
+            ]
+        );
+    }
+
+    #[test]
+    fn image_lists_secrets_and_environment_references_once() {
+        let cd = ContainerDefinition {
+            secrets: Some(vec![Secret {
+                name: "DB_PASSWORD".to_owned(),
+                value_from: DB_PASSWORD.to_owned(),
+            }]),
+            environment: Some(environment(&[
+                ("DB_PASSWORD", DB_PASSWORD),
+                ("API_KEY", API_KEY),
+                ("LOG_LEVEL", "debug"),
+            ])),
+            ..container("app", "app:1")
+        };
+        let td = TaskDefinition {
+            task_definition_arn: Some(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/app:1".to_owned(),
+            ),
+            ..task_definition(vec![cd])
+        };
+        let image = Image::from_task_definition(&td, "prod".to_owned(), "app".to_owned()).unwrap();
+
+        assert_eq!(image.secrets, vec![secret_ref("DB_PASSWORD", DB_PASSWORD)]);
+        assert_eq!(image.environment_secrets.len(), 2);
+        assert_eq!(
+            all_secret_references(&image),
+            vec![
+                &secret_ref("DB_PASSWORD", DB_PASSWORD),
+                &secret_ref("API_KEY", API_KEY)
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::client;
+use crate::ecs::FleetSummary;
+use crate::image::{Image, ImageDiff};
+
+use anyhow::Result;
+use hyper::{Body, Method, Request};
+use serde_json::{json, Value};
+
+// section text is limited to 3000 characters by Slack
+const SECTION_TEXT_MAX_LEN: usize = 3000;
+
+fn truncate(mut text: String) -> String {
+    if text.len() > SECTION_TEXT_MAX_LEN {
+        let mut end = SECTION_TEXT_MAX_LEN - 3;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    text
+}
+
+fn markdown_section(text: String) -> Value {
+    json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": truncate(text) }
+    })
+}
+
+fn image_line(image: &Image) -> String {
+    format!("• `{}` {}", image.image_name, image.display_name())
+}
+
+// Block Kit payload, see https://api.slack.com/reference/block-kit/blocks
+pub fn format_slack_blocks(diff: &ImageDiff, scan_summary: &FleetSummary) -> Value {
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": "ECS image scan" }
+        }),
+        json!({
+            "type": "section",
+            "fields": [
+                { "type": "mrkdwn", "text": format!("*Clusters*\n{}", scan_summary.cluster_count) },
+                { "type": "mrkdwn", "text": format!("*Images*\n{}", scan_summary.image_count) },
+                { "type": "mrkdwn", "text": format!("*Changes*\n{}", diff.change_count()) }
+            ]
+        }),
+    ];
+
+    if !diff.is_empty() {
+        let mut lines: Vec<String> = Vec::new();
+        lines.extend(
+            diff.added
+                .iter()
+                .map(|i| format!("added {}", image_line(i))),
+        );
+        lines.extend(
+            diff.removed
+                .iter()
+                .map(|i| format!("removed {}", image_line(i))),
+        );
+        lines.extend(diff.changed.iter().map(|(before, after)| {
+            format!(
+                "changed {}: `{}` -> `{}`",
+                after.display_name(),
+                before.image_name,
+                after.image_name
+            )
+        }));
+        blocks.push(json!({ "type": "divider" }));
+        blocks.push(markdown_section(lines.join("\n")));
+    }
+
+    let latest_images: Vec<String> = diff
+        .added
+        .iter()
+        .chain(diff.changed.iter().map(|(_, after)| after))
+        .filter(|image| image.uses_latest_tag())
+        .map(image_line)
+        .collect();
+    if !latest_images.is_empty() {
+        blocks.push(markdown_section(format!(
+            ":warning: *Images using the latest tag*\n{}",
+            latest_images.join("\n")
+        )));
+    }
+
+    json!({ "blocks": blocks })
+}
+
+pub async fn send_slack_notification(webhook_url: &str, blocks: Value) -> Result<()> {
+    let http_client = client::new_hyper_client()?;
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(webhook_url)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_vec(&blocks)?))?;
+    let res = http_client.request(req).await?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        return Err(anyhow!(
+            "Slack webhook returned {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(service_name: &str, image_name: &str) -> Image {
+        Image {
+            service_name: service_name.to_owned(),
+            task_definition_name: format!(
+                "arn:aws:ecs:eu-west-1:123456789012:task-definition/{}:1",
+                service_name
+            ),
+            image_name: image_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn summary() -> FleetSummary {
+        FleetSummary {
+            cluster_count: 2,
+            scanned_cluster_count: 2,
+            service_count: 3,
+            image_count: 3,
+            error_count: 0,
+        }
+    }
+
+    fn text_object(text: &Value) -> &str {
+        let kind = text["type"].as_str().unwrap();
+        assert!(
+            kind == "plain_text" || kind == "mrkdwn",
+            "text object type {}",
+            kind
+        );
+        text["text"].as_str().unwrap()
+    }
+
+    // the block and text object limits of https://api.slack.com/reference/block-kit/blocks
+    fn assert_block_kit(payload: &Value) {
+        let blocks = payload["blocks"].as_array().unwrap();
+        assert!(!blocks.is_empty() && blocks.len() <= 50);
+        for block in blocks {
+            match block["type"].as_str().unwrap() {
+                "header" => {
+                    assert_eq!(block["text"]["type"], "plain_text");
+                    assert!(text_object(&block["text"]).chars().count() <= 150);
+                }
+                "section" => {
+                    assert!(block.get("text").is_some() || block.get("fields").is_some());
+                    if let Some(text) = block.get("text") {
+                        assert!(text_object(text).chars().count() <= 3000);
+                    }
+                    if let Some(fields) = block.get("fields") {
+                        let fields = fields.as_array().unwrap();
+                        assert!(fields.len() <= 10);
+                        assert!(fields
+                            .iter()
+                            .all(|field| text_object(field).chars().count() <= 2000));
+                    }
+                }
+                "divider" => assert_eq!(block.as_object().unwrap().len(), 1),
+                other => panic!("unexpected block type {}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_scan_has_header_and_summary_only() {
+        let payload = format_slack_blocks(&ImageDiff::default(), &summary());
+
+        assert_block_kit(&payload);
+        let blocks = payload["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["type"], "header");
+        let fields: Vec<&str> = blocks[1]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(text_object)
+            .collect();
+        assert_eq!(fields, vec!["*Clusters*\n2", "*Images*\n3", "*Changes*\n0"]);
+    }
+
+    #[test]
+    fn changes_and_latest_tag_warning() {
+        let diff = ImageDiff {
+            added: vec![image("worker", "worker:latest")],
+            removed: vec![image("admin", "admin:1")],
+            changed: vec![(image("web", "nginx:1.18"), image("web", "nginx:1.19"))],
+        };
+        let payload = format_slack_blocks(&diff, &summary());
+
+        assert_block_kit(&payload);
+        let blocks = payload["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks[2]["type"], "divider");
+        assert_eq!(
+            text_object(&blocks[3]["text"]),
+            "added • `worker:latest` worker @ worker:1\n\
+             removed • `admin:1` admin @ admin:1\n\
+             changed web @ web:1: `nginx:1.18` -> `nginx:1.19`"
+        );
+        assert_eq!(
+            text_object(&blocks[4]["text"]),
+            ":warning: *Images using the latest tag*\n• `worker:latest` worker @ worker:1"
+        );
+    }
+
+    #[test]
+    fn long_change_lists_are_truncated_to_the_section_limit() {
+        let diff = ImageDiff {
+            added: (0..200)
+                .map(|i| image(&format!("service-{}", i), "nginx:1.19"))
+                .collect(),
+            ..Default::default()
+        };
+        let payload = format_slack_blocks(&diff, &summary());
+
+        assert_block_kit(&payload);
+        let changes = text_object(&payload["blocks"][3]["text"]);
+        assert_eq!(changes.len(), 3000);
+        assert!(changes.ends_with("..."));
+    }
+}
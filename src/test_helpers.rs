@@ -0,0 +1,845 @@
+// Mock ECS, ECR, IAM, Cloud Map and DynamoDB clients for unit tests.
+//
+// The mocks are real rusoto clients built with a dispatcher that answers from
+// canned data instead of AWS, so the helpers under test run unchanged and no
+// credentials are needed. Every request is recorded for call count assertions.
+
+// each test module only uses the mocks it needs
+#![allow(dead_code)]
+
+use crate::ecr::EcrClients;
+use rusoto_core::region::Region;
+use rusoto_core::request::{DispatchSignedRequestFuture, HttpResponse};
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, DispatchSignedRequest};
+use rusoto_credential::StaticProvider;
+use rusoto_dynamodb::DynamoDbClient;
+use rusoto_ecr::EcrClient;
+use rusoto_ecs::{Cluster, ContainerDefinition, EcsClient, Service, Tag, TaskDefinition};
+use rusoto_iam::IamClient;
+use rusoto_servicediscovery::ServiceDiscoveryClient;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+// One request received by a mock client
+#[derive(Debug, Clone)]
+pub struct MockCall {
+    // x-amz-target without the service prefix, e.g. DescribeServices
+    pub operation: String,
+    pub region: String,
+    pub request: Value,
+    // the exchange as it went over the wire, e.g. to build a replay fixture
+    pub target: String,
+    pub body: String,
+    pub status: u16,
+    pub response: String,
+}
+
+type Answer = (u16, Value);
+type Responder = dyn Fn(&str, &Value) -> Answer + Send + Sync;
+
+#[derive(Default)]
+struct CallLog {
+    calls: Vec<MockCall>,
+    // answers served before the canned data, in order, e.g. a throttling error
+    scripted: HashMap<String, VecDeque<Answer>>,
+}
+
+struct MockDispatcher {
+    log: Arc<Mutex<CallLog>>,
+    responder: Arc<Responder>,
+}
+
+impl DispatchSignedRequest for MockDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let target = request
+            .headers
+            .get("x-amz-target")
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+            .unwrap_or_default();
+        let operation = target.rsplit('.').next().unwrap().to_owned();
+        let raw_body = match request.payload {
+            Some(SignedRequestPayload::Buffer(ref bytes)) => {
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            _ => String::new(),
+        };
+        let body = if raw_body.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(&raw_body).unwrap()
+        };
+        let mut log = self.log.lock().unwrap();
+        let scripted = log
+            .scripted
+            .get_mut(&operation)
+            .and_then(|answers| answers.pop_front());
+        let (status, response) = scripted.unwrap_or_else(|| (self.responder)(&operation, &body));
+        let response = response.to_string();
+        log.calls.push(MockCall {
+            operation,
+            region: request.region.name().to_owned(),
+            request: body,
+            target,
+            body: raw_body,
+            status,
+            response: response.clone(),
+        });
+        Box::pin(async move {
+            Ok(HttpResponse {
+                status: hyper::StatusCode::from_u16(status).unwrap(),
+                headers: Default::default(),
+                body: ByteStream::from(response.into_bytes()),
+            })
+        })
+    }
+}
+
+impl CallLog {
+    fn count(&self, operation: &str) -> usize {
+        self.calls
+            .iter()
+            .filter(|call| call.operation == operation)
+            .count()
+    }
+
+    fn requests(&self, operation: &str) -> Vec<Value> {
+        self.calls
+            .iter()
+            .filter(|call| call.operation == operation)
+            .map(|call| call.request.clone())
+            .collect()
+    }
+}
+
+fn error_answer(error_type: &str, message: &str) -> Answer {
+    (400, json!({ "__type": error_type, "message": message }))
+}
+
+fn credentials() -> StaticProvider {
+    StaticProvider::new_minimal("mock".to_owned(), "mock".to_owned())
+}
+
+fn short_name(arn: &str) -> &str {
+    arn.rsplit('/').next().unwrap()
+}
+
+// arn, name or family:revision of a resource against a request parameter
+fn same_resource(arn: &str, reference: &str) -> bool {
+    arn == reference || short_name(arn) == reference
+}
+
+// e.g. TAGS in the include parameter of the Describe* APIs
+fn includes(request: &Value, field: &str) -> bool {
+    request["include"]
+        .as_array()
+        .is_some_and(|include| include.contains(&json!(field)))
+}
+
+// the given page of values and the token of the next one, like the List* APIs
+fn page(values: Vec<Value>, request: &Value, default_max_results: usize) -> (Vec<Value>, Value) {
+    let start: usize = request["nextToken"]
+        .as_str()
+        .map(|token| token.parse().unwrap())
+        .unwrap_or(0);
+    let max_results = request["maxResults"]
+        .as_u64()
+        .map(|max_results| max_results as usize)
+        .unwrap_or(default_max_results);
+    let end = values.len().min(start + max_results);
+    let next_token = if end < values.len() {
+        json!(end.to_string())
+    } else {
+        Value::Null
+    };
+    (values[start..end].to_vec(), next_token)
+}
+
+#[derive(Default)]
+struct EcsData {
+    clusters: Vec<Cluster>,
+    // (cluster ARN, service), in listing order
+    services: Vec<(String, Service)>,
+    task_definitions: Vec<(TaskDefinition, Vec<Tag>)>,
+    // fixed answers of operations without canned data
+    responses: HashMap<String, Value>,
+    // (cluster ARN, error type) of clusters whose every request fails
+    failing_clusters: Vec<(String, String)>,
+}
+
+impl EcsData {
+    fn cluster_arn(&self, reference: Option<&str>) -> String {
+        let reference = reference.unwrap_or("default");
+        self.clusters
+            .iter()
+            .filter_map(|cluster| cluster.cluster_arn.as_deref())
+            .find(|arn| same_resource(arn, reference))
+            .unwrap_or(reference)
+            .to_owned()
+    }
+
+    fn services_of<'a>(&'a self, cluster_arn: &'a str) -> impl Iterator<Item = &'a Service> {
+        self.services
+            .iter()
+            .filter(move |(arn, _)| arn == cluster_arn)
+            .map(|(_, service)| service)
+    }
+
+    fn respond(&self, operation: &str, request: &Value) -> Answer {
+        if let Some(cluster) = request["cluster"].as_str() {
+            let cluster_arn = self.cluster_arn(Some(cluster));
+            let failure = self
+                .failing_clusters
+                .iter()
+                .find(|(arn, _)| *arn == cluster_arn);
+            if let Some((_, error_type)) = failure {
+                return error_answer(error_type, &format!("scripted failure of {}", cluster_arn));
+            }
+        }
+        match operation {
+            "ListClusters" => {
+                let arns = self
+                    .clusters
+                    .iter()
+                    .map(|cluster| json!(cluster.cluster_arn))
+                    .collect();
+                let (arns, next_token) = page(arns, request, 100);
+                (200, json!({ "clusterArns": arns, "nextToken": next_token }))
+            }
+            "DescribeClusters" => {
+                let references = request["clusters"].as_array().cloned().unwrap_or_default();
+                if references.len() > 100 {
+                    return error_answer(
+                        "InvalidParameterException",
+                        "clusters can have at most 100 items",
+                    );
+                }
+                let mut clusters = Vec::new();
+                let mut failures = Vec::new();
+                for reference in references {
+                    let reference = reference.as_str().unwrap();
+                    let found = self.clusters.iter().find(|cluster| {
+                        same_resource(cluster.cluster_arn.as_deref().unwrap(), reference)
+                    });
+                    match found {
+                        Some(cluster) => {
+                            let mut cluster = cluster.clone();
+                            if !includes(request, "TAGS") {
+                                cluster.tags = None;
+                            }
+                            clusters.push(serde_json::to_value(cluster).unwrap())
+                        }
+                        None => failures.push(json!({ "arn": reference, "reason": "MISSING" })),
+                    }
+                }
+                (200, json!({ "clusters": clusters, "failures": failures }))
+            }
+            "ListServices" => {
+                let cluster_arn = self.cluster_arn(request["cluster"].as_str());
+                let arns = self
+                    .services_of(&cluster_arn)
+                    .filter(|service| {
+                        request["launchType"].as_str().is_none_or(|launch_type| {
+                            service.launch_type.as_deref() == Some(launch_type)
+                        })
+                    })
+                    .filter(|service| {
+                        request["schedulingStrategy"]
+                            .as_str()
+                            .is_none_or(|strategy| {
+                                service.scheduling_strategy.as_deref() == Some(strategy)
+                            })
+                    })
+                    .map(|service| json!(service.service_arn))
+                    .collect();
+                let (arns, next_token) = page(arns, request, 10);
+                (200, json!({ "serviceArns": arns, "nextToken": next_token }))
+            }
+            "DescribeServices" => {
+                let references = request["services"].as_array().cloned().unwrap_or_default();
+                if references.len() > 10 {
+                    return error_answer(
+                        "InvalidParameterException",
+                        "services can have at most 10 items",
+                    );
+                }
+                let cluster_arn = self.cluster_arn(request["cluster"].as_str());
+                let mut services = Vec::new();
+                let mut failures = Vec::new();
+                for reference in references {
+                    let reference = reference.as_str().unwrap();
+                    let found = self.services_of(&cluster_arn).find(|service| {
+                        same_resource(service.service_arn.as_deref().unwrap(), reference)
+                    });
+                    match found {
+                        Some(service) => services.push(serde_json::to_value(service).unwrap()),
+                        None => failures.push(json!({ "arn": reference, "reason": "MISSING" })),
+                    }
+                }
+                (200, json!({ "services": services, "failures": failures }))
+            }
+            "DescribeTaskDefinition" => {
+                let reference = request["taskDefinition"].as_str().unwrap();
+                let found = self
+                    .task_definitions
+                    .iter()
+                    .filter(|(td, _)| {
+                        let arn = td.task_definition_arn.as_deref().unwrap();
+                        same_resource(arn, reference) || td.family.as_deref() == Some(reference)
+                    })
+                    .max_by_key(|(td, _)| td.revision);
+                match found {
+                    Some((td, tags)) => {
+                        let mut response = json!({ "taskDefinition": td });
+                        if includes(request, "TAGS") {
+                            response["tags"] = serde_json::to_value(tags).unwrap();
+                        }
+                        (200, response)
+                    }
+                    None => error_answer("ClientException", "Unable to describe task definition."),
+                }
+            }
+            _ => match self.responses.get(operation) {
+                Some(response) => (200, response.clone()),
+                None => error_answer(
+                    "UnknownOperationException",
+                    &format!("no mock response for {}", operation),
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MockEcsClient {
+    data: Arc<Mutex<EcsData>>,
+    log: Arc<Mutex<CallLog>>,
+}
+
+impl MockEcsClient {
+    pub fn with_clusters(cluster_arns: Vec<&str>) -> Self {
+        cluster_arns
+            .into_iter()
+            .fold(MockEcsClient::default(), |mock, arn| {
+                mock.with_cluster(Cluster {
+                    cluster_arn: Some(arn.to_owned()),
+                    cluster_name: Some(short_name(arn).to_owned()),
+                    status: Some("ACTIVE".to_owned()),
+                    ..Default::default()
+                })
+            })
+    }
+
+    pub fn with_cluster(self, cluster: Cluster) -> Self {
+        self.data.lock().unwrap().clusters.push(cluster);
+        self
+    }
+
+    // services without an ARN get one derived from the cluster and their name
+    pub fn with_services(self, cluster: &str, services: Vec<Service>) -> Self {
+        {
+            let mut data = self.data.lock().unwrap();
+            let cluster_arn = data.cluster_arn(Some(cluster));
+            for mut service in services {
+                if service.service_arn.is_none() {
+                    service.service_arn = Some(format!(
+                        "{}/{}",
+                        cluster_arn.replacen(":cluster/", ":service/", 1),
+                        service.service_name.as_deref().unwrap_or_default()
+                    ));
+                }
+                service.cluster_arn = Some(cluster_arn.clone());
+                data.services.push((cluster_arn.clone(), service));
+            }
+        }
+        self
+    }
+
+    // family and revision are taken from the ARN when the task definition has none
+    pub fn with_task_definition(self, arn: &str, task_definition: TaskDefinition) -> Self {
+        self.with_tagged_task_definition(arn, task_definition, Vec::new())
+    }
+
+    pub fn with_tagged_task_definition(
+        self,
+        arn: &str,
+        mut task_definition: TaskDefinition,
+        tags: Vec<Tag>,
+    ) -> Self {
+        let name = short_name(arn);
+        let mut parts = name.splitn(2, ':');
+        let family = parts.next().unwrap().to_owned();
+        let revision = parts.next().and_then(|revision| revision.parse().ok());
+        task_definition.task_definition_arn = Some(arn.to_owned());
+        task_definition.family = task_definition.family.or(Some(family));
+        task_definition.revision = task_definition.revision.or(revision);
+        self.data
+            .lock()
+            .unwrap()
+            .task_definitions
+            .push((task_definition, tags));
+        self
+    }
+
+    // fixed answer for an operation the canned data does not cover
+    pub fn with_response(self, operation: &str, response: Value) -> Self {
+        self.data
+            .lock()
+            .unwrap()
+            .responses
+            .insert(operation.to_owned(), response);
+        self
+    }
+
+    // every request about the cluster fails with the given error type
+    pub fn fail_cluster(self, cluster_arn: &str, error_type: &str) -> Self {
+        self.data
+            .lock()
+            .unwrap()
+            .failing_clusters
+            .push((cluster_arn.to_owned(), error_type.to_owned()));
+        self
+    }
+
+    // the next `times` calls of the operation fail with the given error type
+    pub fn fail_next(self, operation: &str, times: usize, error_type: &str) -> Self {
+        {
+            let mut log = self.log.lock().unwrap();
+            let answers = log.scripted.entry(operation.to_owned()).or_default();
+            for _ in 0..times {
+                answers.push_back(error_answer(error_type, "scripted failure"));
+            }
+        }
+        self
+    }
+
+    pub fn client(&self) -> EcsClient {
+        self.client_in(Region::EuWest1)
+    }
+
+    pub fn client_in(&self, region: Region) -> EcsClient {
+        let data = self.data.clone();
+        let dispatcher = MockDispatcher {
+            log: self.log.clone(),
+            responder: Arc::new(move |operation, request| {
+                data.lock().unwrap().respond(operation, request)
+            }),
+        };
+        EcsClient::new_with(dispatcher, credentials(), region)
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.log.lock().unwrap().calls.clone()
+    }
+
+    pub fn call_count(&self, operation: &str) -> usize {
+        self.log.lock().unwrap().count(operation)
+    }
+
+    pub fn requests(&self, operation: &str) -> Vec<Value> {
+        self.log.lock().unwrap().requests(operation)
+    }
+}
+
+#[derive(Default)]
+struct EcrData {
+    // (repository name, tag, digest)
+    images: Vec<(String, String, String)>,
+}
+
+impl EcrData {
+    fn respond(&self, operation: &str, request: &Value) -> Answer {
+        if operation != "DescribeImages" {
+            return error_answer(
+                "UnknownOperationException",
+                &format!("no mock response for {}", operation),
+            );
+        }
+        let repository_name = request["repositoryName"].as_str().unwrap();
+        let mut details = Vec::new();
+        for image_id in request["imageIds"].as_array().into_iter().flatten() {
+            let found = self.images.iter().find(|(repository, tag, digest)| {
+                repository == repository_name
+                    && (image_id["imageTag"].as_str() == Some(tag.as_str())
+                        || image_id["imageDigest"].as_str() == Some(digest.as_str()))
+            });
+            match found {
+                Some((repository, tag, digest)) => details.push(json!({
+                    "registryId": request["registryId"],
+                    "repositoryName": repository,
+                    "imageDigest": digest,
+                    "imageTags": [tag],
+                })),
+                None => {
+                    return error_answer(
+                        "ImageNotFoundException",
+                        &format!("image {} not found in {}", image_id, repository_name),
+                    )
+                }
+            }
+        }
+        (200, json!({ "imageDetails": details }))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MockEcrClient {
+    data: Arc<Mutex<EcrData>>,
+    log: Arc<Mutex<CallLog>>,
+}
+
+impl MockEcrClient {
+    pub fn with_image(self, repository_name: &str, tag: &str, digest: &str) -> Self {
+        self.data.lock().unwrap().images.push((
+            repository_name.to_owned(),
+            tag.to_owned(),
+            digest.to_owned(),
+        ));
+        self
+    }
+
+    pub fn fail_next(self, operation: &str, times: usize, error_type: &str) -> Self {
+        {
+            let mut log = self.log.lock().unwrap();
+            let answers = log.scripted.entry(operation.to_owned()).or_default();
+            for _ in 0..times {
+                answers.push_back(error_answer(error_type, "scripted failure"));
+            }
+        }
+        self
+    }
+
+    pub fn client(&self) -> EcrClient {
+        self.client_in(Region::EuWest1)
+    }
+
+    // regional clients sharing the scripted images and the call log
+    pub fn clients(&self) -> EcrClients {
+        let mock = self.clone();
+        EcrClients::new(move |region| mock.client_in(region))
+    }
+
+    pub fn client_in(&self, region: Region) -> EcrClient {
+        let data = self.data.clone();
+        let dispatcher = MockDispatcher {
+            log: self.log.clone(),
+            responder: Arc::new(move |operation, request| {
+                data.lock().unwrap().respond(operation, request)
+            }),
+        };
+        EcrClient::new_with(dispatcher, credentials(), region)
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.log.lock().unwrap().calls.clone()
+    }
+
+    pub fn call_count(&self, operation: &str) -> usize {
+        self.log.lock().unwrap().count(operation)
+    }
+}
+
+// IAM speaks the query protocol, so its mock answers form encoded requests with xml
+struct IamDispatcher {
+    denied: Arc<Vec<String>>,
+    requested_actions: Arc<Mutex<Vec<String>>>,
+    requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+// a value of an application/x-www-form-urlencoded body
+fn form_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                decoded.push(u8::from_str_radix(&value[i + 1..i + 3], 16).unwrap());
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+impl DispatchSignedRequest for IamDispatcher {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let body = match request.payload {
+            Some(SignedRequestPayload::Buffer(ref bytes)) => {
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            _ => String::new(),
+        };
+        let params: Vec<(String, String)> = body
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (form_decode(key), form_decode(value)))
+            .collect();
+        let actions: Vec<String> = params
+            .iter()
+            .filter(|(key, _)| key.starts_with("ActionNames.member."))
+            .map(|(_, value)| value.clone())
+            .collect();
+        self.requested_actions
+            .lock()
+            .unwrap()
+            .extend(actions.iter().cloned());
+        self.requests
+            .lock()
+            .unwrap()
+            .push(params.into_iter().collect());
+
+        let results: String = actions
+            .iter()
+            .map(|action| {
+                let decision = if self.denied.contains(action) { "implicitDeny" } else { "allowed" };
+                format!(
+                    "<member><EvalActionName>{}</EvalActionName><EvalResourceName>*</EvalResourceName>\
+                     <EvalDecision>{}</EvalDecision></member>",
+                    action, decision
+                )
+            })
+            .collect();
+        let response = format!(
+            "<SimulatePrincipalPolicyResponse><SimulatePrincipalPolicyResult>\
+             <IsTruncated>false</IsTruncated><EvaluationResults>{}</EvaluationResults>\
+             </SimulatePrincipalPolicyResult><ResponseMetadata><RequestId>mock</RequestId>\
+             </ResponseMetadata></SimulatePrincipalPolicyResponse>",
+            results
+        );
+        Box::pin(async move {
+            Ok(HttpResponse {
+                status: hyper::StatusCode::OK,
+                headers: Default::default(),
+                body: ByteStream::from(response.into_bytes()),
+            })
+        })
+    }
+}
+
+// Answers SimulatePrincipalPolicy, allowing every action except the denied ones
+#[derive(Clone, Default)]
+pub struct MockIamClient {
+    denied: Vec<String>,
+    requested_actions: Arc<Mutex<Vec<String>>>,
+    requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+impl MockIamClient {
+    pub fn denying(actions: Vec<&str>) -> Self {
+        MockIamClient {
+            denied: actions.into_iter().map(str::to_owned).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn client(&self) -> IamClient {
+        let dispatcher = IamDispatcher {
+            denied: Arc::new(self.denied.clone()),
+            requested_actions: self.requested_actions.clone(),
+            requests: self.requests.clone(),
+        };
+        IamClient::new_with(dispatcher, credentials(), Region::UsEast1)
+    }
+
+    // every action simulated so far, in order
+    pub fn requested_actions(&self) -> Vec<String> {
+        self.requested_actions.lock().unwrap().clone()
+    }
+
+    // decoded query parameters of each SimulatePrincipalPolicy call, e.g. PolicySourceArn
+    pub fn requests(&self) -> Vec<HashMap<String, String>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// Answers GetService with the namespace of each Cloud Map service
+#[derive(Clone, Default)]
+pub struct MockServiceDiscoveryClient {
+    namespaces: Arc<Mutex<HashMap<String, String>>>,
+    log: Arc<Mutex<CallLog>>,
+}
+
+impl MockServiceDiscoveryClient {
+    pub fn with_service(self, service_id: &str, namespace_id: &str) -> Self {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .insert(service_id.to_owned(), namespace_id.to_owned());
+        self
+    }
+
+    pub fn client(&self) -> ServiceDiscoveryClient {
+        let namespaces = self.namespaces.clone();
+        let dispatcher = MockDispatcher {
+            log: self.log.clone(),
+            responder: Arc::new(move |operation, request| {
+                let service_id = request["Id"].as_str().unwrap_or_default();
+                match namespaces.lock().unwrap().get(service_id) {
+                    Some(namespace_id) if operation == "GetService" => (
+                        200,
+                        json!({ "Service": { "Id": service_id, "NamespaceId": namespace_id } }),
+                    ),
+                    _ => error_answer(
+                        "ServiceNotFound",
+                        &format!("no mock {} for {}", operation, service_id),
+                    ),
+                }
+            }),
+        };
+        ServiceDiscoveryClient::new_with(dispatcher, credentials(), Region::EuWest1)
+    }
+
+    pub fn call_count(&self, operation: &str) -> usize {
+        self.log.lock().unwrap().count(operation)
+    }
+}
+
+// Keeps the items put by BatchWriteItem and answers Query from them
+#[derive(Clone, Default)]
+pub struct MockDynamoDbClient {
+    items: Arc<Mutex<Vec<Value>>>,
+    // BatchWriteItem calls left that send every item back as unprocessed
+    throttled_writes: Arc<Mutex<usize>>,
+    log: Arc<Mutex<CallLog>>,
+}
+
+// `name = :value` or `name >= :value` conditions joined by AND, on string attributes
+fn matches_conditions(item: &Value, expression: &str, values: &Value) -> bool {
+    expression.split(" AND ").all(|condition| {
+        let parts: Vec<&str> = condition.split_whitespace().collect();
+        let (attribute, value) = (item[parts[0]]["S"].as_str(), values[parts[2]]["S"].as_str());
+        match parts[1] {
+            "=" => attribute == value,
+            ">=" => attribute >= value,
+            op => panic!("unsupported operator {} in mock query", op),
+        }
+    })
+}
+
+impl MockDynamoDbClient {
+    pub fn throttle_writes(self, times: usize) -> Self {
+        *self.throttled_writes.lock().unwrap() = times;
+        self
+    }
+
+    pub fn client(&self) -> DynamoDbClient {
+        let items = self.items.clone();
+        let throttled_writes = self.throttled_writes.clone();
+        let dispatcher = MockDispatcher {
+            log: self.log.clone(),
+            responder: Arc::new(move |operation, request| match operation {
+                "BatchWriteItem" => {
+                    let mut throttled_writes = throttled_writes.lock().unwrap();
+                    if *throttled_writes > 0 {
+                        *throttled_writes -= 1;
+                        return (200, json!({ "UnprocessedItems": request["RequestItems"] }));
+                    }
+                    let mut items = items.lock().unwrap();
+                    for requests in request["RequestItems"].as_object().unwrap().values() {
+                        for write in requests.as_array().unwrap() {
+                            let item = write["PutRequest"]["Item"].clone();
+                            // the key is (cluster_arn, sk), a put replaces the item with the same key
+                            items.retain(|other| {
+                                other["cluster_arn"] != item["cluster_arn"]
+                                    || other["sk"] != item["sk"]
+                            });
+                            items.push(item);
+                        }
+                    }
+                    (200, json!({ "UnprocessedItems": {} }))
+                }
+                "Query" => {
+                    let values = &request["ExpressionAttributeValues"];
+                    let found: Vec<Value> = items
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|item| {
+                            ["KeyConditionExpression", "FilterExpression"].iter().all(
+                                |expression| {
+                                    request[*expression].as_str().is_none_or(|expression| {
+                                        matches_conditions(item, expression, values)
+                                    })
+                                },
+                            )
+                        })
+                        .cloned()
+                        .collect();
+                    (200, json!({ "Items": found, "Count": found.len() }))
+                }
+                _ => error_answer(
+                    "UnknownOperationException",
+                    &format!("no mock response for {}", operation),
+                ),
+            }),
+        };
+        DynamoDbClient::new_with(dispatcher, credentials(), Region::EuWest1)
+    }
+
+    pub fn items(&self) -> Vec<Value> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn call_count(&self, operation: &str) -> usize {
+        self.log.lock().unwrap().count(operation)
+    }
+
+    pub fn requests(&self, operation: &str) -> Vec<Value> {
+        self.log.lock().unwrap().requests(operation)
+    }
+}
+
+pub fn service(name: &str, task_definition_arn: &str) -> Service {
+    Service {
+        service_name: Some(name.to_owned()),
+        task_definition: Some(task_definition_arn.to_owned()),
+        status: Some("ACTIVE".to_owned()),
+        ..Default::default()
+    }
+}
+
+pub fn container(name: &str, image: &str) -> ContainerDefinition {
+    ContainerDefinition {
+        name: Some(name.to_owned()),
+        image: Some(image.to_owned()),
+        ..Default::default()
+    }
+}
+
+pub fn task_definition(containers: Vec<ContainerDefinition>) -> TaskDefinition {
+    TaskDefinition {
+        container_definitions: Some(containers),
+        status: Some("ACTIVE".to_owned()),
+        ..Default::default()
+    }
+}